@@ -0,0 +1,392 @@
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand};
+
+use crate::utils::Delimiter;
+
+const GROUP: &str = "Column names. NB: case insensitive.";
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "ldscrs",
+    version,
+    author = "Wenjie Wei <weiwenjie@westlake.edu.cn>",
+    about = "LD Score Regression (LDSC) in Rust"
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+/// Options shared by every subcommand.
+#[derive(Args, Debug, Clone)]
+pub struct GlobalArgs {
+    #[arg(
+        long,
+        global = true,
+        default_value = "ldsc",
+        help = "Output filename prefix."
+    )]
+    pub out: String,
+
+    #[arg(long, global = true, default_value_t = 8, help = "Number of threads.")]
+    pub threads: usize,
+
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet", help = "Increase log verbosity (-v for debug, -vv for trace).")]
+    pub verbose: u8,
+
+    #[arg(short, long, global = true, action = ArgAction::SetTrue, help = "Only log warnings and errors.")]
+    pub quiet: bool,
+}
+
+// parsed once per run, so the size of the largest variant does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Munge summary statistics
+    Munge(MungeArgs),
+    /// Estimate LD scores
+    L2(L2Args),
+    /// Estimate SNP heritability by LD score regression
+    H2(H2Args),
+    /// Estimate genetic correlation between the first trait and each of the others
+    Rg(RgArgs),
+    /// Cell-type-specific analysis of the annotations listed in an .ldcts file
+    H2Cts(H2CtsArgs),
+    /// Make an annot file from a BED file or a gene set
+    MakeAnnot(MakeAnnotArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct MungeArgs {
+    #[arg(long, default_value = None, help = "Input filename.", required = true)]
+    pub sumstats: String,
+
+    #[arg(long="N", default_value = None, help = "Sample size. If this option is not set, will try to infer the sample size from the input file. If the input file contains a sample size column, and this flag is set, the argument to this flag has priority.")]
+    pub n: Option<f64>,
+
+    #[arg(long="N-cas", default_value = None, help = "Number of cases. If this option is not set, will try to infer the number of cases from the input file. If the input file contains a number of cases column, and this flag is set, the argument to this flag has priority.")]
+    pub n_cas: Option<f64>,
+
+    #[arg(long="N-con", default_value = None, help = "Number of controls. If this option is not set, will try to infer the number of controls from the input file. If the input file contains a number of controls column, and this flag is set, the argument to this flag has priority.")]
+    pub n_con: Option<f64>,
+
+    #[arg(long, default_value_t = 0.9, help = "Minimum INFO score.")]
+    pub info_min: f64,
+
+    #[arg(long, default_value_t = 0.01, help = "Minimum MAF.")]
+    pub maf_min: f64,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "Use this flag to parse Stephan Ripke's daner* file format.", conflicts_with = "daner_n")]
+    pub daner: bool,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "Use this flag to parse more recent daner* formatted files, which include sample size column 'Nca' and 'Nco'." ,conflicts_with = "daner")]
+    pub daner_n: bool,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "Don't require alleles. Useful if only unsigned summary statistics are available and the goal is h2 / partitioned h2 estimation rather than rg estimation.", conflicts_with = "merge_alleles")]
+    pub no_alleles: bool,
+
+    #[arg(long, default_value = None, help = "Same as --merge, except the file should have three columns: SNP, A1, A2, and all alleles will be matched to the --merge-alleles file alleles.", conflicts_with = "no_alleles")]
+    pub merge_alleles: Option<String>,
+
+    #[arg(long, default_value = None, help = "Minimum N (sample size). Default is (90th percentile N) / 2.")]
+    pub n_min: Option<f64>,
+
+    #[arg(long, default_value = None, help = "Remove SNPs with chi^2 at or above this value, e.g. to drop the MHC or APOE. Default is to keep every SNP; the regressions have their own --chisq-max.")]
+    pub chisq_max: Option<f64>,
+
+    #[arg(long, default_value_t = 5e6 as usize, help = "Chunksize.")]
+    pub chunksize: usize,
+
+    #[arg(long, default_value = None, help = "Name of SNP column (if not a name that ldsc understands). ")]
+    pub snp: Option<String>,
+
+    #[arg(long="N-col", default_value = None, help = "Name of N column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
+    pub n_col: Option<String>,
+
+    #[arg(long="N-cas-col", default_value = None, help = "Name of N column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
+    pub n_cas_col: Option<String>,
+
+    #[arg(long="N-con-col", default_value = None, help = "Name of N column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
+    pub n_con_col: Option<String>,
+
+    #[arg(long, default_value = None, help = "Name of A1 column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
+    pub a1: Option<String>,
+
+    #[arg(long, default_value = None, help = "Name of A2 column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
+    pub a2: Option<String>,
+
+    #[arg(long, default_value = None, help = "Name of p-value column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
+    pub p: Option<String>,
+
+    #[arg(long, default_value = None, help = "Name of FRQ or MAF column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
+    pub frq: Option<String>,
+
+    #[arg(long, default_value = None, help = "Name of signed sumstat column, comma null value (e.g., Z,0 or OR,1). ", help_heading=Some(GROUP))]
+    pub signed_sumstats: Option<String>,
+
+    #[arg(long, default_value = None, help = "Name of INFO column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
+    pub info: Option<String>,
+
+    #[arg(long, default_value = None, help = "Comma-separated list of INFO columns. Will filter on the mean. ", help_heading=Some(GROUP))]
+    pub info_list: Option<String>,
+
+    #[arg(long, default_value = None, help = "Name of NSTUDY column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
+    pub nstudy: Option<String>,
+
+    #[arg(long, default_value = None, help = "Minimum # of studies. Default is to remove everything below the max, unless there is an N column, in which case do nothing.", help_heading=Some(GROUP))]
+    pub nstudy_min: Option<f64>,
+
+    #[arg(long, default_value = None, help = "Comma-separated list of column names to ignore.", help_heading=Some(GROUP))]
+    pub ignore: Option<String>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "A1 is the increasing allele.", help_heading=Some(GROUP))]
+    pub a1_inc: bool,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "Keep the MAF column (if one exists).", help_heading=Some(GROUP))]
+    pub keep_maf: bool,
+
+    #[arg(long, value_enum, default_value = None, help = "Delimiter of the --sumstats file. Default is to guess it from the first lines of the file.")]
+    pub delim: Option<Delimiter>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(group(ArgGroup::new("ld_wind").required(true).multiple(false)))]
+pub struct L2Args {
+    #[arg(long, default_value = None, help = "Prefix for PLINK .bed/.bim/.fam file. An @ is replaced by each of chromosomes 1-22.", required = true)]
+    pub bfile: String,
+
+    #[arg(long, default_value = None, help = "Specify the window size to be used for estimating LD Scores in units of # of SNPs.", group = "ld_wind")]
+    pub ld_wind_snps: Option<usize>,
+
+    #[arg(long, default_value = None, help = "Specify the window size to be used for estimating LD Scores in units of kilobase-pairs (kb).", group = "ld_wind")]
+    pub ld_wind_kb: Option<f64>,
+
+    #[arg(long, default_value = None, help = "Specify the window size to be used for estimating LD Scores in units of centiMorgans (cM).", group = "ld_wind")]
+    pub ld_wind_cm: Option<f64>,
+
+    #[arg(
+        long,
+        default_value_t = 50,
+        help = "Chunk size for LD Score calculation. Use the default."
+    )]
+    pub chunk_size: usize,
+
+    #[arg(long, default_value = None, help = "Minor allele frequency lower bound. Default is MAF > 0.")]
+    pub maf: Option<f64>,
+
+    #[arg(long, default_value = None, help = "File with SNPs to include in LD Score estimation. The file should contain one SNP ID per row.")]
+    pub extract: Option<String>,
+
+    #[arg(long, default_value = None, help = "File with individuals to include in LD Score estimation. The file should contain one individual ID per row.")]
+    pub keep: Option<String>,
+
+    #[arg(long, default_value = None, help = "Annotation file (.annot or .annot.gz) for partitioned LD Score estimation, with the same SNPs in the same order as the .bim file.", conflicts_with_all = ["extract", "cts_bin"])]
+    pub annot: Option<String>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "This flag says your annot files have only annotations, with no SNP, CM, CHR, BP columns.", requires = "annot")]
+    pub thin_annot: bool,
+
+    #[arg(long, default_value = None, help = "This flag tells LDSC to compute partitioned LD Scores, where the partition is defined by cutting one or several continuous variable[s] into bins. The argument to this flag should be the name of a single file or a comma-separated list of files. The file format is two columns, with SNP IDs in the first column and the continuous variable in the second column.", requires = "cts_breaks", conflicts_with = "extract")]
+    pub cts_bin: Option<String>,
+
+    #[arg(long, default_value = None, allow_hyphen_values = true, help = "Breakpoints for the continuous variables cut into bins with --cts-bin. For each continuous variable, specify breaks as a comma-separated list of breakpoints, and separate the breakpoints for each variable with an x. For example, if binning on MAF and distance to gene (in kb), you might set --cts-breaks 0.1,0.25,0.4x10,100,1000. Use N for a minus sign.", requires = "cts_bin")]
+    pub cts_breaks: Option<String>,
+
+    #[arg(long, default_value = None, help = "Use this flag to specify names for the continuous variables cut into bins with --cts-bin. The argument to this flag should be a comma-separated list of names. For example, if binning on DAF and distance to gene, you might set --cts-bin DAF,DIST_TO_GENE.", requires = "cts_bin")]
+    pub cts_names: Option<String>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "By default, setting --cts-bin causes LDSC to print the resulting annot matrix. Setting --no-print-annot tells LDSC not to print the annot matrix.")]
+    pub no_print_annot: bool,
+
+    #[arg(long, default_value = None, help = "Only print LD Scores for these SNPs.")]
+    pub print_snps: Option<String>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "Yes, I really want to compute whole-chromosome LD Score.")]
+    pub yes_really: bool,
+}
+
+/// Reference panel and regression weight LD scores shared by the regression subcommands.
+#[derive(Args, Debug, Clone)]
+#[command(group(ArgGroup::new("ref_ld_group").required(true).multiple(false).args(["ref_ld", "ref_ld_chr"])))]
+#[command(group(ArgGroup::new("w_ld_group").required(true).multiple(false).args(["w_ld", "w_ld_chr"])))]
+pub struct LdArgs {
+    #[arg(long, default_value = None, help = "Use --ref-ld to tell LDSC which LD Scores to use as the predictors in the LD Score regression. LDSC will automatically append .l2.ldscore/.l2.ldscore.gz to the filename prefix.")]
+    pub ref_ld: Option<String>,
+
+    #[arg(long, default_value = None, help = "Same as --ref-ld, but will automatically concatenate .l2.ldscore files split across 22 chromosomes. LDSC will automatically append .l2.ldscore/.l2.ldscore.gz to the filename prefix. If the filename prefix contains the symbol @, LDSC will replace the @ symbol with chromosome numbers. Otherwise, LDSC will append chromosome numbers to the end of the filename prefix. Example 1: --ref-ld-chr ld/ will read ld/1.l2.ldscore.gz ... ld/22.l2.ldscore.gz Example 2: --ref-ld-chr ld/@_kg will read ld/1_kg.l2.ldscore.gz ... ld/22_kg.l2.ldscore.gz")]
+    pub ref_ld_chr: Option<String>,
+
+    #[arg(long, default_value = None, help = "Filename prefix for file with LD Scores with sum r^2 taken over SNPs included in the regression. LDSC will automatically append .l2.ldscore/.l2.ldscore.gz.")]
+    pub w_ld: Option<String>,
+
+    #[arg(long, default_value = None, help = "Same as --w-ld, but will read files split into 22 chromosomes in the same manner as --ref-ld-chr.")]
+    pub w_ld_chr: Option<String>,
+
+    #[arg(long = "M", default_value = None, help = "# of SNPs (if you don't want to use the .l2.M files that came with your .l2.ldscore.gz files)")]
+    pub m: Option<String>,
+
+    #[arg(long = "not-M-5-50", action = ArgAction::SetTrue, help = "This flag tells LDSC to use the .l2.M file instead of the .l2.M_5_50 file.")]
+    pub not_m_5_50: bool,
+
+    #[arg(long, default_value = None, help = "Max chi^2: SNPs at or above it (for --rg, whose product of chi^2 is at or above its square) are removed, and counted in out.chisq_max.json. Default for partitioned LD Scores and --h2-cts is max(0.001 * max(N), 80); otherwise every SNP is kept.")]
+    pub chisq_max: Option<f64>,
+
+    #[arg(
+        long,
+        default_value_t = 200,
+        help = "Number of block jackknife blocks."
+    )]
+    pub n_blocks: usize,
+
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "jk_block_kb", help = "Use one block jackknife block per chromosome instead of --n-blocks blocks of equal numbers of SNPs.")]
+    pub jk_chr: bool,
+
+    #[arg(long, default_value = None, help = "Split each chromosome into block jackknife blocks spanning less than this many kilobase-pairs (kb), instead of --n-blocks blocks of equal numbers of SNPs.")]
+    pub jk_block_kb: Option<f64>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct H2Args {
+    #[arg(long, default_value = None, help = "Filename for a .sumstats[.gz] file for one-phenotype LD Score regression. --h2 requires at minimum also setting the --ref-ld and --w-ld flags.", required = true)]
+    pub h2: String,
+
+    #[command(flatten)]
+    pub ld: LdArgs,
+
+    #[arg(long, default_value = None, conflicts_with = "no_intercept", help = "Intercept for constrained-intercept single-trait LD Score regression.")]
+    pub intercept_h2: Option<f64>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "If used with --h2, this constrains the LD Score regression intercept to equal 1. If used with --rg, this constrains the LD Score regression intercepts for the h2 estimates to be one and the intercept for the genetic covariance estimate to be zero.")]
+    pub no_intercept: bool,
+
+    #[arg(long, default_value = None, help = "Sample prevalence of binary phenotype (for conversion to liability scale). Defaults to the fraction of cases of the N_CAS column of the summary statistics.")]
+    pub samp_prev: Option<String>,
+
+    #[arg(long, default_value = None, help = "Population prevalence of binary phenotype (for conversion to liability scale).")]
+    pub pop_prev: Option<String>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "This flag informs LDSC that the partitioned LD Scores were generates using an annot matrix with overlapping categories (i.e., not all row sums equal 1), and prevents LDSC from displaying output that is meaningless with overlapping categories.")]
+    pub overlap_annot: bool,
+
+    #[arg(long, default_value = None, help = "For use with --overlap-annot. Provides allele frequencies to prune to common snps if --not-M-5-50 is not set.")]
+    pub frqfile: Option<String>,
+
+    #[arg(long, default_value = None, help = "Prefix for --frqfile files split over chromosome.")]
+    pub frqfile_chr: Option<String>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "when categories are overlapping, print coefficients as well as heritabilities.")]
+    pub print_coefficients: bool,
+
+    #[arg(long, default_value = None, conflicts_with_all = ["no_intercept", "intercept_h2"], help = "Test statistic bound for use with the two-step estimator. Not compatible with --no-intercept and --intercept-h2. Default is 30 with a single LD Score.")]
+    pub two_step: Option<f64>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "If this flag is set, ldsc will print the block jackknife delete-values (i.e., the regression coefficeints estimated from the data with a block removed). The delete-values are formatted as a matrix with (# of jackknife blocks) rows and (# of LD Scores) columns.")]
+    pub print_delete_vals: bool,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "Print the block jackknife delete values and pseudovalues of h2, the intercept and, with partitioned LD Scores, the coefficients to out.jackknife.tsv, one row per block.")]
+    pub print_jackknife: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct H2CtsArgs {
+    #[arg(long, default_value = None, help = "Filename for a .sumstats[.gz] file for cell-type-specific analysis. --h2-cts requires the --ref-ld-chr, --w-ld, and --ref-ld-chr-cts flags.", required = true)]
+    pub h2_cts: String,
+
+    #[arg(long, default_value = None, help = "Name of a file that has a list of file name prefixes for cell-type-specific analysis.", required = true)]
+    pub ref_ld_chr_cts: String,
+
+    #[command(flatten)]
+    pub ld: LdArgs,
+
+    #[arg(long, default_value = None, conflicts_with = "no_intercept", help = "Intercept for constrained-intercept single-trait LD Score regression.")]
+    pub intercept_h2: Option<f64>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "If used with --h2, this constrains the LD Score regression intercept to equal 1. If used with --rg, this constrains the LD Score regression intercepts for the h2 estimates to be one and the intercept for the genetic covariance estimate to be zero.")]
+    pub no_intercept: bool,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "When there are multiple LD Score prefixes for a cell type, print one coefficient per prefix, named <name>_<i>, not only the first one.")]
+    pub print_all_cts: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RgArgs {
+    #[arg(long, default_value = None, help = "Comma-separated list of prefixes of .chisq filed for genetic correlation estimation.", required = true)]
+    pub rg: String,
+
+    #[command(flatten)]
+    pub ld: LdArgs,
+
+    #[arg(long, default_value = None, conflicts_with = "no_intercept", help = "Intercepts for constrained-intercept single-trait LD Score regression. Must have same length as --rg.")]
+    pub intercept_h2: Option<String>,
+
+    #[arg(long, default_value = None, conflicts_with_all = ["no_intercept", "matrix"], help = "Intercepts for constrained-intercept cross-trait LD Score regression. Must have same length as --rg. The first entry is ignored.")]
+    pub intercept_gencov: Option<String>,
+
+    #[arg(long, default_value = None, conflicts_with_all = ["no_intercept", "intercept_h2", "intercept_gencov"], help = "Test statistic bound for use with the two-step estimator. Not compatible with --no-intercept, --intercept-h2 and --intercept-gencov. Default is 30 with a single LD Score.")]
+    pub two_step: Option<f64>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "If used with --h2, this constrains the LD Score regression intercept to equal 1. If used with --rg, this constrains the LD Score regression intercepts for the h2 estimates to be one and the intercept for the genetic covariance estimate to be zero.")]
+    pub no_intercept: bool,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "For rg estimation, skip checking whether the alleles match. This check is redundant for pairs of chisq files generated using munge_sumstats.py and the same argument to the --merge-alleles flag.")]
+    pub no_check_alleles: bool,
+
+    #[arg(long, default_value = None, help = "Comma-separated sample prevalences of binary phenotypes (for conversion to liability scale), N/A for a phenotype that is not binary. Default to the fraction of cases of the N_CAS column of the summary statistics.")]
+    pub samp_prev: Option<String>,
+
+    #[arg(long, default_value = None, help = "Comma-separated population prevalences of binary phenotypes (for conversion to liability scale), N/A for a phenotype that is not binary.")]
+    pub pop_prev: Option<String>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "Estimate rg between every pair of phenotypes in --rg, reading the LD Scores and each phenotype once, and write symmetric rg, SE and P matrices to out.rg.tsv, out.rg_se.tsv, out.rg_p.tsv and out.rg.json.")]
+    pub matrix: bool,
+
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "matrix", help = "Print the block jackknife delete values and pseudovalues of the h2 of both phenotypes, their genetic covariance, the intercepts and rg to out.jackknife.tsv, one row per block for each phenotype paired with the first.")]
+    pub print_jackknife: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(group(ArgGroup::new("regions").required(true).multiple(false).args(["bed_file", "gene_set_file"])))]
+pub struct MakeAnnotArgs {
+    #[arg(long, default_value = None, help = "plink bim file for the dataset you will use to compute LD scores. An @ is replaced by each of chromosomes 1-22, making one annot file per chromosome.", required = true)]
+    pub bimfile: String,
+
+    #[arg(long, default_value = None, conflicts_with = "gene_set_file", help = "the UCSC bed file with the regions that make up your annotation")]
+    pub bed_file: Option<String>,
+
+    #[arg(long, default_value = None, help = "a file of gene names, one line per gene.")]
+    pub gene_set_file: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "ENSG_coord.txt",
+        help = "a file with columns GENE, CHR, START, and END, where START and END are base pair coordinates of TSS and TES. This file can contain more genes than are in the gene set. START and END are 1-based and inclusive, as in ldsc, which turns them into the BED interval [START - 1, END): SNPs at START and at END are in the gene."
+    )]
+    pub gene_coord_file: String,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "how many base pairs to add around the transcribed region (or the regions of --bed-file) to make the annotation?"
+    )]
+    pub windowsize: u64,
+
+    #[arg(long, default_value = None, requires = "gene_set_file", help = "A GTF or GFF3 file (possibly compressed), e.g. from GENCODE or Ensembl, to locate the genes of --gene-set-file instead of --gene-coord-file. Genes are matched by gene ID, with or without version suffix, or by gene symbol.")]
+    pub gene_annot_file: Option<String>,
+
+    #[arg(long, action = ArgAction::SetTrue, requires = "gene_annot_file", help = "Make windows around the transcription start site of each gene of --gene-annot-file instead of around the gene body.")]
+    pub tss: bool,
+
+    #[arg(long, default_value = None, requires = "gene_annot_file", help = "Kilobase-pairs (kb) to add upstream of each gene of --gene-annot-file (or of its TSS with --tss), following its strand. Default is --windowsize. With --tss and --downstream-kb this makes promoter windows.")]
+    pub upstream_kb: Option<f64>,
+
+    #[arg(long, default_value = None, requires = "gene_annot_file", help = "Kilobase-pairs (kb) to add downstream of each gene of --gene-annot-file (or of its TSS with --tss), following its strand. Default is --windowsize.")]
+    pub downstream_kb: Option<f64>,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "don't merge the bed file; make an annot file with values proportional to the number of intervals in the bedfile overlapping the SNP. Unlike ldsc's make_annot.py, which writes a binary ANNOT with one row per overlapping interval (so that the rows no longer match the bim file), each SNP gets one row with its number of overlapping intervals.")]
+    pub nomerge: bool,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "Write a full annot file with CHR, BP, SNP and CM columns before the annotation, instead of a thin annot file (read by l2 with --thin-annot).")]
+    pub full_annot: bool,
+}
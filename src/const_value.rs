@@ -27,6 +27,16 @@ pub static DEFAULT_CNAMES: phf::Map<&'static str, &'static str> = phf_map! {
     "PVAL" => "P",
     "P_VAL" => "P",
     "GC_PVALUE" => "P",
+    // -LOG10 P-VALUE
+    "LOG10P" => "LOG10P",
+    "LOG10_P" => "LOG10P",
+    "MLOG10P" => "LOG10P",
+    "NEG_LOG10_P" => "LOG10P",
+    "NEG_LOG_10_P_VALUE" => "LOG10P",
+    "MINUS_LOG10_P" => "LOG10P",
+    // LN P-VALUE
+    "LN_P" => "LN_P",
+    "LNP" => "LN_P",
     // ALLELE 1
     "A1" => "A1",
     "ALLELE1" => "A1",
@@ -68,6 +78,12 @@ pub static DEFAULT_CNAMES: phf::Map<&'static str, &'static str> = phf_map! {
     "EFFECTS" => "BETA",
     "EFFECT" => "BETA",
     "SIGNED_SUMSTAT" => "SIGNED_SUMSTAT",
+    // STANDARD ERROR
+    "SE" => "SE",
+    "STDERR" => "SE",
+    "STD_ERR" => "SE",
+    "STANDARD_ERROR" => "SE",
+    "STANDARD_ERROR_OF_BETA" => "SE",
     // INFO
     "INFO" => "INFO",
     // MAF
@@ -99,6 +115,8 @@ pub static DEFAULT_CNAMES: phf::Map<&'static str, &'static str> = phf_map! {
 pub static DESCRIBE_CNAME: phf::Map<&'static str, &'static str> = phf_map! {
     "SNP" => "Variant ID (e.g., rs number)",
     "P" => "p-Value",
+    "LOG10P" => "-log10 p-Value",
+    "LN_P" => "Natural log of p-Value",
     "A1" => "Allele 1, interpreted as ref allele for signed sumstat.",
     "A2" => "Allele 2, interpreted as non-ref allele for signed sumstat.",
     "N" => "Sample size",
@@ -111,5 +129,44 @@ pub static DESCRIBE_CNAME: phf::Map<&'static str, &'static str> = phf_map! {
     "INFO" => "INFO score (imputation quality; higher --> better imputation)",
     "FRQ" => "Allele frequency",
     "SIGNED_SUMSTAT" => "Directional summary statistic as specified by --signed-sumstats.",
+    "SE" => "Standard error of BETA or LOG_ODDS (of log(OR) for OR).",
     "NSTUDY" => "Number of studies in which the SNP was genotyped."
 };
+
+/// ldsc's FLIP_ALLELES: A1, A2 of one trait followed by A1, A2 of another, for every pair of
+/// non-strand-ambiguous SNPs whose alleles match up to a strand and/or reference flip (the
+/// keys are ldsc's MATCH_ALLELES), mapped to whether the reference allele is flipped.
+pub static FLIP_ALLELES: phf::Map<&'static str, bool> = phf_map! {
+    "ACAC" => false,
+    "ACCA" => true,
+    "ACGT" => true,
+    "ACTG" => false,
+    "AGAG" => false,
+    "AGCT" => true,
+    "AGGA" => true,
+    "AGTC" => false,
+    "CAAC" => true,
+    "CACA" => false,
+    "CAGT" => false,
+    "CATG" => true,
+    "CTAG" => true,
+    "CTCT" => false,
+    "CTGA" => false,
+    "CTTC" => true,
+    "GAAG" => true,
+    "GACT" => false,
+    "GAGA" => false,
+    "GATC" => true,
+    "GTAC" => true,
+    "GTCA" => false,
+    "GTGT" => false,
+    "GTTG" => true,
+    "TCAG" => false,
+    "TCCT" => true,
+    "TCGA" => true,
+    "TCTC" => false,
+    "TGAC" => false,
+    "TGCA" => true,
+    "TGGT" => true,
+    "TGTG" => false,
+};
@@ -0,0 +1,76 @@
+use anyhow::{bail, Result};
+use nalgebra::{DMatrix, DVector};
+
+use crate::jackknife::{get_separators, Jackknife};
+
+// number of reweighting steps before the final fit
+const N_UPDATES: usize = 2;
+
+/// Iteratively re-weighted least squares, ldsc's `IRWLS`.
+///
+/// Starting from weights `w`, fits `y` on `x` by weighted least squares, passes the
+/// coefficients to `update_func` for new weights, twice, then returns the block jackknife
+/// of the final weighted fit. Blocks are `separators` if given, else `n_blocks` blocks of
+/// equal size.
+pub fn irwls<F>(
+    x: &DMatrix<f64>,
+    y: &DVector<f64>,
+    update_func: F,
+    n_blocks: usize,
+    w: &DVector<f64>,
+    separators: Option<Vec<usize>>,
+) -> Result<Jackknife>
+where
+    F: Fn(&DVector<f64>) -> Result<DVector<f64>>,
+{
+    let n = x.nrows();
+    if y.len() != n || w.len() != n {
+        bail!("x, y and w must have the same number of rows.");
+    }
+    let mut w = w.map(f64::sqrt);
+    for _ in 0..N_UPDATES {
+        let coef = wls(x, y, &w)?;
+        let new_w = update_func(&coef)?.map(f64::sqrt);
+        if new_w.len() != w.len() {
+            bail!("New weights must have the same shape as the old weights.");
+        }
+        w = new_w;
+    }
+    let x = weight(x, &w)?;
+    let y = weight(&DMatrix::from_column_slice(n, 1, y.as_slice()), &w)?;
+    let separators = match separators {
+        Some(s) => s,
+        None => get_separators(n, n_blocks)?,
+    };
+    Jackknife::lstsq(&x, &y.column(0).into_owned(), separators)
+}
+
+/// Least squares fit of `y` on `x`, each row weighted by `w`.
+pub fn wls(x: &DMatrix<f64>, y: &DVector<f64>, w: &DVector<f64>) -> Result<DVector<f64>> {
+    let x = weight(x, w)?;
+    let y = weight(&DMatrix::from_column_slice(y.len(), 1, y.as_slice()), w)?;
+    lstsq(&x, &y.column(0).into_owned())
+}
+
+/// Minimum-norm least squares solution of `x b = y`, like `np.linalg.lstsq`.
+pub fn lstsq(x: &DMatrix<f64>, y: &DVector<f64>) -> Result<DVector<f64>> {
+    let svd = x.clone().svd(true, true);
+    let eps = f64::EPSILON * x.nrows().max(x.ncols()) as f64 * svd.singular_values.max();
+    match svd.solve(y, eps) {
+        Ok(b) => Ok(b),
+        Err(e) => bail!("Least squares fit failed: {}", e),
+    }
+}
+
+/// Multiply each row of `x` by the normalized weights `w / sum(w)`.
+pub fn weight(x: &DMatrix<f64>, w: &DVector<f64>) -> Result<DMatrix<f64>> {
+    if w.iter().any(|v| *v <= 0.0) {
+        bail!("Weights must be > 0");
+    }
+    let w = w / w.sum();
+    let mut x = x.clone();
+    for (mut row, wi) in x.row_iter_mut().zip(w.iter()) {
+        row *= *wi;
+    }
+    Ok(x)
+}
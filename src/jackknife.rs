@@ -0,0 +1,240 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use nalgebra::{DMatrix, DVector, RowDVector};
+
+/// Block jackknife estimates of a vector of parameters.
+///
+/// Mirrors ldsc's `Jackknife`: `est` is the estimate on all data, `delete_values` hold the
+/// estimate with each block left out, and the jackknife mean, variance, SE and covariance
+/// are computed from the pseudovalues.
+#[derive(Debug, Clone)]
+pub struct Jackknife {
+    pub est: RowDVector<f64>,
+    /// Block boundaries: block `i` spans `separators[i]..separators[i + 1]`.
+    pub separators: Vec<usize>,
+    /// n_blocks x p.
+    pub delete_values: DMatrix<f64>,
+    /// n_blocks x p.
+    pub pseudovalues: DMatrix<f64>,
+    pub jknife_est: RowDVector<f64>,
+    pub jknife_var: RowDVector<f64>,
+    pub jknife_se: RowDVector<f64>,
+    pub jknife_cov: DMatrix<f64>,
+}
+
+/// Boundaries of `n_blocks` blocks of (almost) equal size over `n` data points, computed
+/// like ldsc's `np.floor(np.linspace(0, n, n_blocks + 1))`.
+pub fn get_separators(n: usize, n_blocks: usize) -> Result<Vec<usize>> {
+    if n_blocks > n {
+        bail!("More blocks than data points.");
+    }
+    if n_blocks == 0 {
+        bail!("Must have at least one jackknife block.");
+    }
+    let step = n as f64 / n_blocks as f64;
+    let mut separators = (0..n_blocks)
+        .map(|i| (i as f64 * step).floor() as usize)
+        .collect::<Vec<_>>();
+    separators.push(n);
+    Ok(separators)
+}
+
+/// Jackknife block of each SNP on chromosomes `chr` at positions `bp` (sorted by both): one
+/// block per chromosome, split into blocks spanning less than `max_dist` bp if given.
+pub fn block_labels(chr: &[i64], bp: &[i64], max_dist: Option<f64>) -> Result<Vec<usize>> {
+    if chr.len() != bp.len() {
+        bail!("CHR and BP must have the same length.");
+    }
+    let mut labels = Vec::with_capacity(chr.len());
+    let mut block = 0;
+    let mut start = None;
+    for (i, (&c, &b)) in chr.iter().zip(bp).enumerate() {
+        if i > 0 && c == chr[i - 1] && b < bp[i - 1] {
+            bail!("SNPs must be sorted by position to define jackknife blocks by distance.");
+        }
+        let new_block = match start {
+            None => false,
+            Some((c0, b0)) => c != c0 || max_dist.is_some_and(|d| (b - b0) as f64 >= d),
+        };
+        if new_block {
+            block += 1;
+        }
+        if new_block || start.is_none() {
+            start = Some((c, b));
+        }
+        labels.push(block);
+    }
+    Ok(labels)
+}
+
+/// Separators of the blocks of contiguous SNPs with the same label in `labels`.
+pub fn separators_from_labels(labels: &[usize]) -> Result<Vec<usize>> {
+    if labels.is_empty() {
+        bail!("Must have at least one jackknife block.");
+    }
+    let mut separators = vec![0];
+    let mut seen = HashSet::from([labels[0]]);
+    for i in 1..labels.len() {
+        if labels[i] != labels[i - 1] {
+            if !seen.insert(labels[i]) {
+                bail!("Jackknife blocks must be contiguous.");
+            }
+            separators.push(i);
+        }
+    }
+    separators.push(labels.len());
+    Ok(separators)
+}
+
+/// Pseudovalues `n_blocks * est - (n_blocks - 1) * delete_values`.
+pub fn delete_values_to_pseudovalues(
+    delete_values: &DMatrix<f64>,
+    est: &RowDVector<f64>,
+) -> DMatrix<f64> {
+    let n_blocks = delete_values.nrows() as f64;
+    let mut pseudovalues = delete_values * -(n_blocks - 1.0);
+    for mut row in pseudovalues.row_iter_mut() {
+        row += est * n_blocks;
+    }
+    pseudovalues
+}
+
+impl Jackknife {
+    /// Jackknife estimates from the estimate on all data and the delete values.
+    pub fn from_delete_values(
+        est: RowDVector<f64>,
+        delete_values: DMatrix<f64>,
+        separators: Vec<usize>,
+    ) -> Self {
+        let pseudovalues = delete_values_to_pseudovalues(&delete_values, &est);
+        Self::from_pseudovalues(est, delete_values, pseudovalues, separators)
+    }
+
+    fn from_pseudovalues(
+        est: RowDVector<f64>,
+        delete_values: DMatrix<f64>,
+        pseudovalues: DMatrix<f64>,
+        separators: Vec<usize>,
+    ) -> Self {
+        let n_blocks = pseudovalues.nrows() as f64;
+        let jknife_est = pseudovalues.row_mean();
+        let mut centered = pseudovalues.clone();
+        for mut row in centered.row_iter_mut() {
+            row -= &jknife_est;
+        }
+        // np.cov(pseudovalues.T, ddof=1) / n_blocks
+        let jknife_cov = centered.tr_mul(&centered) / ((n_blocks - 1.0) * n_blocks);
+        let jknife_var = jknife_cov.diagonal().transpose();
+        let jknife_se = jknife_var.map(f64::sqrt);
+        Jackknife {
+            est,
+            separators,
+            delete_values,
+            pseudovalues,
+            jknife_est,
+            jknife_var,
+            jknife_se,
+            jknife_cov,
+        }
+    }
+
+    /// Block jackknife of the least squares fit of `y` on `x`, ldsc's `LstsqJackknifeFast`:
+    /// each delete value solves the normal equations with the block's X'X and X'y removed.
+    pub fn lstsq(x: &DMatrix<f64>, y: &DVector<f64>, separators: Vec<usize>) -> Result<Self> {
+        let (n, p) = x.shape();
+        if y.len() != n {
+            bail!("Number of data points in y != number of data points in x.");
+        }
+        if separators.first() != Some(&0) || separators.last() != Some(&n) {
+            bail!("Separators must span the data points.");
+        }
+        let n_blocks = separators.len() - 1;
+        if n_blocks > n {
+            bail!("More blocks than data points.");
+        }
+        let mut xty_blocks = Vec::with_capacity(n_blocks);
+        let mut xtx_blocks = Vec::with_capacity(n_blocks);
+        for w in separators.windows(2) {
+            let xb = x.rows(w[0], w[1] - w[0]);
+            let yb = y.rows(w[0], w[1] - w[0]);
+            xty_blocks.push(xb.tr_mul(&yb));
+            xtx_blocks.push(xb.tr_mul(&xb));
+        }
+        let xty_tot = xty_blocks.iter().fold(DVector::zeros(p), |acc, b| acc + b);
+        let xtx_tot = xtx_blocks
+            .iter()
+            .fold(DMatrix::zeros(p, p), |acc, b| acc + b);
+        let est = solve(&xtx_tot, &xty_tot)?.transpose();
+        let mut delete_values = DMatrix::zeros(n_blocks, p);
+        for (j, (xty, xtx)) in xty_blocks.iter().zip(&xtx_blocks).enumerate() {
+            let delete = solve(&(&xtx_tot - xtx), &(&xty_tot - xty))?;
+            delete_values.set_row(j, &delete.transpose());
+        }
+        Ok(Self::from_delete_values(est, delete_values, separators))
+    }
+
+    /// Jackknife of the ratio `est` = numer / denom, ldsc's `RatioJackknife`, from the delete
+    /// values of the numerator and the denominator (both n_blocks x p).
+    pub fn ratio(
+        est: RowDVector<f64>,
+        numer_delete_values: &DMatrix<f64>,
+        denom_delete_values: &DMatrix<f64>,
+    ) -> Self {
+        let n_blocks = numer_delete_values.nrows() as f64;
+        let delete_values = numer_delete_values.component_div(denom_delete_values);
+        let mut pseudovalues = &delete_values * -(n_blocks - 1.0);
+        for mut row in pseudovalues.row_iter_mut() {
+            row += &est * n_blocks;
+        }
+        Self::from_pseudovalues(est, delete_values, pseudovalues, vec![])
+    }
+}
+
+/// Solve the square system `a x = b` by LU decomposition, like `np.linalg.solve`.
+fn solve(a: &DMatrix<f64>, b: &DVector<f64>) -> Result<DVector<f64>> {
+    match a.clone().lu().solve(b) {
+        Some(x) => Ok(x),
+        None => bail!("Singular matrix."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separators_match_numpy_linspace() {
+        assert_eq!(get_separators(10, 3).unwrap(), [0, 3, 6, 10]);
+        assert_eq!(get_separators(7, 7).unwrap(), [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert!(get_separators(3, 4).is_err());
+    }
+
+    #[test]
+    fn blocks_by_chromosome_and_distance() {
+        let chr = [1, 1, 1, 1, 2, 2];
+        let bp = [100, 500, 1100, 1200, 100, 5000];
+        let by_chr = block_labels(&chr, &bp, None).unwrap();
+        assert_eq!(by_chr, [0, 0, 0, 0, 1, 1]);
+        let by_dist = block_labels(&chr, &bp, Some(1000.0)).unwrap();
+        assert_eq!(by_dist, [0, 0, 1, 1, 2, 3]);
+        assert_eq!(separators_from_labels(&by_dist).unwrap(), [0, 2, 4, 5, 6]);
+        assert!(block_labels(&[1, 1], &[200, 100], None).is_err());
+        assert!(separators_from_labels(&[0, 1, 0]).is_err());
+    }
+
+    #[test]
+    fn lstsq_jackknife_of_a_mean() {
+        // regressing y on a constant estimates its mean; the jackknife SE of a mean with
+        // one point per block is the usual standard error
+        let y = DVector::from_vec(vec![1.0, 2.0, 4.0, 7.0, 11.0]);
+        let x = DMatrix::from_element(5, 1, 1.0);
+        let jk = Jackknife::lstsq(&x, &y, get_separators(5, 5).unwrap()).unwrap();
+        let mean = 5.0;
+        let sd = (y.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / 4.0).sqrt();
+        assert!((jk.est[0] - mean).abs() < 1e-12);
+        assert!((jk.jknife_est[0] - mean).abs() < 1e-12);
+        assert!((jk.jknife_se[0] - sd / 5f64.sqrt()).abs() < 1e-12);
+        assert!((jk.delete_values[(0, 0)] - 6.0).abs() < 1e-12);
+    }
+}
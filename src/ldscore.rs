@@ -0,0 +1,700 @@
+use anyhow::{anyhow, bail, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{info, warn};
+use nalgebra::DMatrix;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+
+use crate::cli::{GlobalArgs, L2Args};
+use crate::parse::{read_annot, Annot};
+use crate::plink::{read_bim, read_fam, Individual, PlinkBed, Snp};
+use crate::utils::{expand_chr, get_input_reader, py_str, read_ids, sub_chr};
+
+// SNPs with MAF above this count towards M_5_50
+const MAF_5_50: f64 = 0.05;
+// LD score matrices with a larger condition number are flagged as ill-conditioned
+const MAX_COND_NUM: f64 = 10000.0;
+
+/// For each SNP, the index of the leftmost SNP within `max_dist` of it, given sorted
+/// `coords` (SNP index, BP or CM).
+pub fn block_lefts(coords: &[f64], max_dist: f64) -> Vec<usize> {
+    let mut j = 0;
+    coords
+        .iter()
+        .map(|x| {
+            while j < coords.len() && (coords[j] - x).abs() > max_dist {
+                j += 1;
+            }
+            j
+        })
+        .collect()
+}
+
+/// Approximately unbiased estimate of r^2 from the sample correlation `r` of `n` individuals.
+pub fn l2_unbiased(r: f64, n: usize) -> f64 {
+    let denom = if n > 2 { n - 2 } else { n } as f64;
+    let sq = r * r;
+    sq - (1.0 - sq) / denom
+}
+
+/// LD scores of every SNP kept in `bed` with respect to each column of `annot` (SNPs x
+/// annotations), summing r^2 over the SNPs in its window.
+///
+/// A port of ldsc's `__corSumVarBlocks__`: SNPs are read `chunk_size` at a time and windows
+/// are rounded up to a multiple of `chunk_size`, exactly as ldsc does, so that the LD scores
+/// match those of ldsc run with the same `--chunk-size`.
+pub fn ld_score_var_blocks(
+    bed: &PlinkBed,
+    block_left: &[usize],
+    chunk_size: usize,
+    annot: &DMatrix<f64>,
+) -> DMatrix<f64> {
+    let m = bed.kept_snps().len();
+    let n = bed.n_indiv();
+    let mut c = chunk_size.max(1);
+    let round_up = |x: usize, c: usize| x.div_ceil(c) * c;
+    let block_sizes = block_left
+        .iter()
+        .enumerate()
+        .map(|(i, l)| round_up(i - l, c))
+        .collect::<Vec<_>>();
+    let zero_rows = annot
+        .row_iter()
+        .map(|r| r.iter().all(|x| *x == 0.0))
+        .collect::<Vec<_>>();
+    let all_zero = |start: usize, len: usize| zero_rows[start..start + len].iter().all(|z| *z);
+    let r2 =
+        |a: &DMatrix<f64>, b: &DMatrix<f64>| a.tr_mul(&(b / n as f64)).map(|r| l2_unbiased(r, n));
+    let mut cor_sum = DMatrix::zeros(m, annot.ncols());
+
+    // b = index of the first SNP whose window does not include SNP 0, rounded up to a
+    // multiple of c
+    let mut b = round_up(block_left.iter().position(|l| *l > 0).unwrap_or(m), c);
+    if b > m {
+        c = 1;
+        b = m;
+    }
+    let mut l_a = 0;
+    let mut next = 0;
+    let mut get_snps = |k: usize| {
+        let block = bed.standardized_block(next, next + k);
+        next += k;
+        block
+    };
+    let mut a = get_snps(b);
+    // chunks inside the first block
+    for l_b in (0..b).step_by(c) {
+        let r_ab = r2(&a, &a.columns(l_b, c).into_owned());
+        let mut rows = cor_sum.rows_mut(l_a, b);
+        rows += r_ab * annot.rows(l_b, c);
+    }
+
+    // chunks to the right of the first block
+    let b0 = b;
+    let md = c * (m / c);
+    let end = if md != m { md + 1 } else { md };
+    let mut chunk = DMatrix::zeros(n, 0);
+    for l_b in (b0..end).step_by(c) {
+        let old_b = b;
+        b = block_sizes[l_b];
+        if l_b > b0 && b > 0 {
+            // block sizes can't grow by more than c, nor drop below c unless to 0
+            let mut new_a = DMatrix::zeros(n, b);
+            new_a
+                .columns_mut(0, b - c)
+                .copy_from(&a.columns(old_b + c - b, b - c));
+            new_a.columns_mut(b - c, c).copy_from(&chunk);
+            a = new_a;
+            l_a += old_b + c - b;
+        } else if l_b == b0 && b > 0 {
+            a = a.columns(b0 - b, b).into_owned();
+            l_a = b0 - b;
+        } else if b == 0 {
+            // no SNPs to the left in the window, e.g. after a gap
+            a = DMatrix::zeros(n, 0);
+            l_a = l_b;
+        }
+        if l_b == md {
+            c = m - md;
+        }
+        chunk = get_snps(c);
+        // sparse annotations (e.g. pathways) can be all zeros for this block and chunk
+        if all_zero(l_a, b) && all_zero(l_b, c) {
+            continue;
+        }
+        let r_ab = r2(&a, &chunk);
+        let mut rows = cor_sum.rows_mut(l_a, b);
+        rows += &r_ab * annot.rows(l_b, c);
+        let mut rows = cor_sum.rows_mut(l_b, c);
+        rows += r_ab.tr_mul(&annot.rows(l_a, b));
+        let r_bb = r2(&chunk, &chunk);
+        let mut rows = cor_sum.rows_mut(l_b, c);
+        rows += r_bb * annot.rows(l_b, c);
+    }
+    cor_sum
+}
+
+/// Entry point of `ldscrs l2`.
+///
+/// With an `@` in `--bfile`, LD scores are estimated for each of chromosomes 1-22 in turn,
+/// substituting the chromosome in `--bfile`, `--out` (see [`sub_chr`]) and any `@` in
+/// `--annot` and `--cts-bin`, so the output can be read back with `--ref-ld-chr <out>`.
+pub fn run(args: &L2Args, global: &GlobalArgs) -> Result<()> {
+    if !args.bfile.contains('@') {
+        return estimate(args, &global.out);
+    }
+    expand_chr(&args.bfile, ".bed")?;
+    for chr in 1..=22 {
+        let sub = |x: &Option<String>| x.as_ref().map(|x| x.replace('@', &chr.to_string()));
+        let chr_args = L2Args {
+            bfile: sub_chr(&args.bfile, chr),
+            annot: sub(&args.annot),
+            cts_bin: sub(&args.cts_bin),
+            ..args.clone()
+        };
+        info!("Estimating LD Score for chromosome {}.", chr);
+        estimate(&chr_args, &sub_chr(&global.out, chr))?;
+    }
+    Ok(())
+}
+
+/// Estimate LD scores for `args.bfile`, writing them to `out`.l2.ldscore.gz.
+fn estimate(args: &L2Args, out: &str) -> Result<()> {
+    let bim_fname = format!("{}.bim", args.bfile);
+    let snps = read_bim(&bim_fname)?;
+    info!("Read list of {} SNPs from {}", snps.len(), bim_fname);
+    let fam_fname = format!("{}.fam", args.bfile);
+    let indivs = read_fam(&fam_fname)?;
+    info!(
+        "Read list of {} individuals from {}",
+        indivs.len(),
+        fam_fname
+    );
+
+    let keep_indivs = match &args.keep {
+        Some(path) => {
+            let keep = keep_indivs(path, &indivs)?;
+            info!("After filtering, {} individuals remain", keep.len());
+            Some(keep)
+        }
+        None => None,
+    };
+    let annot = if let Some(path) = &args.annot {
+        let annot = read_annot(path, args.thin_annot)?;
+        info!(
+            "Read {} annotations for {} SNPs from {}",
+            annot.colnames.len(),
+            annot.matrix.nrows(),
+            path
+        );
+        let same_snps = match &annot.snps {
+            Some(ids) => ids.len() == snps.len() && ids.iter().zip(&snps).all(|(a, b)| *a == b.snp),
+            None => annot.matrix.nrows() == snps.len(),
+        };
+        if !same_snps {
+            bail!("The .annot file must contain the same SNPs in the same order as the .bim file.");
+        }
+        Some(annot)
+    } else if let Some(cts_bin) = &args.cts_bin {
+        Some(cts_bin_annot(
+            cts_bin,
+            args.cts_breaks.as_deref().unwrap_or_default(),
+            args.cts_names.as_deref(),
+            &snps,
+        )?)
+    } else {
+        None
+    };
+
+    let keep_snps = match &args.extract {
+        Some(path) => {
+            let ids = read_ids(path, 0)?.into_iter().collect::<HashSet<_>>();
+            let keep = (0..snps.len())
+                .filter(|&j| ids.contains(&snps[j].snp))
+                .collect::<Vec<_>>();
+            if keep.is_empty() {
+                bail!("No SNPs retained for analysis");
+            }
+            info!("After filtering, {} SNPs remain", keep.len());
+            keep
+        }
+        None => (0..snps.len()).collect(),
+    };
+
+    let bed_fname = format!("{}.bed", args.bfile);
+    info!("Reading genotypes from {}", bed_fname);
+    let maf_min = args.maf.unwrap_or(0.0);
+    let mut bed = PlinkBed::open(&bed_fname, indivs.len(), snps.len())?;
+    if let Some(keep) = &keep_indivs {
+        bed = bed.with_keep_indivs(keep, maf_min)?;
+    }
+    let bed = bed.with_keep_snps(&keep_snps, maf_min)?;
+    let m = bed.kept_snps().len();
+    info!("After filtering, {} SNPs remain", m);
+    if m == 0 {
+        bail!("No SNPs retained for analysis");
+    }
+    let kept = bed
+        .kept_snps()
+        .iter()
+        .map(|&j| &snps[j])
+        .collect::<Vec<_>>();
+
+    // determine block widths
+    let (coords, max_dist) = if let Some(ld_wind_snps) = args.ld_wind_snps {
+        ((0..m).map(|i| i as f64).collect(), ld_wind_snps as f64)
+    } else if let Some(ld_wind_kb) = args.ld_wind_kb {
+        (
+            kept.iter().map(|s| s.bp as f64).collect::<Vec<_>>(),
+            ld_wind_kb * 1000.0,
+        )
+    } else if let Some(ld_wind_cm) = args.ld_wind_cm {
+        (kept.iter().map(|s| s.cm).collect(), ld_wind_cm)
+    } else {
+        bail!("Must specify exactly one --ld-wind option");
+    };
+    let block_left = block_lefts(&coords, max_dist);
+    if block_left[m - 1] == 0 && !args.yes_really {
+        bail!("Do you really want to compute whole-chomosome LD Score? If so, set the --yes-really flag (warning: it will use a lot of time / memory)");
+    }
+
+    // filter the annotations down to the SNPs passing the MAF cutoff
+    let (annot_matrix, annot_colnames, integer) = match &annot {
+        Some(annot) => (
+            annot.matrix.select_rows(bed.kept_snps()),
+            annot.colnames.clone(),
+            annot.integer,
+        ),
+        None => (DMatrix::from_element(m, 1, 1.0), vec![], true),
+    };
+    let colnames = if annot_matrix.ncols() == 1 {
+        vec!["L2".to_string()]
+    } else {
+        annot_colnames.iter().map(|c| format!("{}L2", c)).collect()
+    };
+
+    info!("Estimating LD Score.");
+    let l2 = ld_score_var_blocks(&bed, &block_left, args.chunk_size, &annot_matrix);
+
+    // only print the --print-snps SNPs, but M still counts every SNP
+    let print_rows = match &args.print_snps {
+        Some(path) => {
+            let ids = read_ids(path, 0)?.into_iter().collect::<HashSet<_>>();
+            let rows = (0..m)
+                .filter(|&i| ids.contains(&kept[i].snp))
+                .collect::<Vec<_>>();
+            if rows.is_empty() {
+                bail!("After merging with --print-snps, no SNPs remain.");
+            }
+            info!(
+                "After merging with --print-snps, LD Scores for {} SNPs will be printed.",
+                rows.len()
+            );
+            rows
+        }
+        None => (0..m).collect(),
+    };
+
+    let out_fname = format!("{}.l2.ldscore.gz", out);
+    info!(
+        "Writing LD Scores for {} SNPs to {}",
+        print_rows.len(),
+        out_fname
+    );
+    write_ldscore(&out_fname, &kept, &colnames, &l2, &print_rows)?;
+
+    // M is the sum of each annotation, i.e. the number of SNPs without annotations
+    let maf = bed.maf();
+    let common = (0..m).filter(|&i| maf[i] > MAF_5_50).collect::<Vec<_>>();
+    let format_m = |x: f64| {
+        if integer {
+            format!("{}", x as i64)
+        } else {
+            py_str(x)
+        }
+    };
+    let m_annot = annot_matrix
+        .row_sum()
+        .iter()
+        .map(|x| format_m(*x))
+        .collect::<Vec<_>>();
+    let m_5_50 = annot_matrix
+        .select_rows(&common)
+        .row_sum()
+        .iter()
+        .map(|x| format_m(*x))
+        .collect::<Vec<_>>();
+    write_m(&format!("{}.l2.M", out), &m_annot)?;
+    write_m(&format!("{}.l2.M_5_50", out), &m_5_50)?;
+
+    if args.cts_bin.is_some() && !args.no_print_annot {
+        let annot_fname = format!("{}.annot.gz", out);
+        info!(
+            "Writing annot matrix produced by --cts-bin to {}",
+            annot_fname
+        );
+        write_annot(&annot_fname, &kept, &annot_colnames, &annot_matrix)?;
+    }
+
+    log_summary(&out_fname, &colnames, &l2, &maf, &print_rows);
+    if l2.ncols() > 1 {
+        let svd = l2.select_rows(&print_rows).svd(false, false);
+        let cond_num = svd.singular_values.max() / svd.singular_values.min();
+        info!("\nLD Score Matrix Condition Number\n{}", py_str(cond_num));
+        if cond_num > MAX_COND_NUM {
+            warn!("WARNING: ill-conditioned LD Score Matrix!");
+        }
+    }
+    Ok(())
+}
+
+/// Bin SNPs on the continuous variables in `fnames` (comma-separated files of SNP and
+/// value, in .bim order) at `breaks`, one annotation per combination of bins.
+///
+/// `breaks` holds one comma-separated list per file, separated by x, with N for a minus
+/// sign (e.g. `N1,0,1x0.1,0.5`); `names` (comma-separated) name the variables.
+/// Indices of the individuals in `indivs` whose IID is listed in the --keep file at
+/// `path`, which has one IID per line.
+fn keep_indivs(path: &str, indivs: &[Individual]) -> Result<Vec<usize>> {
+    let ids = read_ids(path, 0)?.into_iter().collect::<HashSet<_>>();
+    let keep = (0..indivs.len())
+        .filter(|&i| ids.contains(&indivs[i].iid))
+        .collect::<Vec<_>>();
+    if keep.is_empty() {
+        bail!("No individuals retained for analysis");
+    }
+    Ok(keep)
+}
+
+fn cts_bin_annot(fnames: &str, breaks: &str, names: Option<&str>, snps: &[Snp]) -> Result<Annot> {
+    let fnames = fnames.split(',').collect::<Vec<_>>();
+    let breaks = breaks
+        .replace('N', "-")
+        .split('x')
+        .map(|y| y.split(',').map(|x| x.parse::<f64>()).collect())
+        .collect::<Result<Vec<Vec<_>>, _>>()
+        .map_err(|e| {
+            anyhow!(
+                "--cts-breaks must be a comma-separated list of numbers: {}",
+                e
+            )
+        })?;
+    if breaks.len() != fnames.len() {
+        bail!("Need to specify one set of breaks for each file in --cts-bin.");
+    }
+    let cts_colnames = match names {
+        Some(names) => {
+            let names = names.split(',').map(|x| x.to_string()).collect::<Vec<_>>();
+            if names.len() != fnames.len() {
+                bail!(
+                    "Must specify either no --cts-names or one value for each file in --cts-bin."
+                );
+            }
+            names
+        }
+        None => (0..fnames.len()).map(|i| format!("ANNOT{}", i)).collect(),
+    };
+    info!(
+        "Reading numbers with which to bin SNPs from {}",
+        fnames.join(",")
+    );
+
+    // bin of each SNP for each variable, and the bin labels of each variable
+    let mut bins = Vec::new();
+    let mut labels = Vec::new();
+    for (fname, breaks) in fnames.iter().zip(breaks) {
+        let mut ids = Vec::new();
+        let mut values = Vec::new();
+        for line in get_input_reader(fname)?.lines() {
+            let line = line?;
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields[..] {
+                [] => continue,
+                [id, value] => {
+                    // pandas.cut would leave the SNP without a bin
+                    let Some(value) = value.parse::<f64>().ok().filter(|x| x.is_finite()) else {
+                        bail!(
+                            "Cannot bin SNP {} in {}: {:?} is not a finite number.",
+                            id,
+                            fname,
+                            value
+                        );
+                    };
+                    ids.push(id.to_string());
+                    values.push(value);
+                }
+                _ => bail!("{} must have two columns, SNP and ANNOT.", fname),
+            }
+        }
+        if ids.len() != snps.len() || ids.iter().zip(snps).any(|(a, b)| *a != b.snp) {
+            bail!("--cts-bin and the .bim file must have identical SNP columns.");
+        }
+
+        let max_cts = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let min_cts = values.iter().cloned().fold(f64::INFINITY, f64::min);
+        let mut cut_breaks = breaks.clone();
+        let mut name_breaks = breaks.iter().map(|x| (*x, py_str(*x))).collect::<Vec<_>>();
+        if cut_breaks.iter().all(|x| *x >= max_cts) || cut_breaks.iter().all(|x| *x <= min_cts) {
+            bail!("All breaks lie outside the range of the cts variable.");
+        }
+        if cut_breaks.iter().all(|x| *x <= max_cts) {
+            name_breaks.push((max_cts, String::new()));
+            cut_breaks.push(max_cts + 1.0);
+        }
+        if cut_breaks.iter().all(|x| *x >= min_cts) {
+            name_breaks.push((min_cts, String::new()));
+            cut_breaks.push(min_cts - 1.0);
+        }
+        name_breaks.sort_by(|a, b| a.0.total_cmp(&b.0));
+        cut_breaks.sort_by(|a, b| a.total_cmp(b));
+        // so that column names are consistent across chromosomes with different max values
+        let n_breaks = name_breaks.len();
+        name_breaks[0].1 = "min".to_string();
+        name_breaks[n_breaks - 1].1 = "max".to_string();
+        labels.push(
+            name_breaks
+                .windows(2)
+                .map(|w| format!("{}_{}", w[0].1, w[1].1))
+                .collect::<Vec<_>>(),
+        );
+        // bins are right-closed, like pandas.cut
+        bins.push(
+            values
+                .iter()
+                .map(|v| cut_breaks.windows(2).position(|w| w[0] < *v && *v <= w[1]))
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    // one column per combination of bins, the last variable varying fastest
+    let n_annot = labels.iter().map(|l| l.len()).product();
+    let mut matrix = DMatrix::zeros(snps.len(), n_annot);
+    for i in 0..snps.len() {
+        let mut k = 0;
+        for (bin, labs) in bins.iter().zip(&labels) {
+            match bin[i] {
+                Some(b) => k = k * labs.len() + b,
+                None => bail!(
+                    "SNP {} has no annotation in --cts-bin. This is a bug!",
+                    snps[i].snp
+                ),
+            }
+        }
+        matrix[(i, k)] = 1.0;
+    }
+    let mut colnames = vec![String::new()];
+    for (name, labs) in cts_colnames.iter().zip(&labels) {
+        colnames = colnames
+            .iter()
+            .flat_map(|prefix| {
+                labs.iter().map(move |lab| match prefix.as_str() {
+                    "" => format!("{}_{}", name, lab),
+                    _ => format!("{}_{}_{}", prefix, name, lab),
+                })
+            })
+            .collect();
+    }
+    Ok(Annot {
+        snps: None,
+        colnames,
+        matrix,
+        integer: true,
+    })
+}
+
+/// Write `CHR SNP BP CM <annotations>` as a gzipped, tab-separated `.annot.gz` file.
+fn write_annot(path: &str, snps: &[&Snp], colnames: &[String], annot: &DMatrix<f64>) -> Result<()> {
+    let mut writer = BufWriter::new(GzEncoder::new(File::create(path)?, Compression::default()));
+    writeln!(writer, "CHR\tSNP\tBP\tCM\t{}", colnames.join("\t"))?;
+    for (i, snp) in snps.iter().enumerate() {
+        write!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            snp.chr,
+            snp.snp,
+            snp.bp,
+            py_str(snp.cm)
+        )?;
+        for x in annot.row(i).iter() {
+            write!(writer, "\t{}", *x as i64)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Write `CHR SNP BP <LD scores>` for `rows` of `l2` as a gzipped, tab-separated
+/// `.ldscore.gz` file, with LD scores printed to 3 decimals as ldsc does.
+fn write_ldscore(
+    path: &str,
+    snps: &[&Snp],
+    colnames: &[String],
+    l2: &DMatrix<f64>,
+    rows: &[usize],
+) -> Result<()> {
+    let mut writer = BufWriter::new(GzEncoder::new(File::create(path)?, Compression::default()));
+    writeln!(writer, "CHR\tSNP\tBP\t{}", colnames.join("\t"))?;
+    for &i in rows {
+        let snp = snps[i];
+        write!(writer, "{}\t{}\t{}", snp.chr, snp.snp, snp.bp)?;
+        for x in l2.row(i).iter() {
+            write!(writer, "\t{:.3}", x)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Write one line of tab-separated SNP counts as a `.M` / `.M_5_50` file.
+fn write_m(path: &str, m: &[String]) -> Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{}", m.join("\t"))?;
+    Ok(())
+}
+
+/// Mean, SD, min, 25%, 50%, 75% and max, like pandas' `describe`.
+fn describe(values: &[f64]) -> [f64; 7] {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let n = sorted.len() as f64;
+    let mean = sorted.iter().sum::<f64>() / n;
+    let sd = (sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+    let quantile = |q: f64| {
+        let pos = q * (n - 1.0);
+        let lo = pos.floor() as usize;
+        let hi = pos.ceil() as usize;
+        sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f64)
+    };
+    [
+        mean,
+        sd,
+        sorted[0],
+        quantile(0.25),
+        quantile(0.5),
+        quantile(0.75),
+        sorted[sorted.len() - 1],
+    ]
+}
+
+fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let (mx, my) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (a, b) in x.iter().zip(y) {
+        sxy += (a - mx) * (b - my);
+        sxx += (a - mx).powi(2);
+        syy += (b - my).powi(2);
+    }
+    sxy / (sxx * syy).sqrt()
+}
+
+/// Log the distribution of MAF and the LD scores printed, and their correlations.
+fn log_summary(fname: &str, colnames: &[String], l2: &DMatrix<f64>, maf: &[f64], rows: &[usize]) {
+    let mut names = vec!["MAF".to_string()];
+    names.extend(colnames.iter().cloned());
+    let mut columns = vec![rows.iter().map(|&i| maf[i]).collect::<Vec<_>>()];
+    for k in 0..l2.ncols() {
+        columns.push(rows.iter().map(|&i| l2[(i, k)]).collect());
+    }
+    let width = names.iter().map(|s| s.len() + 2).max().unwrap_or(0).max(12);
+    let header = names
+        .iter()
+        .map(|s| format!("{:>width$}", s))
+        .collect::<String>();
+
+    let stats = columns.iter().map(|c| describe(c)).collect::<Vec<_>>();
+    let mut table = format!("\nSummary of LD Scores in {}\n      {}", fname, header);
+    for (k, stat) in ["mean", "std", "min", "25%", "50%", "75%", "max"]
+        .iter()
+        .enumerate()
+    {
+        table += &format!("\n{:<6}", stat);
+        for s in &stats {
+            table += &format!("{:>width$.4}", s[k]);
+        }
+    }
+    info!("{}", table);
+
+    let mut table = format!("\nMAF/LD Score Correlation Matrix\n      {}", header);
+    for (name, x) in names.iter().zip(&columns) {
+        table += &format!("\n{:<6}", name);
+        for y in &columns {
+            table += &format!("{:>width$.4}", pearson(x, y));
+        }
+    }
+    info!("{}", table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn block_lefts_follow_the_window() {
+        let coords = [0.0, 1.0, 2.0, 10.0, 11.0, 30.0];
+        assert_eq!(block_lefts(&coords, 1.0), [0, 0, 1, 3, 3, 5]);
+        assert_eq!(block_lefts(&coords, 100.0), [0; 6]);
+    }
+
+    #[test]
+    fn l2_unbiased_matches_ldsc() {
+        assert_eq!(l2_unbiased(1.0, 10), 1.0);
+        assert!((l2_unbiased(0.5, 10) - (0.25 - 0.75 / 8.0)).abs() < 1e-15);
+        assert_eq!(l2_unbiased(0.0, 2), -0.5);
+    }
+
+    #[test]
+    fn keep_file_lists_one_iid_per_line() {
+        let dir = TempDir::new();
+        let fam = dir.write(
+            "keep.fam",
+            "F1 I1 0 0 1 -9\nF1 I2 0 0 2 -9\nF2 I3 0 0 1 -9\nF2 I4 0 0 2 -9\n",
+        );
+        let indivs = read_fam(&fam).unwrap();
+        let keep = dir.write("keep.txt", "I4\nI2\nI9\n");
+        assert_eq!(keep_indivs(&keep, &indivs).unwrap(), [1, 3]);
+
+        // family IDs are not IIDs
+        let keep = dir.write("keep.txt", "F1\nF2\n");
+        let err = keep_indivs(&keep, &indivs).unwrap_err();
+        assert_eq!(err.to_string(), "No individuals retained for analysis");
+    }
+
+    #[test]
+    fn cts_bin_reports_values_it_cannot_bin() {
+        let snps = (1..=4)
+            .map(|i| Snp {
+                chr: 1,
+                snp: format!("rs{}", i),
+                cm: 0.0,
+                bp: i * 100,
+                a1: "A".to_string(),
+                a2: "G".to_string(),
+            })
+            .collect::<Vec<_>>();
+        let dir = TempDir::new();
+
+        let path = &dir.write("cts", "rs1 0.5\nrs2 1.5\nrs3 2.5\nrs4 -1\n");
+        let annot = cts_bin_annot(path, "1,2", Some("X"), &snps).unwrap();
+        assert_eq!(annot.colnames, ["X_min_1.0", "X_1.0_2.0", "X_2.0_max"]);
+        let bins = (0..4)
+            .map(|i| annot.matrix.row(i).iter().position(|x| *x == 1.0).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(bins, [0, 1, 2, 0]);
+
+        let path = &dir.write("cts", "rs1 0.5\nrs2 NA\nrs3 2.5\nrs4 -1\n");
+        let err = cts_bin_annot(path, "1,2", None, &snps).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Cannot bin SNP rs2 in {}: \"NA\" is not a finite number.",
+                path
+            )
+        );
+    }
+}
@@ -1,4 +1,20 @@
 pub mod cli;
-// pub mod munge_sumstats;
 pub mod const_value;
+pub mod irwls;
+pub mod jackknife;
+pub mod ldscore;
+pub mod logger;
+pub mod make_annot;
+pub mod munge_sumstats;
+pub mod parse;
+pub mod plink;
+pub mod regressions;
+pub mod stats;
+pub mod sumstats;
+#[cfg(test)]
+mod test_utils;
 pub mod utils;
+
+pub use munge_sumstats::{
+    munge, munge_with, FilterReport, MedianCheck, MungeOptions, MungeReport, MungedSumstats,
+};
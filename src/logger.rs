@@ -0,0 +1,182 @@
+use anyhow::Result;
+use chrono::{Local, Utc};
+use log::{info, LevelFilter, Log, Metadata, Record};
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::cli::GlobalArgs;
+
+const MASTHEAD: &str = "*********************************************************************";
+
+/// Logger writing every record to stderr and to `<out>.log`, like ldsc does.
+///
+/// stderr honours `--verbose`/`--quiet`, while the log file always keeps at least
+/// the info records so it can be archived next to the results.
+struct TeeLogger {
+    stderr_level: LevelFilter,
+    file_level: LevelFilter,
+    file: Mutex<LineWriter<File>>,
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.stderr_level || metadata.level() <= self.file_level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() <= self.stderr_level {
+            eprintln!(
+                "[{} {:<5} {}] {}",
+                Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+        // warnings and errors carry their own "WARNING:"/"ERROR" prefix, as in ldsc
+        if record.level() <= self.file_level {
+            if let Ok(mut file) = self.file.lock() {
+                let _ = writeln!(file, "{}", record.args());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+impl TeeLogger {
+    /// Logger for the verbosity of `global`, creating the log file at `log_file`.
+    fn new(global: &GlobalArgs, log_file: &str) -> Result<Self> {
+        let stderr_level = if global.quiet {
+            LevelFilter::Warn
+        } else {
+            match global.verbose {
+                0 => LevelFilter::Info,
+                1 => LevelFilter::Debug,
+                _ => LevelFilter::Trace,
+            }
+        };
+        let file = File::create(log_file)
+            .map_err(|e| anyhow::anyhow!("Cannot create log file {}: {}", log_file, e))?;
+        Ok(Self {
+            stderr_level,
+            file_level: stderr_level.max(LevelFilter::Info),
+            file: Mutex::new(LineWriter::new(file)),
+        })
+    }
+}
+
+/// Initialize the global logger, writing to stderr and `<out>.log`.
+pub fn init_logger(global: &GlobalArgs) -> Result<()> {
+    let logger = TeeLogger::new(global, &format!("{}.log", global.out))?;
+    log::set_max_level(logger.file_level);
+    log::set_boxed_logger(Box::new(logger))?;
+    Ok(())
+}
+
+/// Log the masthead, the command line and the start time.
+pub fn log_header() {
+    info!("{}", MASTHEAD);
+    info!("* LD Score Regression (LDSC) in Rust");
+    info!("* Version {}", env!("CARGO_PKG_VERSION"));
+    info!("{}", MASTHEAD);
+    info!("Call: \n{}", format_call(std::env::args()));
+    info!(
+        "Beginning analysis at {}",
+        Local::now().format("%a %b %e %H:%M:%S %Y")
+    );
+}
+
+/// Log the end time and the total time elapsed since `start`.
+pub fn log_footer(start: Instant) {
+    info!(
+        "Analysis finished at {}",
+        Local::now().format("%a %b %e %H:%M:%S %Y")
+    );
+    info!("Total time elapsed: {}", sec_to_str(start.elapsed()));
+    log::logger().flush();
+}
+
+// One option per line, as ldsc prints it.
+fn format_call(args: impl Iterator<Item = String>) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for arg in args {
+        match lines.last_mut() {
+            Some(last) if !arg.starts_with('-') || arg == "-" => {
+                last.push(' ');
+                last.push_str(&arg);
+            }
+            _ => lines.push(arg),
+        }
+    }
+    lines.join(" \\\n") + "\n"
+}
+
+fn sec_to_str(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let (d, rem) = ((secs / 86400.0).floor(), secs % 86400.0);
+    let (h, rem) = ((rem / 3600.0).floor(), rem % 3600.0);
+    let (m, s) = ((rem / 60.0).floor(), rem % 60.0);
+    let mut out = String::new();
+    if d > 0.0 {
+        out.push_str(&format!("{}d:", d));
+    }
+    if h > 0.0 {
+        out.push_str(&format!("{}h:", h));
+    }
+    if m > 0.0 {
+        out.push_str(&format!("{}m:", m));
+    }
+    out.push_str(&format!("{:.1}s", s));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use log::Level;
+
+    #[test]
+    fn log_file_keeps_info_records_when_quiet() {
+        let dir = TempDir::new();
+        let out = dir.path("logger");
+        let global = GlobalArgs {
+            out: out.clone(),
+            threads: 1,
+            verbose: 0,
+            quiet: true,
+        };
+        let log_file = format!("{}.log", out);
+        let logger = TeeLogger::new(&global, &log_file).unwrap();
+        assert_eq!(logger.stderr_level, LevelFilter::Warn);
+
+        for (level, msg) in [
+            (Level::Info, "Read 10 SNPs."),
+            (Level::Warn, "WARNING: 2 SNPs had INFO outside of [0,2]."),
+            (Level::Debug, "Column name map"),
+        ] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("{}", msg))
+                    .build(),
+            );
+        }
+        logger.flush();
+        let text = std::fs::read_to_string(&log_file).unwrap();
+        assert_eq!(
+            text,
+            "Read 10 SNPs.\nWARNING: 2 SNPs had INFO outside of [0,2].\n"
+        );
+    }
+}
@@ -1,3 +1,40 @@
-fn main() {
-    println!("Hello, world!");
+use anyhow::Result;
+use clap::Parser;
+use log::error;
+use std::time::Instant;
+
+use ldscrs::cli::{Cli, Commands};
+use ldscrs::logger::{init_logger, log_footer, log_header};
+use ldscrs::{ldscore, make_annot, munge_sumstats, sumstats};
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    init_logger(&cli.global)?;
+    let start = Instant::now();
+    log_header();
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(cli.global.threads)
+        .build_global()?;
+
+    let result = match &cli.command {
+        Commands::Munge(args) => munge_sumstats::run(args, &cli.global),
+        Commands::L2(args) => ldscore::run(args, &cli.global),
+        Commands::H2(args) => sumstats::estimate_h2(args, &cli.global).map(|_| ()),
+        Commands::Rg(args) if args.matrix => {
+            sumstats::estimate_rg_matrix(args, &cli.global).map(|_| ())
+        }
+        Commands::H2Cts(args) => sumstats::cell_type_specific(args, &cli.global).map(|_| ()),
+        Commands::Rg(args) => sumstats::estimate_rg(args, &cli.global).map(|_| ()),
+        Commands::MakeAnnot(args) => make_annot::run(args, &cli.global),
+    };
+    if let Err(e) = &result {
+        error!("ERROR: {:#}", e);
+    }
+    log_footer(start);
+    if result.is_err() {
+        std::process::exit(1);
+    }
+    Ok(())
 }
@@ -0,0 +1,570 @@
+use anyhow::{anyhow, bail, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+
+use crate::cli::{GlobalArgs, MakeAnnotArgs};
+use crate::plink::{parse_chr, read_bim, Snp};
+use crate::utils::{get_input_reader, py_str, read_ids, sub_chr};
+
+/// A genomic region as in a BED file: 0-based, end exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    /// Chromosome, coded like [`Snp::chr`].
+    pub chr: u8,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Interval {
+    /// The interval extended by `windowsize` bp on both sides.
+    pub fn extend(&self, windowsize: u64) -> Self {
+        Interval {
+            chr: self.chr,
+            start: self.start.saturating_sub(windowsize),
+            end: self.end + windowsize,
+        }
+    }
+}
+
+/// Read the regions of a BED file, skipping track, browser and comment lines.
+pub fn read_bed(path: &str) -> Result<Vec<Interval>> {
+    let mut intervals = Vec::new();
+    for (i, line) in get_input_reader(path)?.lines().enumerate() {
+        let line = line?;
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.is_empty()
+            || ["track", "browser", "#"]
+                .iter()
+                .any(|p| fields[0].starts_with(p))
+        {
+            continue;
+        }
+        if fields.len() < 3 {
+            bail!(
+                "{} line {}: expected at least 3 columns, found {}.",
+                path,
+                i + 1,
+                fields.len()
+            );
+        }
+        let err = |what: &str| anyhow!("{} line {}: invalid {}.", path, i + 1, what);
+        let interval = Interval {
+            chr: parse_chr(fields[0]).ok_or_else(|| err("chromosome"))?,
+            start: fields[1].parse().map_err(|_| err("start"))?,
+            end: fields[2].parse().map_err(|_| err("end"))?,
+        };
+        if interval.end < interval.start {
+            bail!("{} line {}: end before start.", path, i + 1);
+        }
+        intervals.push(interval);
+    }
+    Ok(intervals)
+}
+
+/// Read a whitespace-delimited gene coordinate file with columns GENE, CHR, START and END,
+/// where START and END are the 1-based TSS and TES, as BED intervals [START - 1, END) keyed
+/// by gene, like the `x2 - 1` of ldsc's `gene_set_to_bed`.
+pub fn read_gene_coords(path: &str) -> Result<Vec<(String, Interval)>> {
+    let mut lines = get_input_reader(path)?.lines();
+    let header = match lines.next() {
+        Some(line) => line?,
+        None => bail!("Empty file: {:?}", path),
+    };
+    let header = header.split_whitespace().collect::<Vec<_>>();
+    let find = |c: &str| {
+        header
+            .iter()
+            .position(|h| *h == c)
+            .ok_or_else(|| anyhow!("Could not find {} column in {}.", c, path))
+    };
+    let cols = [find("GENE")?, find("CHR")?, find("START")?, find("END")?];
+    let mut genes = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.is_empty() {
+            continue;
+        }
+        let err = |what: &str| anyhow!("{} line {}: invalid {}.", path, i + 2, what);
+        let field = |j: usize| fields.get(cols[j]).copied().ok_or_else(|| err("row"));
+        let start = field(2)?.parse::<u64>().map_err(|_| err("START"))?;
+        let interval = Interval {
+            chr: parse_chr(field(1)?).ok_or_else(|| err("CHR"))?,
+            start: start.saturating_sub(1),
+            end: field(3)?.parse().map_err(|_| err("END"))?,
+        };
+        genes.push((field(0)?.to_string(), interval));
+    }
+    Ok(genes)
+}
+
+/// The intervals of the genes of `gene_set` in `coords` (every entry of a gene listed more
+/// than once), like ldsc's `gene_set_to_bed`.
+pub fn gene_set_intervals(gene_set: &[String], coords: &[(String, Interval)]) -> Vec<Interval> {
+    let genes = gene_set.iter().map(|g| g.as_str()).collect::<HashSet<_>>();
+    let matched = coords
+        .iter()
+        .filter(|(g, _)| genes.contains(g.as_str()))
+        .collect::<Vec<_>>();
+    let found = matched
+        .iter()
+        .map(|(g, _)| g.as_str())
+        .collect::<HashSet<_>>()
+        .len();
+    info!(
+        "Found {} of {} genes of the gene set in the gene coordinate file.",
+        found,
+        genes.len()
+    );
+    matched.iter().map(|(_, interval)| *interval).collect()
+}
+
+/// A gene of a GTF or GFF3 file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gene {
+    /// Ensembl-style gene ID, possibly with a version suffix.
+    pub id: String,
+    pub symbol: Option<String>,
+    pub chr: u8,
+    /// 1-based, inclusive.
+    pub start: u64,
+    pub end: u64,
+    /// Whether the gene is on the minus strand, where the TSS is `end`.
+    pub reverse: bool,
+}
+
+impl Gene {
+    /// The gene body, or its TSS if `tss`, extended `upstream` and `downstream` bp in the
+    /// direction of transcription, as a BED interval.
+    pub fn window(&self, tss: bool, upstream: u64, downstream: u64) -> Interval {
+        let (start, end) = match (tss, self.reverse) {
+            (false, _) => (self.start, self.end),
+            (true, false) => (self.start, self.start),
+            (true, true) => (self.end, self.end),
+        };
+        let (left, right) = match self.reverse {
+            false => (upstream, downstream),
+            true => (downstream, upstream),
+        };
+        Interval {
+            chr: self.chr,
+            start: start.saturating_sub(left).max(1) - 1,
+            end: end + right,
+        }
+    }
+}
+
+/// Attributes of a GTF (`key "value";`) or GFF3 (`key=value;`) line.
+fn parse_attributes(field: &str) -> HashMap<&str, &str> {
+    field
+        .split(';')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .filter_map(|a| match a.split_once('=') {
+            Some((k, v)) if !k.contains(' ') => Some((k, v)),
+            _ => a
+                .split_once(' ')
+                .map(|(k, v)| (k, v.trim().trim_matches('"'))),
+        })
+        .collect()
+}
+
+/// Read the genes of a GTF or GFF3 file (possibly compressed): the gene, pseudogene and
+/// `*_gene` features on chromosomes PLINK knows, identified by gene_id (GFF3: or ID) and
+/// named by gene_name (GFF3: or Name).
+pub fn read_genes(path: &str) -> Result<Vec<Gene>> {
+    let mut genes = Vec::new();
+    for (i, line) in get_input_reader(path)?.lines().enumerate() {
+        let line = line?;
+        if line.starts_with("##FASTA") {
+            break;
+        }
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() != 9 {
+            bail!(
+                "{} line {}: expected 9 tab-separated columns, found {}.",
+                path,
+                i + 1,
+                fields.len()
+            );
+        }
+        let feature = fields[2];
+        if !(feature == "gene" || feature == "pseudogene" || feature.ends_with("_gene")) {
+            continue;
+        }
+        // skip unplaced scaffolds and the like
+        let Some(chr) = parse_chr(fields[0]) else {
+            continue;
+        };
+        let err = |what: &str| anyhow!("{} line {}: invalid {}.", path, i + 1, what);
+        let attributes = parse_attributes(fields[8]);
+        let id = attributes
+            .get("gene_id")
+            .or_else(|| attributes.get("ID"))
+            .ok_or_else(|| err("gene ID"))?;
+        genes.push(Gene {
+            id: id.strip_prefix("gene:").unwrap_or(id).to_string(),
+            symbol: attributes
+                .get("gene_name")
+                .or_else(|| attributes.get("Name"))
+                .map(|s| s.to_string()),
+            chr,
+            start: fields[3].parse().map_err(|_| err("start"))?,
+            end: fields[4].parse().map_err(|_| err("end"))?,
+            reverse: fields[6] == "-",
+        });
+    }
+    Ok(genes)
+}
+
+/// The genes of `genes` whose ID, with or without its version suffix, or symbol is in
+/// `gene_set`.
+pub fn select_genes<'a>(gene_set: &[String], genes: &'a [Gene]) -> Vec<&'a Gene> {
+    let wanted = gene_set.iter().map(|g| g.as_str()).collect::<HashSet<_>>();
+    let mut found = HashSet::new();
+    let selected = genes
+        .iter()
+        .filter(|gene| {
+            let unversioned = gene
+                .id
+                .split_once('.')
+                .map_or(gene.id.as_str(), |(id, _)| id);
+            let keys = [
+                Some(gene.id.as_str()),
+                Some(unversioned),
+                gene.symbol.as_deref(),
+            ];
+            let matched = keys.iter().flatten().filter(|k| wanted.contains(*k));
+            let mut any = false;
+            for k in matched {
+                found.insert(*k);
+                any = true;
+            }
+            any
+        })
+        .collect();
+    info!(
+        "Found {} of {} genes of the gene set in the gene annotation file.",
+        found.len(),
+        wanted.len()
+    );
+    selected
+}
+
+/// Number of `intervals` overlapping each SNP, or, if `merge`, whether any does (as if
+/// overlapping intervals were merged first). ldsc's `--nomerge` instead repeats the row of
+/// a SNP for each interval overlapping it; the count keeps one row per SNP.
+pub fn annotate(snps: &[Snp], intervals: &[Interval], merge: bool) -> Vec<u32> {
+    // per chromosome, sorted starts and ends: the intervals overlapping position p are
+    // those starting at or before p minus those ending at or before p
+    let mut by_chr: HashMap<u8, (Vec<u64>, Vec<u64>)> = HashMap::new();
+    for interval in intervals {
+        let (starts, ends) = by_chr.entry(interval.chr).or_default();
+        starts.push(interval.start);
+        ends.push(interval.end);
+    }
+    for (starts, ends) in by_chr.values_mut() {
+        starts.sort_unstable();
+        ends.sort_unstable();
+    }
+    snps.iter()
+        .map(|snp| {
+            let Some((starts, ends)) = by_chr.get(&snp.chr) else {
+                return 0;
+            };
+            // 0-based position of the SNP
+            let p = snp.bp.saturating_sub(1);
+            let count = starts.partition_point(|&s| s <= p) - ends.partition_point(|&e| e <= p);
+            match merge {
+                true => count.min(1) as u32,
+                false => count as u32,
+            }
+        })
+        .collect()
+}
+
+/// Write the annotation `annot` of `snps` as a gzipped thin annot file with a single ANNOT
+/// column, or, if `full`, with CHR, BP, SNP and CM columns first.
+fn write_annot(path: &str, snps: &[Snp], annot: &[u32], full: bool) -> Result<()> {
+    let mut writer = BufWriter::new(GzEncoder::new(File::create(path)?, Compression::default()));
+    match full {
+        true => writeln!(writer, "CHR\tBP\tSNP\tCM\tANNOT")?,
+        false => writeln!(writer, "ANNOT")?,
+    }
+    for (snp, a) in snps.iter().zip(annot) {
+        if full {
+            let cm = py_str(snp.cm);
+            write!(writer, "{}\t{}\t{}\t{}\t", snp.chr, snp.bp, snp.snp, cm)?;
+        }
+        writeln!(writer, "{}", a)?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Annotate the SNPs of `bimfile` and write them to `out`.annot.gz.
+fn make_annot(
+    args: &MakeAnnotArgs,
+    bimfile: &str,
+    intervals: &[Interval],
+    out: &str,
+) -> Result<()> {
+    let snps = read_bim(bimfile)?;
+    info!("Read list of {} SNPs from {}", snps.len(), bimfile);
+    let annot = annotate(&snps, intervals, !args.nomerge);
+    let n_annot = annot.iter().filter(|&&a| a > 0).count();
+    info!("{} of {} SNPs are in the annotation.", n_annot, snps.len());
+    if n_annot == 0 {
+        warn!(
+            "WARNING: no SNPs are in the annotation. Check the chromosome names and coordinates."
+        );
+    }
+    let fname = format!("{}.annot.gz", out);
+    info!("Writing annot file to {}", fname);
+    write_annot(&fname, &snps, &annot, args.full_annot)
+}
+
+/// Make an annot file for the SNPs of `--bimfile` from the regions of `--bed-file`, or from
+/// the genes of `--gene-set-file` located by `--gene-coord-file`, like ldsc's
+/// `make_annot.py`, or by the GTF/GFF3 file of `--gene-annot-file`. An @ in `--bimfile`
+/// makes one annot file per chromosome.
+pub fn run(args: &MakeAnnotArgs, global: &GlobalArgs) -> Result<()> {
+    let extend = |intervals: Vec<Interval>| {
+        intervals
+            .iter()
+            .map(|i| i.extend(args.windowsize))
+            .collect::<Vec<_>>()
+    };
+    let intervals = match (&args.bed_file, &args.gene_set_file) {
+        (Some(bed_file), None) => {
+            let intervals = read_bed(bed_file)?;
+            info!("Read {} regions from {}", intervals.len(), bed_file);
+            extend(intervals)
+        }
+        (None, Some(gene_set_file)) => {
+            let gene_set = read_ids(gene_set_file, 0)?;
+            info!("Read {} genes from {}", gene_set.len(), gene_set_file);
+            match &args.gene_annot_file {
+                Some(gene_annot_file) => {
+                    let genes = read_genes(gene_annot_file)?;
+                    info!("Read {} genes from {}", genes.len(), gene_annot_file);
+                    let kb_to_bp = |kb: f64| (kb * 1000.0).round() as u64;
+                    let upstream = args.upstream_kb.map_or(args.windowsize, kb_to_bp);
+                    let downstream = args.downstream_kb.map_or(args.windowsize, kb_to_bp);
+                    select_genes(&gene_set, &genes)
+                        .iter()
+                        .map(|g| g.window(args.tss, upstream, downstream))
+                        .collect()
+                }
+                None => {
+                    let coords = read_gene_coords(&args.gene_coord_file)?;
+                    extend(gene_set_intervals(&gene_set, &coords))
+                }
+            }
+        }
+        _ => bail!("Must specify either --bed-file or --gene-set-file."),
+    };
+
+    if !args.bimfile.contains('@') {
+        return make_annot(args, &args.bimfile, &intervals, &global.out);
+    }
+    for chr in 1..=22 {
+        info!("Making annot file for chromosome {}.", chr);
+        make_annot(
+            args,
+            &sub_chr(&args.bimfile, chr),
+            &intervals,
+            &sub_chr(&global.out, chr),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn snp(chr: u8, bp: u64) -> Snp {
+        Snp {
+            chr,
+            snp: format!("rs{}_{}", chr, bp),
+            cm: 0.0,
+            bp,
+            a1: "A".to_string(),
+            a2: "G".to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_gtf_and_gff3_gene_windows() {
+        let gtf = r#"chr1	HAVANA	gene	1000	2000	.	-	.	gene_id "ENSG01.5"; gene_type "protein_coding"; gene_name "ABC";"#;
+        let gff3 =
+            "1\tensembl\tncRNA_gene\t500\t600\t.\t+\t.\tID=gene:ENSG02;Name=XYZ;biotype=lncRNA";
+        let attributes = parse_attributes(gtf.split('\t').nth(8).unwrap());
+        assert_eq!(attributes["gene_id"], "ENSG01.5");
+        assert_eq!(attributes["gene_name"], "ABC");
+        let attributes = parse_attributes(gff3.split('\t').nth(8).unwrap());
+        assert_eq!(attributes["ID"], "gene:ENSG02");
+
+        let dir = TempDir::new();
+        let path = dir.write("genes.gtf", format!("##gff-version 3\n{}\n{}\n", gtf, gff3));
+        let genes = read_genes(&path).unwrap();
+        assert_eq!(genes.len(), 2);
+        assert_eq!(
+            (genes[1].id.as_str(), genes[1].symbol.as_deref()),
+            ("ENSG02", Some("XYZ"))
+        );
+        let set = ["ENSG01".to_string(), "XYZ".to_string()];
+        assert_eq!(select_genes(&set, &genes).len(), 2);
+
+        // the TSS of a minus strand gene is its end, and upstream is to the right
+        let minus = &genes[0];
+        assert_eq!(minus.window(false, 100, 10).start, 989);
+        assert_eq!(minus.window(false, 100, 10).end, 2100);
+        let promoter = minus.window(true, 100, 10);
+        assert_eq!((promoter.start, promoter.end), (1989, 2100));
+        let promoter = genes[1].window(true, 1000, 10);
+        assert_eq!((promoter.start, promoter.end), (0, 510));
+    }
+
+    #[test]
+    fn annotates_snps_in_bed_intervals() {
+        let snps = [
+            snp(1, 100),
+            snp(1, 101),
+            snp(1, 150),
+            snp(1, 200),
+            snp(2, 150),
+        ];
+        // BED intervals are 0-based and end exclusive: [100, 200) holds bp 101-200
+        let intervals = [
+            Interval {
+                chr: 1,
+                start: 100,
+                end: 200,
+            },
+            Interval {
+                chr: 1,
+                start: 140,
+                end: 160,
+            },
+        ];
+        assert_eq!(annotate(&snps, &intervals, true), [0, 1, 1, 1, 0]);
+        assert_eq!(annotate(&snps, &intervals, false), [0, 1, 2, 1, 0]);
+        let extended = intervals.map(|i| i.extend(1));
+        assert_eq!(annotate(&snps, &extended, true), [1, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn reads_bed_regions() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "regions.bed",
+            "browser position chr1:1-1000\n\
+             track name=test\n\
+             # a comment\n\
+             \n\
+             chr1\t100\t200\tname\t0\t+\n\
+             chrX 5 5\n",
+        );
+        let intervals = read_bed(&path).unwrap();
+        assert_eq!(
+            intervals,
+            [
+                Interval {
+                    chr: 1,
+                    start: 100,
+                    end: 200
+                },
+                Interval {
+                    chr: 23,
+                    start: 5,
+                    end: 5
+                },
+            ]
+        );
+
+        let path = dir.write("reversed.bed", "track name=test\nchr1\t200\t100\n");
+        let err = read_bed(&path).unwrap_err().to_string();
+        assert!(err.ends_with("line 2: end before start."), "{}", err);
+        let path = dir.write("short.bed", "chr1\t200\n");
+        assert!(read_bed(&path).is_err());
+    }
+
+    #[test]
+    fn gene_coordinates_are_one_based() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "coords.txt",
+            "GENE CHR START END\nABC 1 101 200\nXYZ 2 1 50\n",
+        );
+        let coords = read_gene_coords(&path).unwrap();
+        let set = ["ABC".to_string()];
+        let intervals = gene_set_intervals(&set, &coords);
+        assert_eq!(
+            intervals,
+            [Interval {
+                chr: 1,
+                start: 100,
+                end: 200
+            }]
+        );
+        // both ends of the gene are in it
+        let snps = [snp(1, 100), snp(1, 101), snp(1, 200), snp(1, 201)];
+        assert_eq!(annotate(&snps, &intervals, true), [0, 1, 1, 0]);
+        assert_eq!(coords[1].1.start, 0);
+    }
+
+    #[test]
+    fn writes_thin_and_full_annot_files() {
+        let dir = TempDir::new();
+        let mut snps = vec![snp(1, 100), snp(2, 150)];
+        snps[1].cm = 0.5;
+        let read = |path: &str| {
+            get_input_reader(path)
+                .unwrap()
+                .lines()
+                .collect::<std::io::Result<Vec<_>>>()
+                .unwrap()
+        };
+
+        let path = dir.path("thin.annot.gz");
+        write_annot(&path, &snps, &[0, 2], false).unwrap();
+        assert_eq!(read(&path), ["ANNOT", "0", "2"]);
+
+        let path = dir.path("full.annot.gz");
+        write_annot(&path, &snps, &[0, 2], true).unwrap();
+        assert_eq!(
+            read(&path),
+            [
+                "CHR\tBP\tSNP\tCM\tANNOT",
+                "1\t100\trs1_100\t0.0\t0",
+                "2\t150\trs2_150\t0.5\t2"
+            ]
+        );
+    }
+
+    #[test]
+    fn bed_file_and_gene_set_file_conflict() {
+        use crate::cli::Cli;
+        use clap::error::ErrorKind;
+        use clap::Parser;
+
+        let args = ["ldscrs", "make-annot", "--bimfile", "x.bim", "--out", "x"];
+        let parse = |extra: &[&str]| Cli::try_parse_from(args.iter().chain(extra));
+        assert!(parse(&["--bed-file", "x.bed"]).is_ok());
+        assert!(parse(&["--gene-set-file", "genes.txt"]).is_ok());
+        let err = parse(&["--bed-file", "x.bed", "--gene-set-file", "genes.txt"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+        let err = parse(&[]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    }
+}
@@ -1,139 +1,413 @@
-use anyhow::{bail, Result};
-use clap::{ArgAction, Parser};
+use anyhow::{anyhow, bail, Result};
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use log::{info, warn};
+use log::{debug, info, warn};
 use polars::prelude::*;
 use rayon::prelude::*;
-use statrs::distribution::{ChiSquared, ContinuousCDF};
-use std::collections::HashMap;
-use std::env::set_var;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::io::BufRead;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::BufWriter;
 
-use ldscrs::const_value::{DEFAULT_CNAMES, DESCRIBE_CNAME, NULL_VALUES};
-use ldscrs::utils::get_input_reader;
+use crate::cli::{GlobalArgs, MungeArgs};
+use crate::const_value::{DEFAULT_CNAMES, DESCRIBE_CNAME, NULL_VALUES};
+use crate::stats::{z_from_ln_p, QuantileEstimator};
+use crate::utils::{get_input_reader, read_csv, read_header, CsvChunks, Delimiter};
 
-const GROUP: &str = "Column names. NB: case insensitive.";
 const TOLERANCE: f64 = 0.1;
+// number of values kept for the median of the signed sumstats and the 90th percentile of
+// N, which are exact up to here and estimated in bounded memory past it
+const EXACT_QUANTILE_SNPS: usize = 1 << 20;
+// largest difference between effect / SE and the Z from P, relative to |Z| (at least 1),
+// for the two to count as consistent
+const Z_SE_TOLERANCE: f64 = 0.1;
+// sources of P in order of preference: -log10(P), ln(P), P
+const P_CNAMES: [&str; 3] = ["LOG10P", "LN_P", "P"];
+
+/// Options controlling how summary statistics are munged.
+///
+/// Mirrors the flags of `ldscrs munge`; the defaults match the command line defaults.
+#[derive(Debug, Clone)]
+pub struct MungeOptions {
+    pub n: Option<f64>,
+    pub n_cas: Option<f64>,
+    pub n_con: Option<f64>,
+    pub info_min: f64,
+    pub maf_min: f64,
+    pub daner: bool,
+    pub daner_n: bool,
+    pub no_alleles: bool,
+    pub merge_alleles: Option<String>,
+    pub n_min: Option<f64>,
+    pub chisq_max: Option<f64>,
+    pub chunksize: usize,
+    pub snp: Option<String>,
+    pub n_col: Option<String>,
+    pub n_cas_col: Option<String>,
+    pub n_con_col: Option<String>,
+    pub a1: Option<String>,
+    pub a2: Option<String>,
+    pub p: Option<String>,
+    pub frq: Option<String>,
+    pub signed_sumstats: Option<String>,
+    pub info: Option<String>,
+    pub info_list: Option<String>,
+    pub nstudy: Option<String>,
+    pub nstudy_min: Option<f64>,
+    pub ignore: Option<String>,
+    pub a1_inc: bool,
+    pub keep_maf: bool,
+    pub delim: Option<Delimiter>,
+}
 
-#[derive(Parser, Debug)]
-#[command(
-    name = "munge_sumstats",
-    version = "0.1",
-    author = "Wenjie Wei <weiwenjie@westlake.edu.cn>",
-    about = "Munge summary statistics"
-)]
-struct Args {
-    #[arg(long, default_value = None, help = "Input filename.", required = true)]
-    sumstats: String,
+impl Default for MungeOptions {
+    fn default() -> Self {
+        Self {
+            n: None,
+            n_cas: None,
+            n_con: None,
+            info_min: 0.9,
+            maf_min: 0.01,
+            daner: false,
+            daner_n: false,
+            no_alleles: false,
+            merge_alleles: None,
+            n_min: None,
+            chisq_max: None,
+            chunksize: 5e6 as usize,
+            snp: None,
+            n_col: None,
+            n_cas_col: None,
+            n_con_col: None,
+            a1: None,
+            a2: None,
+            p: None,
+            frq: None,
+            signed_sumstats: None,
+            info: None,
+            info_list: None,
+            nstudy: None,
+            nstudy_min: None,
+            ignore: None,
+            a1_inc: false,
+            keep_maf: false,
+            delim: None,
+        }
+    }
+}
 
-    #[arg(long="N", default_value = None, help = "Sample size. If this option is not set, will try to infer the sample size from the input file. If the input file contains a sample size column, and this flag is set, the argument to this flag has priority.")]
-    n: Option<f64>,
+impl MungeOptions {
+    /// Sample size, overriding any N column.
+    pub fn with_n(mut self, n: f64) -> Self {
+        self.n = Some(n);
+        self
+    }
 
-    #[arg(long="N-cas", default_value = None, help = "Number of cases. If this option is not set, will try to infer the number of cases from the input file. If the input file contains a number of cases column, and this flag is set, the argument to this flag has priority.")]
-    n_cas: Option<f64>,
+    /// Number of cases and controls, overriding any N_CAS/N_CON columns.
+    pub fn with_n_cas_con(mut self, n_cas: f64, n_con: f64) -> Self {
+        self.n_cas = Some(n_cas);
+        self.n_con = Some(n_con);
+        self
+    }
 
-    #[arg(long="N-con", default_value = None, help = "Number of controls. If this option is not set, will try to infer the number of controls from the input file. If the input file contains a number of controls column, and this flag is set, the argument to this flag has priority.")]
-    n_con: Option<f64>,
+    /// Minimum INFO score.
+    pub fn with_info_min(mut self, info_min: f64) -> Self {
+        self.info_min = info_min;
+        self
+    }
 
-    #[arg(long, default_value = None, help = "Output filename prefix.", required = true)]
-    out: String,
+    /// Minimum MAF.
+    pub fn with_maf_min(mut self, maf_min: f64) -> Self {
+        self.maf_min = maf_min;
+        self
+    }
 
-    #[arg(long, default_value_t = 0.9, help = "Minimum INFO score.")]
-    info_min: f64,
+    /// Parse Stephan Ripke's daner* file format.
+    pub fn with_daner(mut self, daner: bool) -> Self {
+        self.daner = daner;
+        self
+    }
 
-    #[arg(long, default_value_t = 0.01, help = "Minimum MAF.")]
-    maf_min: f64,
+    /// Parse daner* files with per-SNP 'Nca' and 'Nco' columns.
+    pub fn with_daner_n(mut self, daner_n: bool) -> Self {
+        self.daner_n = daner_n;
+        self
+    }
 
-    #[arg(long, action = ArgAction::SetTrue, help = "Use this flag to parse Stephan Ripke's daner* file format.", conflicts_with = "daner_n")]
-    daner: bool,
+    /// Don't require alleles.
+    pub fn with_no_alleles(mut self, no_alleles: bool) -> Self {
+        self.no_alleles = no_alleles;
+        self
+    }
 
-    #[arg(long, action = ArgAction::SetTrue, help = "Use this flag to parse more recent daner* formatted files, which include sample size column 'Nca' and 'Nco'." ,conflicts_with = "daner")]
-    daner_n: bool,
+    /// SNP, A1, A2 file that all alleles will be matched to.
+    pub fn with_merge_alleles(mut self, path: Option<String>) -> Self {
+        self.merge_alleles = path;
+        self
+    }
 
-    #[arg(long, action = ArgAction::SetTrue, help = "Don't require alleles. Useful if only unsigned summary statistics are available and the goal is h2 / partitioned h2 estimation rather than rg estimation.", conflicts_with = "merge_alleles")]
-    no_alleles: bool,
+    /// Minimum N. Default is (90th percentile N) / 1.5.
+    pub fn with_n_min(mut self, n_min: Option<f64>) -> Self {
+        self.n_min = n_min;
+        self
+    }
 
-    #[arg(long, default_value = None, help = "Same as --merge, except the file should have three columns: SNP, A1, A2, and all alleles will be matched to the --merge-alleles file alleles.", conflicts_with = "no_alleles")]
-    merge_alleles: Option<String>,
+    /// Maximum chi^2. Default is to keep every SNP.
+    pub fn with_chisq_max(mut self, chisq_max: Option<f64>) -> Self {
+        self.chisq_max = chisq_max;
+        self
+    }
 
-    #[arg(long, default_value = None, help = "Minimum N (sample size). Default is (90th percentile N) / 2.")]
-    n_min: Option<f64>,
+    pub fn with_chunksize(mut self, chunksize: usize) -> Self {
+        self.chunksize = chunksize;
+        self
+    }
 
-    #[arg(long, default_value_t = 5e6 as usize, help = "Chunksize.")]
-    chunksize: usize,
+    /// Names of nonstandard columns, indexed by their internal name
+    /// (SNP, N, N_CAS, N_CON, A1, A2, P, FRQ, INFO, NSTUDY).
+    pub fn with_column(mut self, internal: &str, name: &str) -> Result<Self> {
+        let name = Some(name.to_string());
+        match internal {
+            "SNP" => self.snp = name,
+            "N" => self.n_col = name,
+            "N_CAS" => self.n_cas_col = name,
+            "N_CON" => self.n_con_col = name,
+            "A1" => self.a1 = name,
+            "A2" => self.a2 = name,
+            "P" => self.p = name,
+            "FRQ" => self.frq = name,
+            "INFO" => self.info = name,
+            "NSTUDY" => self.nstudy = name,
+            _ => bail!("Unknown internal column name: {}", internal),
+        }
+        Ok(self)
+    }
 
-    #[arg(long, default_value = None, help = "Name of SNP column (if not a name that ldsc understands). ")]
-    snp: Option<String>,
+    /// Signed sumstat column and its null value, e.g. `("OR", 1.0)`.
+    pub fn with_signed_sumstats(mut self, name: &str, null_value: f64) -> Self {
+        self.signed_sumstats = Some(format!("{},{}", name, null_value));
+        self
+    }
 
-    #[arg(long="N-col", default_value = None, help = "Name of N column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
-    n_col: Option<String>,
+    /// INFO columns to filter on the mean of.
+    pub fn with_info_list(mut self, cols: &[&str]) -> Self {
+        self.info_list = Some(cols.join(","));
+        self
+    }
 
-    #[arg(long="N-cas-col", default_value = None, help = "Name of N column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
-    n_cas_col: Option<String>,
+    /// Minimum # of studies.
+    pub fn with_nstudy_min(mut self, nstudy_min: Option<f64>) -> Self {
+        self.nstudy_min = nstudy_min;
+        self
+    }
 
-    #[arg(long="N-con-col", default_value = None, help = "Name of N column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
-    n_con_col: Option<String>,
+    /// Column names to ignore.
+    pub fn with_ignore(mut self, cols: &[&str]) -> Self {
+        self.ignore = Some(cols.join(","));
+        self
+    }
 
-    #[arg(long, default_value = None, help = "Name of A1 column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
-    a1: Option<String>,
+    /// A1 is the increasing allele.
+    pub fn with_a1_inc(mut self, a1_inc: bool) -> Self {
+        self.a1_inc = a1_inc;
+        self
+    }
 
-    #[arg(long, default_value = None, help = "Name of A2 column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
-    a2: Option<String>,
+    /// Keep the MAF column (if one exists).
+    pub fn with_keep_maf(mut self, keep_maf: bool) -> Self {
+        self.keep_maf = keep_maf;
+        self
+    }
 
-    #[arg(long, default_value = None, help = "Name of p-value column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
-    p: Option<String>,
+    /// Delimiter of the sumstats file, guessed from its first lines if not set.
+    pub fn with_delim(mut self, delim: Option<Delimiter>) -> Self {
+        self.delim = delim;
+        self
+    }
+}
 
-    #[arg(long, default_value = None, help = "Name of FRQ or MAF column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
-    frq: Option<String>,
+impl From<&MungeArgs> for MungeOptions {
+    fn from(args: &MungeArgs) -> Self {
+        Self {
+            n: args.n,
+            n_cas: args.n_cas,
+            n_con: args.n_con,
+            info_min: args.info_min,
+            maf_min: args.maf_min,
+            daner: args.daner,
+            daner_n: args.daner_n,
+            no_alleles: args.no_alleles,
+            merge_alleles: args.merge_alleles.clone(),
+            n_min: args.n_min,
+            chisq_max: args.chisq_max,
+            chunksize: args.chunksize,
+            snp: args.snp.clone(),
+            n_col: args.n_col.clone(),
+            n_cas_col: args.n_cas_col.clone(),
+            n_con_col: args.n_con_col.clone(),
+            a1: args.a1.clone(),
+            a2: args.a2.clone(),
+            p: args.p.clone(),
+            frq: args.frq.clone(),
+            signed_sumstats: args.signed_sumstats.clone(),
+            info: args.info.clone(),
+            info_list: args.info_list.clone(),
+            nstudy: args.nstudy.clone(),
+            nstudy_min: args.nstudy_min,
+            ignore: args.ignore.clone(),
+            a1_inc: args.a1_inc,
+            keep_maf: args.keep_maf,
+            delim: args.delim,
+        }
+    }
+}
 
-    #[arg(long, default_value = None, help = "Name of signed sumstat column, comma null value (e.g., Z,0 or OR,1). ", help_heading=Some(GROUP))]
-    signed_sumstats: Option<String>,
+/// Number of SNPs removed by each munging filter.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FilterReport {
+    /// SNPs read from the input file.
+    pub read: usize,
+    /// SNPs with missing values.
+    pub na: usize,
+    /// SNPs not in --merge-alleles.
+    pub merge: usize,
+    /// SNPs with INFO below --info-min.
+    pub info: usize,
+    /// SNPs with MAF below --maf-min.
+    pub frq: usize,
+    /// SNPs with out-of-bounds p-values.
+    pub p: usize,
+    /// Variants that were not SNPs or were strand-ambiguous.
+    pub alleles: usize,
+    /// SNPs with duplicated rs numbers.
+    pub duplicates: usize,
+    /// SNPs with N (or NSTUDY) below the minimum.
+    pub n: usize,
+    /// SNPs with chi^2 at or above --chisq-max.
+    pub chisq: usize,
+    /// SNPs whose alleles did not match --merge-alleles.
+    pub merge_alleles_mismatch: usize,
+    /// SNPs in the munged output.
+    pub remaining: usize,
+}
 
-    #[arg(long, default_value = None, help = "Name of INFO column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
-    info: Option<String>,
+/// Result of checking the median of the signed summary statistic against its null value.
+#[derive(Debug, Clone, Serialize)]
+pub struct MedianCheck {
+    /// Input column holding the signed summary statistic.
+    pub column: String,
+    pub median: f64,
+    /// Null value the median should be close to, e.g. 0 for BETA or 1 for OR.
+    pub expected: f64,
+    /// Whether the median is within the tolerance of the null value.
+    pub sensible: bool,
+}
 
-    #[arg(long, default_value = None, help = "Comma-separated list of INFO columns. Will filter on the mean. ", help_heading=Some(GROUP))]
-    info_list: Option<String>,
+/// QC summary of a munging run, written as `<out>.munge.json`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MungeReport {
+    /// Input column name to the internal column name it was read as.
+    pub column_translation: BTreeMap<String, String>,
+    /// Input column name to the description of its internal column.
+    pub column_description: BTreeMap<String, String>,
+    pub filters: FilterReport,
+    /// `None` with --a1-inc, where there is no signed summary statistic.
+    pub median_check: Option<MedianCheck>,
+}
 
-    #[arg(long, default_value = None, help = "Name of NSTUDY column (if not a name that ldsc understands). ", help_heading=Some(GROUP))]
-    nstudy: Option<String>,
+/// Munged summary statistics, ready to be written as `.sumstats.gz`.
+#[derive(Debug, Clone)]
+pub struct MungedSumstats {
+    /// SNP, A1, A2, N, Z (and FRQ with `keep_maf`).
+    pub data: DataFrame,
+    pub report: MungeReport,
+}
 
-    #[arg(long, default_value = None, help = "Minimum # of studies. Default is to remove everything below the max, unless there is an N column, in which case do nothing.", help_heading=Some(GROUP))]
-    nstudy_min: Option<f64>,
+/// Entry point of `ldscrs munge`.
+pub fn run(args: &MungeArgs, global: &GlobalArgs) -> Result<()> {
+    let opts = MungeOptions::from(args);
 
-    #[arg(long, default_value = None, help = "Comma-separated list of column names to ignore.", help_heading=Some(GROUP))]
-    ignore: Option<String>,
+    // write each chunk to file as soon as it is munged
+    let out_fname = format!("{}.sumstats.gz", global.out);
+    let outfile = BufWriter::new(File::create(&out_fname)?);
+    let mut gzip_encoder = GzEncoder::new(outfile, Compression::default());
+    let mut final_len = 0;
+    let mut nomiss_len = 0;
+    let report = munge_with(&args.sumstats, &opts, |mut dat| {
+        CsvWriter::new(&mut gzip_encoder)
+            .include_header(final_len == 0)
+            .n_threads(rayon::current_num_threads())
+            .with_separator(b'\t')
+            .with_null_value("".to_owned())
+            .with_float_precision(Some(3))
+            .finish(&mut dat)?;
+        final_len += dat.height();
+        nomiss_len += dat.height() - dat.column("Z")?.null_count();
+        Ok(())
+    })?;
+    gzip_encoder.finish()?;
+    info!(
+        "Wrote summary statistics for {} SNPs ({} with nonmissing Z) to {}.",
+        final_len, nomiss_len, out_fname
+    );
 
-    #[arg(long, action = ArgAction::SetTrue, help = "A1 is the increasing allele.", help_heading=Some(GROUP))]
-    a1_inc: bool,
+    let report_fname = format!("{}.munge.json", global.out);
+    info!("Writing munging report to {}.", report_fname);
+    let writer = BufWriter::new(File::create(report_fname)?);
+    serde_json::to_writer_pretty(writer, &report)?;
 
-    #[arg(long, action = ArgAction::SetTrue, help = "Keep the MAF column (if one exists).", help_heading=Some(GROUP))]
-    keep_maf: bool,
+    Ok(())
 }
-fn main() -> Result<()> {
-    let args = Args::parse();
 
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(8)
-        .build_global()?;
+/// Munge the summary statistics in `source` according to `opts`, holding the munged
+/// SNPs in memory. [`munge_with`] hands them over chunk by chunk instead.
+pub fn munge(source: &str, opts: &MungeOptions) -> Result<MungedSumstats> {
+    let mut data: Option<DataFrame> = None;
+    let report = munge_with(source, opts, |chunk| {
+        match data.as_mut() {
+            Some(data) => {
+                data.vstack_mut(&chunk)?;
+            }
+            None => data = Some(chunk),
+        }
+        Ok(())
+    })?;
+    let mut data = data.ok_or_else(|| anyhow!("No munged SNPs were emitted."))?;
+    data.as_single_chunk_par();
+    Ok(MungedSumstats { data, report })
+}
 
-    let start = std::time::Instant::now();
-    // Initialize logger
-    init_logger(&args)?;
+/// Munge the summary statistics in `source` according to `opts`, passing the munged
+/// SNPs to `emit` one chunk of at most `opts.chunksize` SNPs at a time.
+///
+/// Besides the current chunk, only a 64-bit hash of each rs number kept so far (to drop
+/// duplicates) grows with the input; the median check and the default --n-min are
+/// computed from [`QuantileEstimator`]s of bounded size. `source` is read twice when the
+/// N thresholds have to be computed from the whole file. With --merge-alleles the output
+/// follows the order of the --merge-alleles file, so it is held until the end and
+/// emitted as one chunk, which has one row per --merge-alleles SNP and is thus bounded by
+/// that file rather than by `source`. `emit` is called at least once, if need be with an
+/// empty chunk.
+pub fn munge_with(
+    source: &str,
+    opts: &MungeOptions,
+    mut emit: impl FnMut(DataFrame) -> Result<()>,
+) -> Result<MungeReport> {
+    let mut report = FilterReport::default();
 
     // get colnames
-    let colnames = get_file_colnames(&args.sumstats)?;
+    let (colnames, delim) = read_header(source, opts.delim)?;
     info!("Column names: {:?}", colnames);
+    info!("Delimiter: {:?}", delim);
 
     // get flag_names and null_value
-    let (flag_cnames, signed_sumstst_null) = parse_flag_colnames(&args)?;
+    let (flag_cnames, signed_sumstst_null) = parse_flag_colnames(opts)?;
     info!("Flag column names: {:?}", flag_cnames);
     info!("Null value: {:?}", signed_sumstst_null);
 
     // ingore columns
-    let ignore_cnames = match &args.ignore {
+    let ignore_cnames = match &opts.ignore {
         Some(ignore) => ignore.split(',').map(clean_header).collect::<Vec<_>>(),
         None => vec![],
     };
@@ -143,7 +417,7 @@ fn main() -> Result<()> {
     let mod_default_cnames: HashMap<&str, &str> = DEFAULT_CNAMES
         .into_iter()
         .filter(|&(_, v)| {
-            if args.signed_sumstats.is_some() || args.a1_inc {
+            if opts.signed_sumstats.is_some() || opts.a1_inc {
                 !NULL_VALUES.contains_key(v)
             } else {
                 true
@@ -151,20 +425,18 @@ fn main() -> Result<()> {
         })
         .map(|(k, v)| (*k, *v))
         .collect();
-    info!("Modified default column names: {:?}", mod_default_cnames);
+    debug!("Modified default column names: {:?}", mod_default_cnames);
 
     // get colnames map
-    let cname_map = get_cname_map(flag_cnames, mod_default_cnames, ignore_cnames);
-    info!("Column name map: {:?}", cname_map);
+    let mut cname_map = get_cname_map(flag_cnames, mod_default_cnames, ignore_cnames);
+    debug!("Column name map: {:?}", cname_map);
 
-    // if daner or daner_n
-    // TODO: daner
-    if args.daner {
-        todo!();
-    }
-    if args.daner_n {
-        todo!();
+    // daner files keep N_cas/N_con in the FRQ_A_*/FRQ_U_* headers or in Nca/Nco columns
+    let mut opts = opts.clone();
+    if opts.daner || opts.daner_n {
+        parse_daner(&colnames, &mut cname_map, &mut opts)?;
     }
+    let opts = &opts;
 
     let mut cname_translation = colnames
         .iter()
@@ -185,47 +457,79 @@ fn main() -> Result<()> {
         .collect::<HashMap<_, _>>();
     info!("Column name description: {:?}", cname_description);
 
-    let (sign_cname, signed_sumstst_null) = if args.signed_sumstats.is_none() && !args.a1_inc {
-        let sign_cnames: Vec<_> = cname_translation
-            .iter()
-            .filter(|(_, v)| NULL_VALUES.contains_key(v))
-            .map(|(k, _)| *k)
-            .collect();
-        match sign_cnames.len() {
-            0 => bail!("Could not find a signed summary statistic column."),
-            1 => {
-                let cname = sign_cnames[0];
-                let signed_sumstst_null =
-                    Some(*NULL_VALUES.get(cname_translation[&cname].as_str()).unwrap() as f64);
-                cname_translation.insert(cname, "SIGNED_SUMSTAT".to_string());
-                (cname, signed_sumstst_null)
-            }
-            _ => bail!(
+    let (sign_cname, signed_sumstst_null, sign_type) =
+        if opts.signed_sumstats.is_none() && !opts.a1_inc {
+            let sign_cnames: Vec<_> = cname_translation
+                .iter()
+                .filter(|(_, v)| NULL_VALUES.contains_key(v))
+                .map(|(k, _)| *k)
+                .collect();
+            match sign_cnames.len() {
+                0 => bail!("Could not find a signed summary statistic column."),
+                1 => {
+                    let cname = sign_cnames[0];
+                    let sign_type = cname_translation[&cname].clone();
+                    let signed_sumstst_null =
+                        Some(*NULL_VALUES.get(sign_type.as_str()).unwrap() as f64);
+                    cname_translation.insert(cname, "SIGNED_SUMSTAT".to_string());
+                    (cname.to_string(), signed_sumstst_null, Some(sign_type))
+                }
+                _ => bail!(
                 "Too many signed sumstat columns. Specify which to ignore with the --ignore flag."
             ),
-        }
-    } else {
-        (&"SIGNED_SUMSTATS".to_string(), signed_sumstst_null)
-    };
+            }
+        } else {
+            ("SIGNED_SUMSTATS".to_string(), signed_sumstst_null, None)
+        };
     info!("Signed column name: {:?}", sign_cname);
     info!("Signed column null value: {:?}", signed_sumstst_null);
 
+    // Z is computed as effect / SE when the signed sumstat is an effect size with a SE
+    let has_se = cname_translation.values().any(|v| v == "SE");
+    let z_from_se = match sign_type.as_deref() {
+        Some(t @ ("BETA" | "OR" | "LOG_ODDS")) if has_se => Some(t.to_string()),
+        _ => None,
+    };
+    if z_from_se.is_none() {
+        cname_translation.retain(|_, v| v != "SE");
+    }
+    match &z_from_se {
+        Some(t) => info!(
+            "Computing Z from {} / SE; P is used where SE is missing.",
+            t
+        ),
+        None => info!("Computing Z from P."),
+    }
+
     //check that we have all the columns we need
-    if !args.a1_inc {
-        let req_cols = vec!["SNP", "P", "SIGNED_SUMSTAT"];
+    if !opts.a1_inc {
+        let req_cols = vec!["SNP", "SIGNED_SUMSTAT"];
         for c in req_cols {
             if !&cname_translation.values().any(|v| v == c) {
                 bail!("Could not find {} column.", c);
             }
         }
     } else {
-        let req_cols = vec!["SNP", "P"];
+        let req_cols = vec!["SNP"];
         for c in req_cols {
             if !&cname_translation.values().any(|v| v == c) {
                 bail!("Could not find {} column.", c);
             }
         }
     }
+    // P can also come on a log scale, which is preferred when present
+    let p_cols = cname_translation
+        .values()
+        .filter(|v| P_CNAMES.contains(&v.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    let Some(p_source) = P_CNAMES.into_iter().find(|c| p_cols.iter().any(|v| v == c)) else {
+        bail!("Could not find P column.");
+    };
+    if p_cols.len() > 1 {
+        info!("Using {} as the source of P.", p_source);
+        cname_translation.retain(|_, v| !P_CNAMES.contains(&v.as_str()) || v == p_source);
+    }
 
     //check aren't any duplicate column names in mapping
     for field in cname_translation.keys() {
@@ -243,8 +547,8 @@ fn main() -> Result<()> {
         }
     }
 
-    if args.n.is_none()
-        && (args.n_cas.is_none() || args.n_con.is_none())
+    if opts.n.is_none()
+        && (opts.n_cas.is_none() || opts.n_con.is_none())
         && !(cname_translation.values().any(|v| v == "N")
             || ["N_CAS", "N_CON"]
                 .iter()
@@ -269,7 +573,7 @@ fn main() -> Result<()> {
         }
     }
 
-    if !args.no_alleles
+    if !opts.no_alleles
         && !["A1", "A2"]
             .iter()
             .all(|x| cname_translation.values().any(|v| v == *x))
@@ -282,7 +586,7 @@ fn main() -> Result<()> {
         info!("{}:\t{}", x, desc);
     }
 
-    let merge_alleles_df = if let Some(ma_path) = &args.merge_alleles {
+    let merge_alleles_df = if let Some(ma_path) = &opts.merge_alleles {
         Some(get_merge_allels_df(ma_path)?)
     } else {
         None
@@ -291,66 +595,200 @@ fn main() -> Result<()> {
 
     // Start read sumstats
     //  figure out which columns are going to involve sign information, so we can ensure they're read as floats
-
     let signed_sumstats_cols = cname_translation
         .iter()
         .filter(|(_, v)| *v == "SIGNED_SUMSTAT")
         .map(|(k, _)| *k)
         .collect::<Vec<_>>();
     info!("Signed sumstats columns: {:?}", signed_sumstats_cols);
-    let mut sign_schema = Schema::default();
-    for c in &signed_sumstats_cols {
-        sign_schema.with_column(c.as_str().into(), DataType::Float64);
-    }
-    // info!("Signed sumstats schema: {:?}", sign_schema);
 
-    // Temporary for reading N, due to some N looks like 7e05 but it's a i64
-    sign_schema.with_column("N".into(), DataType::Float64);
+    // every chunk must be parsed with the same dtypes, so don't let polars infer them
+    let mut schema = Schema::default();
+    for (c, v) in &cname_translation {
+        let dtype = match v.as_str() {
+            "SNP" | "A1" | "A2" => DataType::String,
+            // read sample sizes as floats, due to some N looks like 7e05 but it's a i64
+            _ => DataType::Float64,
+        };
+        schema.with_column(c.as_str().into(), dtype);
+    }
 
     let parse_opts = CsvParseOptions::default()
-        .with_separator(b'\t')
         .with_null_values(Some(NullValues::AllColumns(vec![".".into(), "NA".into()])));
-    let sumstats_path = args.sumstats.clone();
-    let mut sumspd = CsvReadOptions::default()
+    let read_opts = CsvReadOptions::default()
         .with_parse_options(parse_opts)
-        .with_has_header(true)
         .with_columns(Some(
             cname_translation
                 .keys()
                 .map(|x| x.as_str().into())
                 .collect(),
         ))
-        // .with_ignore_errors(true)
-        .with_schema_overwrite(Some(sign_schema.into()))
-        .with_chunk_size(args.chunksize)
-        .try_into_reader_with_file_path(Some(sumstats_path.into()))?
-        .finish()?;
-    // trans N col to i64
-    sumspd = sumspd
-        .clone()
-        .lazy()
-        .with_column(col("N").cast(DataType::Int64).alias("N"))
-        .collect()?;
-
-    let dat = parse_dat(sumspd, cname_translation, &merge_alleles_df, &args)?;
-    let mut dat = process_n(dat, &args)?;
-    // trans p to z
-    let p_col = dat.column("P")?.f64()?;
-    let chi2 = ChiSquared::new(1.0)?;
-    // calculate
-    let z_values: Vec<f64> = p_col
-        .into_no_null_iter()
-        .par_bridge()
-        .map(|p_val| chi2.inverse_cdf(1.0 - p_val).sqrt())
+        .with_schema_overwrite(Some(schema.into()));
+    info!(
+        "Reading sumstats from {} into memory {} SNPs at a time.",
+        source, opts.chunksize
+    );
+    let cname_translation: HashMap<String, String> = cname_translation
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
         .collect();
-    let z_series = Series::new("Z".into(), z_values);
-    dat.with_column(z_series)?;
-    // drop p
-    dat.drop_in_place("P")?;
+    let has_col = |c: &str| cname_translation.values().any(|v| v == c);
+    let read_chunks = || {
+        CsvChunks::new(
+            get_input_reader(source)?,
+            read_opts.clone(),
+            opts.chunksize,
+            delim,
+        )
+    };
+
+    // the default N thresholds depend on every SNP that passes the per-SNP filters, so
+    // they are computed in a first pass that keeps only the sample size columns
+    let has_n = has_col("N") || (has_col("N_CAS") && has_col("N_CON"));
+    let has_nstudy = !has_n && has_col("NSTUDY");
+    let n_cols: &[&str] = if has_col("N_CAS") && has_col("N_CON") {
+        &["N_CAS", "N_CON"]
+    } else if has_n && opts.n_min.is_none() {
+        &["N"]
+    } else if has_nstudy && opts.nstudy_min.is_none() {
+        &["NSTUDY"]
+    } else {
+        &[]
+    };
+    let n_summary = if n_cols.is_empty() {
+        None
+    } else {
+        info!(
+            "Reading {} first to determine the N thresholds.",
+            n_cols.join(", ")
+        );
+        let mut row_filter = RowFilter::new(
+            &cname_translation,
+            &merge_alleles_df,
+            z_from_se.as_deref(),
+            opts,
+        )?;
+        let mut first_report = FilterReport::default();
+        let mut n_summary = NSummary::default();
+        for dat in read_chunks()? {
+            let dat = row_filter.apply(dat?, &mut first_report)?;
+            n_summary.add(&dat.select(n_cols.iter().copied())?)?;
+        }
+        // there are no thresholds to compute if every SNP was filtered out
+        if n_summary.snps == 0 {
+            row_filter.log(&first_report);
+            bail!("After applying filters, no SNPs remain.");
+        }
+        Some(n_summary)
+    };
+    let thresholds = NThresholds::new(n_summary, has_n, has_nstudy, opts);
+
+    let mut row_filter = RowFilter::new(
+        &cname_translation,
+        &merge_alleles_df,
+        z_from_se.as_deref(),
+        opts,
+    )?;
+    let mut kept_len = 0;
+    let mut median_sign = QuantileEstimator::new(0.5, EXACT_QUANTILE_SNPS);
+    let mut z_counts = ZFromEffectCounts::default();
+    let mut merged: Option<DataFrame> = None;
+    let mut last_empty = None;
+    for dat in read_chunks()? {
+        let dat = row_filter.apply(dat?, &mut report)?;
+        kept_len += dat.height();
+        if dat.height() == 0 {
+            continue;
+        }
+        let mut dat = process_n(dat, opts, &thresholds, &mut report)?;
+
+        // trans p to z, in log space so that tiny p-values keep their precision
+        let ln_p_col = dat.column("LN_P")?.f64()?;
+        // calculate, keeping the row order (par_bridge would not)
+        let z_values: Vec<f64> = ln_p_col
+            .into_no_null_iter()
+            .collect::<Vec<_>>()
+            .par_iter()
+            .map(|ln_p| z_from_ln_p(*ln_p))
+            .collect();
+        let z_series = Series::new("Z".into(), z_values);
+        dat.with_column(z_series)?;
+        // drop p
+        dat.drop_in_place("LN_P")?;
+
+        if !opts.a1_inc {
+            let signed_sumstat = dat.column("SIGNED_SUMSTAT")?.f64()?;
+            signed_sumstat
+                .into_no_null_iter()
+                .for_each(|x| median_sign.add(x));
+
+            // dat.Z *= (-1) ** (dat.SIGNED_SUMSTAT < signed_sumstat_null)
+            let z = dat.column("Z")?.f64()?;
+            let z_values: Vec<f64> = signed_sumstat
+                .into_iter()
+                .zip(z)
+                .map(|(signed, z)| match (signed, z) {
+                    (Some(signed), Some(z)) if signed < signed_sumstst_null.unwrap() => -z,
+                    (Some(_), Some(z)) => z,
+                    _ => f64::NAN,
+                })
+                .collect();
+            dat.with_column(Series::new("Z".into(), z_values))?;
+
+            if let Some(sign_type) = &z_from_se {
+                z_from_effect(&mut dat, sign_type, &mut z_counts)?;
+                dat.drop_in_place("SE")?;
+            }
+            dat.drop_in_place("SIGNED_SUMSTAT")?;
+        }
+
+        if let Some(chisq_max) = opts.chisq_max {
+            let old_count = dat.height();
+            dat = dat
+                .lazy()
+                .filter((col("Z") * col("Z")).lt(lit(chisq_max)))
+                .collect()?;
+            report.chisq += old_count - dat.height();
+        }
+
+        // the output follows --merge-alleles, so it can only be written once complete
+        if let Some(merge_alleles_df) = &merge_alleles_df {
+            let dat = match_merge_alleles(dat, merge_alleles_df, &mut report)?;
+            match merged.as_mut() {
+                Some(merged) => {
+                    merged.vstack_mut(&dat)?;
+                }
+                None => merged = Some(dat),
+            }
+            continue;
+        }
 
-    if !args.a1_inc {
-        let median_sign = dat.column("SIGNED_SUMSTAT")?.f64()?.median().unwrap();
+        let dat = select_output(dat, opts)?;
+        if dat.height() == 0 {
+            last_empty = Some(dat);
+            continue;
+        }
+        report.remaining += dat.height();
+        emit(dat)?;
+    }
+
+    row_filter.log(&report);
+    if kept_len == 0 {
+        bail!("After applying filters, no SNPs remain.");
+    }
+    let mut remaining = kept_len;
+    thresholds.log(opts, &report, remaining);
+    remaining -= report.n;
+
+    let mut median_check = None;
+    if let Some(median_sign) = median_sign.quantile() {
         let diff = (median_sign - signed_sumstst_null.unwrap()).abs();
+        median_check = Some(MedianCheck {
+            column: sign_cname.clone(),
+            median: median_sign,
+            expected: signed_sumstst_null.unwrap(),
+            sensible: diff <= TOLERANCE,
+        });
         if diff > TOLERANCE {
             warn!(
                 "WARNING: median value of {} is {} (should be close to {}). This column may be mislabeled.",
@@ -362,100 +800,200 @@ fn main() -> Result<()> {
                 sign_cname, median_sign
             );
         }
-
-        // dat.Z *= (-1) ** (dat.SIGNED_SUMSTAT < signed_sumstat_null)
-        let signed_sumstat = dat.column("SIGNED_SUMSTAT")?.f64()?;
-        let z = dat.column("Z")?.f64()?;
-        let z_values: Vec<f64> = signed_sumstat
-            .into_iter()
-            .zip(z)
-            .map(|(signed, z)| match (signed, z) {
-                (Some(signed), Some(z)) if signed < signed_sumstst_null.unwrap() => -z,
-                (Some(_), Some(z)) => z,
-                _ => f64::NAN,
-            })
-            .collect();
-        dat.with_column(Series::new("Z".into(), z_values))?;
-        dat.drop_in_place("SIGNED_SUMSTAT")?;
+    }
+    if let Some(sign_type) = &z_from_se {
+        z_counts.log(sign_type);
     }
 
-    if args.merge_alleles.is_some() {
-        // compare A1+A2 to MA
-        let valid_alleles = Series::new(
-            "valid_alleles".into(),
-            [
-                "GTAC", "ACAC", "ACGT", "GTTG", "CTAG", "CTCT", "ACCA", "CTTC", "AGTC", "GTGT",
-                "GTCA", "AGGA", "GACT", "GAGA", "GAAG", "AGCT", "GATC", "CAAC", "CAGT", "TGCA",
-                "CACA", "TGAC", "AGAG", "CATG", "TCCT", "TCGA", "TGTG", "TGGT", "CTGA", "TCAG",
-                "TCTC", "ACTG",
-            ],
+    if let Some(chisq_max) = opts.chisq_max {
+        remaining -= report.chisq;
+        info!(
+            "Removed {} SNPs with chi^2 >= {} ({} SNPs remain).",
+            report.chisq, chisq_max, remaining
         );
-        dat = dat
-            .clone()
-            .lazy()
-            .with_column(concat_str([col("A1"), col("A2"), col("MA")], "", false).alias("tmp_MA"))
-            .collect()?;
-        let origin_len = dat.height();
-        dat = dat
-            .clone()
-            .lazy()
-            .filter(col("tmp_MA").is_in(lit(valid_alleles)))
-            .collect()?;
-        let clean_len = dat.height();
+    }
+
+    if let Some(merge_alleles_df) = &merge_alleles_df {
+        remaining -= report.merge_alleles_mismatch;
         info!(
             "Removed {} SNPs whose alleles did not match --merge-alleles ({} SNPs remain).",
-            origin_len - clean_len,
-            clean_len
+            report.merge_alleles_mismatch, remaining
         );
-        dat.drop_in_place("tmp_MA")?;
-        dat = dat
-            .clone()
+        let dat = merged
+            .ok_or_else(|| anyhow!("No SNPs were munged."))?
             .lazy()
             .join(
-                merge_alleles_df.clone().unwrap().lazy(),
+                merge_alleles_df.clone().lazy(),
                 [col("SNP")],
                 [col("SNP")],
                 JoinArgs::new(JoinType::Right).with_coalesce(JoinCoalesce::CoalesceColumns),
             )
             .collect()?;
+        let dat = select_output(dat, opts)?;
+        report.remaining = dat.height();
+        emit(dat)?;
+    } else if report.remaining == 0 {
+        if let Some(dat) = last_empty {
+            emit(dat)?;
+        }
     }
 
-    let out_fname = format!("{}.sumstats.gz", args.out);
+    let column_translation = cname_translation.into_iter().collect();
+    let report = MungeReport {
+        column_translation,
+        column_description: cname_description
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        filters: report,
+        median_check,
+    };
+    Ok(report)
+}
+
+/// Drop the SNPs whose alleles do not match --merge-alleles (allowing for strand flips
+/// and swapped alleles).
+fn match_merge_alleles(
+    dat: DataFrame,
+    merge_alleles_df: &DataFrame,
+    report: &mut FilterReport,
+) -> Result<DataFrame> {
+    // compare A1+A2 to MA
+    let valid_alleles = Series::new(
+        "valid_alleles".into(),
+        [
+            "GTAC", "ACAC", "ACGT", "GTTG", "CTAG", "CTCT", "ACCA", "CTTC", "AGTC", "GTGT", "GTCA",
+            "AGGA", "GACT", "GAGA", "GAAG", "AGCT", "GATC", "CAAC", "CAGT", "TGCA", "CACA", "TGAC",
+            "AGAG", "CATG", "TCCT", "TCGA", "TGTG", "TGGT", "CTGA", "TCAG", "TCTC", "ACTG",
+        ],
+    );
+    let mut dat = dat
+        .lazy()
+        .join(
+            merge_alleles_df.clone().lazy(),
+            [col("SNP")],
+            [col("SNP")],
+            JoinArgs::new(JoinType::Left),
+        )
+        .with_column(concat_str([col("A1"), col("A2"), col("MA")], "", false).alias("tmp_MA"))
+        .collect()?;
+    let origin_len = dat.height();
+    dat = dat
+        .lazy()
+        .filter(col("tmp_MA").is_in(lit(valid_alleles)))
+        .collect()?;
+    report.merge_alleles_mismatch += origin_len - dat.height();
+    dat.drop_in_place("tmp_MA")?;
+    dat.drop_in_place("MA")?;
+    Ok(dat)
+}
 
+// Keep the columns written to .sumstats.gz.
+fn select_output(dat: DataFrame, opts: &MungeOptions) -> Result<DataFrame> {
     let mut print_colnames = dat
         .get_column_names()
         .iter()
         .map(|x| x.as_str())
-        // in ['SNP', 'N', 'Z', 'A1', 'A2']
-        .filter(|c| ["SNP", "N", "Z", "A1", "A2", "FRQ"].contains(c))
+        // in ['SNP', 'N', 'Z', 'A1', 'A2'], plus N_CAS for case-control traits
+        .filter(|c| ["SNP", "N", "N_CAS", "Z", "A1", "A2", "FRQ"].contains(c))
         .collect::<Vec<_>>();
-    if !args.keep_maf {
+    if !opts.keep_maf {
         print_colnames.retain(|x| *x != "FRQ");
     }
+    Ok(dat.select(print_colnames)?)
+}
+/// Replace the Z computed from P with effect / SE, e.g. BETA / SE or log(OR) / SE.
+///
+/// Going through P loses precision for the strongest associations, as 1 - P rounds to
+/// 1 for P below about 1e-16. SNPs without a usable SE keep the Z from P, which is
+/// also used to check that the SE column is sensible.
+fn z_from_effect(
+    dat: &mut DataFrame,
+    sign_type: &str,
+    counts: &mut ZFromEffectCounts,
+) -> Result<()> {
+    let effect = dat.column("SIGNED_SUMSTAT")?.f64()?;
+    let se = dat.column("SE")?.f64()?;
+    let z_p = dat.column("Z")?.f64()?;
+
+    let z_values: Vec<f64> = effect
+        .into_iter()
+        .zip(se)
+        .zip(z_p)
+        .map(|((effect, se), z_p)| {
+            let z_p = z_p.unwrap_or(f64::NAN);
+            let z_se = z_se(effect, se, sign_type);
+            if !z_se.is_finite() {
+                return z_p;
+            }
+            counts.se += 1;
+            if z_p.is_finite() {
+                counts.compared += 1;
+                if (z_se - z_p).abs() > Z_SE_TOLERANCE * z_p.abs().max(1.0) {
+                    counts.inconsistent += 1;
+                }
+            }
+            z_se
+        })
+        .collect();
+    counts.snps += z_values.len();
+    dat.with_column(Series::new("Z".into(), z_values))?;
+    Ok(())
+}
 
-    let final_len = dat.height();
-    let nomiss_n_mask = dat.column("N")?.i64()?.is_not_null();
-    let nomiss_len = dat.column("N")?.i64()?.filter(&nomiss_n_mask)?.len();
-    info!(
-        "Writing summary statistics for {} SNPs ({} with nonmissing beta) to {}.",
-        final_len, nomiss_len, out_fname
-    );
+// effect / SE, with log(OR) for OR, or NaN when there is no usable SE
+fn z_se(effect: Option<f64>, se: Option<f64>, sign_type: &str) -> f64 {
+    match (effect, se) {
+        (Some(effect), Some(se)) if se > 0.0 => {
+            let effect = if sign_type == "OR" {
+                effect.ln()
+            } else {
+                effect
+            };
+            effect / se
+        }
+        _ => f64::NAN,
+    }
+}
 
-    // write to file
-    let outfile = File::create(out_fname)?;
-    let mut gzip_encoder = GzEncoder::new(outfile, Compression::default());
-    CsvWriter::new(&mut gzip_encoder)
-        .include_header(true)
-        .n_threads(8)
-        .with_separator(b'\t')
-        .with_null_value("".to_owned())
-        .with_float_precision(Some(3))
-        .finish(&mut dat.select(print_colnames)?)?;
-    gzip_encoder.finish()?;
+/// Number of SNPs whose Z came from effect / SE, summed over the chunks.
+#[derive(Debug, Default)]
+struct ZFromEffectCounts {
+    snps: usize,
+    se: usize,
+    /// SNPs with both effect / SE and a finite Z from P.
+    compared: usize,
+    /// Compared SNPs where the two disagree.
+    inconsistent: usize,
+}
 
-    let duration = start.elapsed();
-    info!("Time elapsed in expensive_function() is: {:?}", duration);
-    Ok(())
+impl ZFromEffectCounts {
+    /// Whether more than 1% of the compared SNPs are inconsistent.
+    fn mislabeled(&self) -> bool {
+        self.inconsistent as f64 > 0.01 * self.compared as f64
+    }
+
+    fn log(&self, sign_type: &str) {
+        info!(
+            "Computed Z from {} / SE for {} SNPs; {} SNPs used Z from P.",
+            sign_type,
+            self.se,
+            self.snps - self.se
+        );
+        if self.mislabeled() {
+            warn!(
+                "WARNING: {} of {} SNPs have {} / SE inconsistent with P. The SE column may be mislabeled.",
+                self.inconsistent, self.compared, sign_type
+            );
+        } else {
+            info!(
+                "{} / SE is consistent with P for {} of {} SNPs.",
+                sign_type,
+                self.compared - self.inconsistent,
+                self.compared
+            );
+        }
+    }
 }
 
 // Figure out which column names to use.
@@ -483,27 +1021,6 @@ fn get_cname_map(
     cname_map
 }
 
-fn init_logger(args: &Args) -> Result<()> {
-    let _out_pre = &args.out;
-    let _log_file = format!("{}.log", _out_pre);
-    // TODO: temp stdout
-    set_var("RUST_LOG", "info");
-    env_logger::init();
-    Ok(())
-}
-
-fn get_file_colnames(sumstats_path: &str) -> Result<Vec<String>> {
-    // read first line from reader
-    let reader = get_input_reader(sumstats_path)?;
-    let mut lines = reader.lines();
-    if let Some(Ok(line)) = lines.next() {
-        let colnames = line.split_whitespace().map(|s| s.to_string()).collect();
-        Ok(colnames)
-    } else {
-        bail!("Empty file: {:?}", sumstats_path);
-    }
-}
-
 // For cleaning file headers.
 //     - convert to uppercase
 //     - replace dashes '-' with underscores '_'
@@ -520,19 +1037,19 @@ fn clean_header(header: &str) -> String {
 
 /// Parse flags that specify how to interpret nonstandard column names.
 /// flag_cnames is a dict that maps (cleaned) arguments to internal column names
-fn parse_flag_colnames(args: &Args) -> Result<(HashMap<String, String>, Option<f64>)> {
+fn parse_flag_colnames(opts: &MungeOptions) -> Result<(HashMap<String, String>, Option<f64>)> {
     let mut flag_cnames: HashMap<String, String> = HashMap::new();
     let cname_options = [
-        (&args.nstudy, "NSTUDY"),
-        (&args.snp, "SNP"),
-        (&args.n_col, "N"),
-        (&args.n_cas_col, "N_CAS"),
-        (&args.n_con_col, "N_CON"),
-        (&args.a1, "A1"),
-        (&args.a2, "A2"),
-        (&args.p, "P"),
-        (&args.frq, "FRQ"),
-        (&args.info, "INFO"),
+        (&opts.nstudy, "NSTUDY"),
+        (&opts.snp, "SNP"),
+        (&opts.n_col, "N"),
+        (&opts.n_cas_col, "N_CAS"),
+        (&opts.n_con_col, "N_CON"),
+        (&opts.a1, "A1"),
+        (&opts.a2, "A2"),
+        (&opts.p, "P"),
+        (&opts.frq, "FRQ"),
+        (&opts.info, "INFO"),
     ];
 
     for (opt, internal) in &cname_options {
@@ -541,7 +1058,7 @@ fn parse_flag_colnames(args: &Args) -> Result<(HashMap<String, String>, Option<f
         }
     }
 
-    if let Some(info_list) = &args.info_list {
+    if let Some(info_list) = &opts.info_list {
         match info_list.split(',').map(clean_header).collect::<Vec<_>>() {
             info_headers if !info_headers.is_empty() => {
                 for header in info_headers {
@@ -557,7 +1074,7 @@ fn parse_flag_colnames(args: &Args) -> Result<(HashMap<String, String>, Option<f
     }
 
     let mut null_value: Option<f64> = None;
-    if let Some(signed_sumstats) = &args.signed_sumstats {
+    if let Some(signed_sumstats) = &opts.signed_sumstats {
         match signed_sumstats.split(',').collect::<Vec<_>>() {
             parts if parts.len() == 2 => {
                 if let Ok(value) = parts[1].parse::<f64>() {
@@ -578,14 +1095,15 @@ fn parse_flag_colnames(args: &Args) -> Result<(HashMap<String, String>, Option<f
 }
 
 fn get_merge_allels_df(ma_path: &str) -> Result<DataFrame> {
-    // merge_alleles = pd.read_csv(args.merge_alleles, compression=compression, header=0,
+    // merge_alleles = pd.read_csv(opts.merge_alleles, compression=compression, header=0,
     //     delim_whitespace=True, na_values='.')
-    let parse_opts = CsvParseOptions::default().with_separator(b'\t');
-    let mapd = CsvReadOptions::default()
-        .with_parse_options(parse_opts)
+    let read_opts = CsvReadOptions::default()
         .with_has_header(true)
-        .try_into_reader_with_file_path(Some(ma_path.into()))?
-        .finish()?;
+        .with_parse_options(
+            CsvParseOptions::default()
+                .with_null_values(Some(NullValues::AllColumns(vec![".".into()]))),
+        );
+    let mapd = read_csv(ma_path, read_opts, None)?;
 
     if !["SNP", "A1", "A2"].iter().all(|x| mapd.column(x).is_ok()) {
         bail!("--merge-alleles must have columns SNP, A1, A2.");
@@ -605,276 +1123,435 @@ fn get_merge_allels_df(ma_path: &str) -> Result<DataFrame> {
     Ok(mapd)
 }
 
-fn parse_dat(
-    dat: DataFrame,
-    convert_colname: HashMap<&String, String>,
-    merge_alleles: &Option<DataFrame>,
-    args: &Args,
-) -> Result<DataFrame> {
-    let origin_tot_snps = dat.height();
-    // let mut dat_list = Vec::new();
-    info!("Read {} SNPs from --sumstats file.", origin_tot_snps);
-    let mut drops = HashMap::from([
-        ("NA", 0),
-        ("P", 0),
-        ("INFO", 0),
-        ("FRQ", 0),
-        ("A", 0),
-        ("SNP", 0),
-        ("MERGE", 0),
-    ]);
-
-    // drop NA but keep INFO
-    let colnames = dat
-        .get_column_names()
-        .iter()
-        .map(|x| x.as_str().to_string())
-        .collect::<Vec<_>>();
-    let drop_na_cols = colnames
-        .iter()
-        .filter(|col| **col != "INFO")
-        .map(|cn| cn.into())
-        .collect::<Vec<String>>();
-    let mut dat = dat.drop_nulls(Some(&drop_na_cols))?;
-    let clean_snps = dat.height();
-    if let Some(x) = drops.get_mut("NA") {
-        *x += origin_tot_snps - clean_snps;
+/// The per-SNP filters, applied to each chunk as it is read: missing values,
+/// --merge-alleles, INFO, FRQ, P, alleles and duplicated rs numbers.
+struct RowFilter<'a> {
+    convert_colname: &'a HashMap<String, String>,
+    merge_snps: Option<Series>,
+    // type of the signed sumstat when Z is computed from it over SE
+    z_from_se: Option<&'a str>,
+    opts: &'a MungeOptions,
+    valid_snps: Series,
+    // hashes of the rs numbers of the SNPs kept so far; of duplicated SNPs the first is
+    // kept. Two of a million rs numbers collide with a chance of about 3e-8.
+    seen: HashSet<u64>,
+    bad_info_count: usize,
+    bad_frq_count: usize,
+    // SNPs kept despite P outside (0,1], as their Z comes from effect / SE
+    p_from_se_count: usize,
+}
+
+impl<'a> RowFilter<'a> {
+    fn new(
+        convert_colname: &'a HashMap<String, String>,
+        merge_alleles: &Option<DataFrame>,
+        z_from_se: Option<&'a str>,
+        opts: &'a MungeOptions,
+    ) -> Result<Self> {
+        let merge_snps = match merge_alleles {
+            Some(merge_alleles) => Some(
+                merge_alleles
+                    .column("SNP")?
+                    .as_materialized_series()
+                    .clone(),
+            ),
+            None => None,
+        };
+        Ok(Self {
+            convert_colname,
+            merge_snps,
+            z_from_se,
+            opts,
+            valid_snps: Series::new(
+                "valid_snps".into(),
+                ["AC", "GT", "AG", "CA", "GA", "TG", "TC", "CT"],
+            ),
+            seen: HashSet::new(),
+            bad_info_count: 0,
+            bad_frq_count: 0,
+            p_from_se_count: 0,
+        })
     }
-    info!(
-        "Removed {} SNPs with missing values.",
-        drops.get("NA").unwrap()
-    );
 
-    // rename columns
-    let new_columns = colnames
-        .iter()
-        .map(|col| convert_colname.get(col).unwrap().to_string())
-        .collect::<Vec<_>>();
-    dat.set_column_names(&new_columns)?;
-
-    // join sumstats align with merge_alleles SNP if merge_alleles is not None
-    // let mut dat = dat
-    //     .clone()
-    //     .lazy()
-    //     .join(
-    //         merge_alleles.clone().lazy(),
-    //         [col("SNP")],
-    //         [col("SNP")],
-    //         JoinArgs::default(),
-    //     )
-    //     .collect()?;
-    let mut dat = match merge_alleles {
-        Some(merge_alleles) => dat
-            .clone()
-            .lazy()
-            .join(
-                merge_alleles.clone().lazy(),
-                [col("SNP")],
-                [col("SNP")],
-                JoinArgs::default(),
-            )
-            .collect()?,
-        None => dat,
-    };
+    /// Filter one chunk, adding the number of SNPs each filter removed to `report`.
+    fn apply(&mut self, dat: DataFrame, report: &mut FilterReport) -> Result<DataFrame> {
+        let opts = self.opts;
+        report.read += dat.height();
 
-    let merged_count = dat.height();
-    if let Some(x) = drops.get_mut("MERGE") {
-        *x += clean_snps - merged_count;
-    }
-    info!(
-        "Removed {} SNPs not in --merge-alleles.",
-        drops.get("MERGE").unwrap()
-    );
+        // drop NA but keep INFO and SE
+        let colnames = dat
+            .get_column_names()
+            .iter()
+            .map(|x| x.as_str().to_string())
+            .collect::<Vec<_>>();
+        let drop_na_cols = colnames
+            .iter()
+            .filter(|col| !["INFO", "SE"].contains(&self.convert_colname[*col].as_str()))
+            .map(|cn| cn.into())
+            .collect::<Vec<String>>();
+        let old = dat.height();
+        let mut dat = dat.drop_nulls(Some(&drop_na_cols))?;
+        report.na += old - dat.height();
+
+        // rename columns
+        let new_columns = colnames
+            .iter()
+            .map(|col| self.convert_colname[col].to_string())
+            .collect::<Vec<_>>();
+        dat.set_column_names(&new_columns)?;
+        let has_col = |c: &str| new_columns.iter().any(|x| x == c);
+
+        // keep SNPs in merge_alleles
+        if let Some(merge_snps) = &self.merge_snps {
+            let old = dat.height();
+            dat = dat
+                .lazy()
+                .filter(col("SNP").is_in(lit(merge_snps.clone())))
+                .collect()?;
+            report.merge += old - dat.height();
+        }
 
-    // filter INFO
-    if new_columns.contains(&"INFO".to_string()) {
-        let bad_info_df = dat
-            .clone()
-            .lazy()
+        let count = |ii: &BooleanChunked| ii.sum().unwrap_or(0) as usize;
+        let mut ii = BooleanChunked::full("ii".into(), true, dat.height());
+
+        // filter INFO
+        if has_col("INFO") {
+            let info = dat.column("INFO")?.f64()?;
             // ((info > 2.0) | (info < 0)) & info.notnull
-            .filter(
-                (col("INFO").gt_eq(2.0).or(col("INFO").lt_eq(0.0))).and(col("INFO").is_not_null()),
-            )
-            .collect()?;
-        let bad_info_count = bad_info_df.height();
-        if bad_info_count > 0 {
-            warn!(
-                "WARNING: {} SNPs had INFO outside of [0,2]. The INFO column may be mislabeled.",
-                bad_info_count
-            );
+            self.bad_info_count +=
+                count(&(info.gt_eq(2.0) | info.lt_eq(0.0)).fill_null_with_values(false)?);
+            let old = count(&ii);
+            ii = &ii & &info.gt_eq(opts.info_min).fill_null_with_values(false)?;
+            report.info += old - count(&ii);
+        }
+
+        // filter FRQ
+        if has_col("FRQ") {
+            let frq = dat.column("FRQ")?.f64()?;
+            self.bad_frq_count += count(&(frq.lt(0.0) | frq.gt(1.0)));
+            let old = count(&ii);
+            ii = &ii & &(frq.gt(opts.maf_min) & frq.lt_eq(1_f64 - opts.maf_min));
+            report.frq += old - count(&ii);
+        }
+
+        // drop info and frq if not needed
+        if has_col("INFO") {
+            dat.drop_in_place("INFO")?;
+        }
+        if has_col("FRQ") && !opts.keep_maf {
+            dat.drop_in_place("FRQ")?;
         }
-        dat = dat
-            .clone()
-            .lazy()
-            .filter(col("INFO").gt_eq(args.info_min))
-            .collect()?;
 
-        if let Some(x) = drops.get_mut("INFO") {
-            *x += merged_count - dat.height();
+        // filter P, on the natural log scale: P in (0,1] <=> ln(P) in (-inf,0]
+        let ln_p = if has_col("LOG10P") {
+            let log10_p = dat.drop_in_place("LOG10P")?.f64()?.clone();
+            self.check_log_p_sign("LOG10P", &log10_p.lt(0.0))?;
+            log10_p.apply_values(|x| -x * std::f64::consts::LN_10)
+        } else if has_col("LN_P") {
+            let ln_p = dat.drop_in_place("LN_P")?.f64()?.clone();
+            self.check_log_p_sign("LN_P", &ln_p.gt(0.0))?;
+            ln_p
+        } else {
+            dat.drop_in_place("P")?.f64()?.apply_values(f64::ln)
+        };
+        // P is only needed by the SNPs whose Z cannot be computed from effect / SE, so
+        // P = 0 (or an underflowed P) does not remove the strongest associations
+        let p_in_bounds = ln_p.gt(f64::NEG_INFINITY) & ln_p.lt_eq(0.0);
+        let p_pass = match self.z_from_se {
+            Some(sign_type) => {
+                let effect = dat.column("SIGNED_SUMSTAT")?.f64()?;
+                let se = dat.column("SE")?.f64()?;
+                let has_se: BooleanChunked = effect
+                    .into_iter()
+                    .zip(se)
+                    .map(|(effect, se)| z_se(effect, se, sign_type).is_finite())
+                    .collect();
+                self.p_from_se_count += count(&(&ii & &(!&p_in_bounds & has_se.clone())));
+                p_in_bounds | has_se
+            }
+            None => p_in_bounds,
+        };
+        let old = count(&ii);
+        ii = &ii & &p_pass;
+        report.p += old - count(&ii);
+        dat.with_column(ln_p.with_name("LN_P".into()))?;
+
+        // A1+A2 in VALID_SNPS
+        if !opts.no_alleles {
+            let pass_alleles = dat
+                .clone()
+                .lazy()
+                .select([concat_str([col("A1"), col("A2")], "", false)
+                    .is_in(lit(self.valid_snps.clone()))
+                    .alias("pass")])
+                .collect()?;
+            let old = count(&ii);
+            ii = &ii & pass_alleles.column("pass")?.bool()?;
+            report.alleles += old - count(&ii);
         }
+        let dat = dat.filter(&ii)?;
+
+        // remove dup SNPs, keeping the first one across all chunks
+        let first: BooleanChunked = dat
+            .column("SNP")?
+            .str()?
+            .into_no_null_iter()
+            .map(|snp| {
+                let mut hasher = DefaultHasher::new();
+                snp.hash(&mut hasher);
+                self.seen.insert(hasher.finish())
+            })
+            .collect();
+        report.duplicates += dat.height() - count(&first);
+        Ok(dat.filter(&first)?)
     }
-    info!(
-        "Removed {} SNPs with INFO <= {}.",
-        drops.get("INFO").unwrap(),
-        args.info_min
-    );
 
-    // Filter FRQ
-    if new_columns.contains(&"FRQ".to_string()) {
-        let bad_frq_df = dat
-            .clone()
-            .lazy()
-            .filter(col("FRQ").lt(0.0).or(col("FRQ").gt(1.0)))
-            .collect()?;
-        let bad_frq_count = bad_frq_df.height();
-        if bad_frq_count > 0 {
+    // A log-scale P of the wrong sign means the column holds another transformation of P,
+    // e.g. -ln(P) in an LN_P column, rather than P outside of (0,1].
+    fn check_log_p_sign(&self, internal: &str, wrong_sign: &BooleanChunked) -> Result<()> {
+        let count = wrong_sign.sum().unwrap_or(0);
+        if count == 0 {
+            return Ok(());
+        }
+        let cname = self
+            .convert_colname
+            .iter()
+            .find(|(_, v)| *v == internal)
+            .map_or(internal, |(k, _)| k.as_str());
+        let expected = match internal {
+            "LN_P" => "ln(P) <= 0",
+            _ => "-log10(P) >= 0",
+        };
+        bail!(
+            "{} SNPs have values of the wrong sign in column {}, which is read as {}. Use --p with a column of P values if it holds another transformation of P.",
+            count,
+            cname,
+            expected
+        );
+    }
+
+    /// Log the number of SNPs each filter removed from the whole file.
+    fn log(&self, report: &FilterReport) {
+        info!("Read {} SNPs from --sumstats file.", report.read);
+        info!("Removed {} SNPs with missing values.", report.na);
+        info!("Removed {} SNPs not in --merge-alleles.", report.merge);
+        if self.bad_info_count > 0 {
+            warn!(
+                "WARNING: {} SNPs had INFO outside of [0,2]. The INFO column may be mislabeled.",
+                self.bad_info_count
+            );
+        }
+        info!(
+            "Removed {} SNPs with INFO <= {}.",
+            report.info, self.opts.info_min
+        );
+        if self.bad_frq_count > 0 {
             warn!(
                 "WARNING: {} SNPs had FRQ outside of [0,1]. The FRQ column may be mislabeled.",
-                bad_frq_count
+                self.bad_frq_count
             );
         }
-        let low_maf = args.maf_min;
-        let high_maf = 1_f64 - args.maf_min;
-        let pass_maf_dat = dat
-            .clone()
-            .lazy()
-            .filter(col("FRQ").gt(low_maf).and(col("FRQ").lt_eq(high_maf)))
-            .collect()?;
-        if let Some(x) = drops.get_mut("FRQ") {
-            *x += dat.height() - pass_maf_dat.height();
+        info!(
+            "Removed {} SNPs with MAF <= {}.",
+            report.frq, self.opts.maf_min
+        );
+        if report.p > 0 {
+            warn!(
+                "WARNING: {} SNPs had P outside of (0,1]. The P column may be mislabeled.",
+                report.p
+            );
         }
-        dat = pass_maf_dat;
+        info!("Removed {} SNPs with out-of-bounds p-values.", report.p);
+        if self.p_from_se_count > 0 {
+            info!(
+                "Did not remove {} SNPs with P outside of (0,1], as their Z comes from {} / SE.",
+                self.p_from_se_count,
+                self.z_from_se.unwrap_or_default()
+            );
+        }
+        info!(
+            "Removed {} variants that were not SNPs or were strand-ambiguous.",
+            report.alleles
+        );
+        let remained = report.read
+            - report.na
+            - report.merge
+            - report.info
+            - report.frq
+            - report.p
+            - report.alleles;
+        info!("{} SNPs remained", remained);
+        info!(
+            "Removed {} SNPs with duplicated rs numbers ({} SNPs remain).",
+            report.duplicates,
+            remained - report.duplicates
+        );
     }
-    info!(
-        "Removed {} SNPs with MAF <= {}.",
-        drops.get("FRQ").unwrap(),
-        args.maf_min,
-    );
+}
 
-    // drop info and frq if not needed
-    if new_columns.contains(&"INFO".to_string()) {
-        dat.drop_in_place("INFO")?;
-    }
-    if new_columns.contains(&"FRQ".to_string()) && !args.keep_maf {
-        dat.drop_in_place("FRQ")?;
-    }
+/// Running summaries of the N_CAS and N_CON, N or NSTUDY columns of the SNPs that pass
+/// the per-SNP filters, from which the sample size thresholds are computed.
+struct NSummary {
+    snps: usize,
+    // largest N_CAS + N_CON, and the sum and count of the fractions of cases at it
+    max_n: f64,
+    cases_at_max_n: (f64, usize),
+    // of N, or of N_CAS, which becomes N once the fraction of cases is known
+    n_q90: QuantileEstimator,
+    max_nstudy: Option<f64>,
+}
 
-    // filter P
-    let pass_p_df = dat
-        .clone()
-        .lazy()
-        .filter(col("P").gt(0.0).and(col("P").lt_eq(1.0)))
-        .collect()?;
-    let pass_p_count = pass_p_df.height();
-    let bad_p_count = dat.height() - pass_p_count;
-    if bad_p_count > 0 {
-        warn!(
-            "WARNING: {} SNPs had P outside of (0,1]. The P column may be mislabeled.",
-            bad_p_count
-        );
-        if let Some(x) = drops.get_mut("P") {
-            *x += bad_p_count;
+impl Default for NSummary {
+    fn default() -> Self {
+        Self {
+            snps: 0,
+            max_n: f64::NEG_INFINITY,
+            cases_at_max_n: (0.0, 0),
+            n_q90: QuantileEstimator::new(0.9, EXACT_QUANTILE_SNPS),
+            max_nstudy: None,
         }
     }
-    dat = pass_p_df;
-    info!(
-        "Removed {} SNPs with out-of-bounds p-values.",
-        drops.get("P").unwrap()
-    );
+}
 
-    if !args.no_alleles {
-        // A1+A2 in VALID_SNPS
-        let valid_snps = Series::new(
-            "valid_snps".into(),
-            ["AC", "GT", "AG", "CA", "GA", "TG", "TC", "CT"],
-        );
-        let mut pass_alleles_df = dat
-            .clone()
-            .lazy()
-            .with_column(concat_str([col("A1"), col("A2")], "", false).alias("tmp_MA"))
-            .filter(col("tmp_MA").is_in(lit(valid_snps)))
-            .collect()?;
-        // drop tmp_MA
-        pass_alleles_df.drop_in_place("tmp_MA")?;
-        let pass_alleles_count = pass_alleles_df.height();
-        if let Some(x) = drops.get_mut("A") {
-            *x += dat.height() - pass_alleles_count;
+impl NSummary {
+    fn add(&mut self, dat: &DataFrame) -> Result<()> {
+        self.snps += dat.height();
+        let has_col = |c: &str| dat.get_column_names().iter().any(|x| *x == c);
+        if has_col("N_CAS") {
+            let n_cas = dat.column("N_CAS")?.f64()?;
+            let n_con = dat.column("N_CON")?.f64()?;
+            for (n_cas, n_con) in n_cas.into_no_null_iter().zip(n_con.into_no_null_iter()) {
+                let n = n_cas + n_con;
+                if n > self.max_n {
+                    self.max_n = n;
+                    self.cases_at_max_n = (0.0, 0);
+                }
+                if n == self.max_n {
+                    self.cases_at_max_n.0 += n_cas / n;
+                    self.cases_at_max_n.1 += 1;
+                }
+                self.n_q90.add(n_cas);
+            }
+        } else if has_col("N") {
+            let n = dat.column("N")?.f64()?;
+            n.into_no_null_iter().for_each(|n| self.n_q90.add(n));
+        } else if has_col("NSTUDY") {
+            let max = dat.column("NSTUDY")?.f64()?.max();
+            self.max_nstudy = match (self.max_nstudy, max) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                (a, b) => a.or(b),
+            };
         }
-        dat = pass_alleles_df;
+        Ok(())
     }
-    info!(
-        "Removed {} variants that were not SNPs or were strand-ambiguous.",
-        drops.get("A").unwrap()
-    );
+}
 
-    let remain_count = dat.height();
-    if remain_count == 0 {
-        bail!("After applying filters, no SNPs remain.");
-    }
-    info!("{} SNPs remained", remain_count);
-    info!("Done.");
+/// Sample size thresholds that depend on the whole file, computed from the SNPs that
+/// pass the per-SNP filters.
+#[derive(Debug, Default)]
+struct NThresholds {
+    // fraction of cases among the SNPs with the largest N_CAS + N_CON
+    p_max_n: Option<f64>,
+    // set when there is an N (or N_CAS and N_CON) column
+    n_min: Option<f64>,
+    // set when NSTUDY is used instead of N
+    nstudy_min: Option<f64>,
+}
 
-    // remove dup SNPs
-    // unique SNP
-    let unique_dat = dat
-        .clone()
-        .lazy()
-        .unique_stable(Some(vec!["SNP".into()]), UniqueKeepStrategy::Any)
-        .collect()?;
-    let dup_count = dat.height() - unique_dat.height();
-    dat = unique_dat;
-    info!(
-        "Removed {} SNPs with duplicated rs numbers ({} SNPs remain).",
-        dup_count,
-        dat.height()
-    );
+impl NThresholds {
+    /// `n_summary` summarises the N_CAS and N_CON, N or NSTUDY columns of the filtered
+    /// SNPs, and is only needed for the thresholds not given in `opts`.
+    fn new(
+        n_summary: Option<NSummary>,
+        has_n: bool,
+        has_nstudy: bool,
+        opts: &MungeOptions,
+    ) -> Self {
+        let mut thresholds = Self {
+            p_max_n: None,
+            n_min: opts.n_min.filter(|_| has_n),
+            nstudy_min: opts.nstudy_min.filter(|_| has_nstudy),
+        };
+        let Some(summary) = n_summary else {
+            return thresholds;
+        };
+        // N is N_CAS / p_max_n, so its quantile is that of N_CAS over p_max_n
+        let mut n_scale = 1.0;
+        if let (sum, count @ 1..) = summary.cases_at_max_n {
+            let p_max_n = sum / count as f64;
+            thresholds.p_max_n = Some(p_max_n);
+            n_scale = p_max_n;
+        }
+        if thresholds.n_min.is_none() {
+            thresholds.n_min = summary.n_q90.quantile().map(|q| q / n_scale / 1.5);
+        }
+        if has_nstudy && thresholds.nstudy_min.is_none() {
+            thresholds.nstudy_min = summary.max_nstudy;
+        }
+        thresholds
+    }
 
-    Ok(dat)
+    fn log(&self, opts: &MungeOptions, report: &FilterReport, len: usize) {
+        if let Some(n_min) = self.n_min {
+            info!(
+                "Removed {} SNPs with N < {} ({} SNPs remain).",
+                report.n,
+                n_min,
+                len - report.n
+            );
+            return;
+        }
+        if let Some(nstudy_min) = self.nstudy_min {
+            info!(
+                "Removed {} SNPs with NSTUDY < {} ({} SNPs remain).",
+                report.n,
+                nstudy_min,
+                len - report.n
+            );
+        }
+        if let Some(n) = opts.n {
+            info!("Using N = {}", n);
+        } else if let (Some(n_cas), Some(n_con)) = (opts.n_cas, opts.n_con) {
+            if !opts.daner {
+                info!("Using N_cas = {}; N_con = {}", n_cas, n_con);
+            }
+        }
+    }
 }
 
-// Determine sample size from --N* flags or N* columns. Filter out low N SNPs.s
-fn process_n(dat: DataFrame, args: &Args) -> Result<DataFrame> {
-    let colnames = dat
-        .get_column_names()
-        .iter()
-        .map(|x| x.as_str())
-        .collect::<Vec<_>>();
-    let mut dat = dat.clone();
-    if colnames.contains(&"N_CAS") && colnames.contains(&"N_CON") {
-        let n_cas = dat.column("N_CAS")?.i64()?;
-        let n_con = dat.column("N_CON")?.i64()?;
-        let n = n_cas + n_con;
-        let p = (&n_cas.cast(&DataType::Float64)? / &n.cast(&DataType::Float64)?)?;
-        let max_n = n.max().unwrap();
-        let p_max_n = p.filter(&n.equal(max_n))?.mean().unwrap();
-        let new_n_series = Series::new("N".into(), n_cas.cast(&DataType::Float64)? / p_max_n);
+// Determine sample size from --N* flags or N* columns, keeping the number of cases as N_CAS.
+// Filter out low N SNPs.
+fn process_n(
+    dat: DataFrame,
+    opts: &MungeOptions,
+    thresholds: &NThresholds,
+    report: &mut FilterReport,
+) -> Result<DataFrame> {
+    let has_col = |dat: &DataFrame, c: &str| dat.get_column_names().iter().any(|x| *x == c);
+    let mut dat = dat;
+    if has_col(&dat, "N_CAS") && has_col(&dat, "N_CON") {
+        let Some(p_max_n) = thresholds.p_max_n else {
+            bail!("The fraction of cases was not determined. This message indicates a bug.");
+        };
+        let n_cas = dat.column("N_CAS")?.f64()?;
+        let new_n_series = Series::new("N".into(), n_cas / p_max_n);
         dat.with_column(new_n_series)?;
-        dat.drop_in_place("N_CAS")?;
+        // N_CAS is kept so that the fraction of cases, sum(N_CAS) / sum(N), can be recovered
+        let n_cas = dat.drop_in_place("N_CAS")?;
+        dat.with_column(n_cas)?;
         dat.drop_in_place("N_CON")?;
     }
 
-    if colnames.contains(&"N") {
-        let n_min = if let Some(n_min) = args.n_min {
-            n_min
-        } else {
-            let n = dat.column("N")?.i64()?;
-            n.quantile(0.9, QuantileMethod::Linear)?.unwrap() / 1.5
+    if has_col(&dat, "N") {
+        let Some(n_min) = thresholds.n_min else {
+            bail!("N_min was not determined. This message indicates a bug.");
         };
         let old_count = dat.height();
         dat = dat.lazy().filter(col("N").gt_eq(lit(n_min))).collect()?;
-        let new_count = dat.height();
-        info!(
-            "Removed {} SNPs with N < {} ({} SNPs remain).",
-            old_count - new_count,
-            n_min,
-            new_count
-        );
-    } else if colnames.contains(&"NSTUDY") && !colnames.contains(&"N") {
-        let nstudy_min = if let Some(nstudy_min) = args.nstudy_min {
-            nstudy_min
-        } else {
-            let nstudy = dat.column("NSTUDY")?.f64()?;
-            nstudy.max().unwrap()
+        report.n += old_count - dat.height();
+    } else if has_col(&dat, "NSTUDY") {
+        let Some(nstudy_min) = thresholds.nstudy_min else {
+            bail!("NSTUDY_min was not determined. This message indicates a bug.");
         };
         let old_count = dat.height();
         dat = dat
@@ -882,30 +1559,450 @@ fn process_n(dat: DataFrame, args: &Args) -> Result<DataFrame> {
             .filter(col("NSTUDY").gt_eq(lit(nstudy_min)))
             .collect()?;
         dat.drop_in_place("NSTUDY")?;
-        let new_count = dat.height();
-        info!(
-            "Removed {} SNPs with NSTUDY < {} ({} SNPs remain).",
-            old_count - new_count,
-            nstudy_min,
-            new_count
-        );
+        report.n += old_count - dat.height();
     }
 
-    if !colnames.contains(&"N") {
-        if let Some(n) = args.n {
+    if !has_col(&dat, "N") {
+        if let Some(n) = opts.n {
             dat = dat.lazy().with_column(lit(n).alias("N")).collect()?;
-            info!("Using N = {}", n);
-        } else if let (Some(n_cas), Some(n_con)) = (args.n_cas, args.n_con) {
+        } else if let (Some(n_cas), Some(n_con)) = (opts.n_cas, opts.n_con) {
             let n = n_cas + n_con;
-            dat = dat.lazy().with_column(lit(n).alias("N")).collect()?;
-            if !args.daner {
-                info!("Using N_cas = {}; N_con = {}", n_cas, n_con);
-            }
+            dat = dat
+                .lazy()
+                .with_columns([lit(n).alias("N"), lit(n_cas).alias("N_CAS")])
+                .collect()?;
         } else {
             bail!(
                 "Cannot determine N. This message indicates a bug.\nN should have been checked earlier in the program."
             );
         }
     }
+
+    // N is written as an integer, due to some N looks like 7e05 but it's a i64
+    for c in ["N", "N_CAS"] {
+        if has_col(&dat, c) {
+            let n = dat.column(c)?.f64()?.apply_values(|x| x.round());
+            dat.with_column(n.cast(&DataType::Int64)?.with_name(c.into()))?;
+        }
+    }
     Ok(dat)
 }
+/// Adapt the column name map to Stephan Ripke's daner* format.
+///
+/// With `--daner`, N_cas and N_con are parsed from the FRQ_A_<ncas> and FRQ_U_<ncon>
+/// headers and any N, N_CAS, N_CON or FRQ columns are ignored. With `--daner-n`, the
+/// per-SNP Nca and Nco columns are used. Either way FRQ_U_<ncon> is used as FRQ.
+fn parse_daner(
+    colnames: &[String],
+    cname_map: &mut HashMap<String, String>,
+    opts: &mut MungeOptions,
+) -> Result<()> {
+    let find_header = |prefix: &str| {
+        colnames
+            .iter()
+            .find(|x| x.starts_with(prefix))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Could not find {}* column expected for daner format",
+                    prefix
+                )
+            })
+    };
+    let frq_u = find_header("FRQ_U_")?;
+
+    if opts.daner {
+        let frq_a = find_header("FRQ_A_")?;
+        let n_cas = frq_a["FRQ_A_".len()..]
+            .parse::<f64>()
+            .map_err(|_| anyhow!("Could not infer N_cas from the {} column.", frq_a))?;
+        let n_con = frq_u["FRQ_U_".len()..]
+            .parse::<f64>()
+            .map_err(|_| anyhow!("Could not infer N_con from the {} column.", frq_u))?;
+        info!(
+            "Inferred that N_cas = {}, N_con = {} from the FRQ_[A/U] columns.",
+            n_cas, n_con
+        );
+        opts.n_cas = Some(n_cas);
+        opts.n_con = Some(n_con);
+
+        // drop any N, N_cas, N_con or FRQ columns
+        cname_map.retain(|_, v| !["N", "N_CAS", "N_CON", "FRQ"].contains(&v.as_str()));
+    }
+
+    if opts.daner_n {
+        for (daner_col, internal) in [("Nca", "N_CAS"), ("Nco", "N_CON")] {
+            if !colnames.iter().any(|x| x == daner_col) {
+                bail!(
+                    "Could not find {} column expected for daner-n format",
+                    daner_col
+                );
+            }
+            cname_map.insert(clean_header(daner_col), internal.to_string());
+        }
+    }
+
+    cname_map.insert(clean_header(frq_u), "FRQ".to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::io::Read;
+
+    fn default_cname_map() -> HashMap<String, String> {
+        let default = DEFAULT_CNAMES.into_iter().map(|(k, v)| (*k, *v)).collect();
+        get_cname_map(HashMap::new(), default, vec![])
+    }
+
+    fn colnames(names: &[&str]) -> Vec<String> {
+        names.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn daner_sample_sizes_come_from_frq_headers() {
+        let names = colnames(&[
+            "CHR",
+            "SNP",
+            "BP",
+            "A1",
+            "A2",
+            "FRQ_A_1000",
+            "FRQ_U_2000",
+            "INFO",
+            "OR",
+            "SE",
+            "P",
+            "N",
+        ]);
+        let mut cname_map = default_cname_map();
+        let mut opts = MungeOptions::default().with_daner(true);
+        parse_daner(&names, &mut cname_map, &mut opts).unwrap();
+        assert_eq!((opts.n_cas, opts.n_con), (Some(1000.0), Some(2000.0)));
+        assert_eq!(cname_map["FRQ_U_2000"], "FRQ");
+        assert!(!cname_map.contains_key("FRQ_A_1000"));
+        assert!(!cname_map.values().any(|v| v == "N"));
+
+        let names = colnames(&["SNP", "A1", "A2", "FRQ_A_x", "FRQ_U_2000", "OR", "P"]);
+        let mut opts = MungeOptions::default().with_daner(true);
+        assert!(parse_daner(&names, &mut default_cname_map(), &mut opts).is_err());
+    }
+
+    #[test]
+    fn daner_n_uses_per_snp_columns() {
+        let names = colnames(&[
+            "SNP",
+            "A1",
+            "A2",
+            "FRQ_A_1000",
+            "FRQ_U_2000",
+            "OR",
+            "P",
+            "Nca",
+            "Nco",
+        ]);
+        let mut cname_map = default_cname_map();
+        let mut opts = MungeOptions::default().with_daner_n(true);
+        parse_daner(&names, &mut cname_map, &mut opts).unwrap();
+        assert_eq!((opts.n_cas, opts.n_con), (None, None));
+        assert_eq!(cname_map["NCA"], "N_CAS");
+        assert_eq!(cname_map["NCO"], "N_CON");
+        assert_eq!(cname_map["FRQ_U_2000"], "FRQ");
+
+        let names = colnames(&["SNP", "A1", "A2", "FRQ_U_2000", "OR", "P", "Nca"]);
+        let mut opts = MungeOptions::default().with_daner_n(true);
+        assert!(parse_daner(&names, &mut default_cname_map(), &mut opts).is_err());
+    }
+
+    #[test]
+    fn munges_daner_files() {
+        let dir = TempDir::new();
+        let text = "SNP\tA1\tA2\tFRQ_A_1000\tFRQ_U_3000\tOR\tP\tNca\tNco\n\
+                    rs1\tA\tG\t0.3\t0.4\t1.1\t0.01\t500\t1500\n\
+                    rs2\tC\tT\t0.2\t0.5\t0.9\t0.2\t1000\t3000\n";
+        let path = dir.write("daner.txt", text);
+
+        let opts = MungeOptions::default().with_daner(true);
+        let dat = munge(&path, &opts).unwrap().data;
+        let n = dat.column("N").unwrap().i64().unwrap();
+        let n_cas = dat.column("N_CAS").unwrap().i64().unwrap();
+        assert_eq!(n.into_no_null_iter().collect::<Vec<_>>(), [4000, 4000]);
+        assert_eq!(n_cas.into_no_null_iter().collect::<Vec<_>>(), [1000, 1000]);
+
+        // N = N_cas / (fraction of cases at max N), and rs1 falls below the N filter
+        let opts = MungeOptions::default().with_daner_n(true);
+        let dat = munge(&path, &opts).unwrap().data;
+        let n = dat.column("N").unwrap().i64().unwrap();
+        assert_eq!(n.into_no_null_iter().collect::<Vec<_>>(), [4000]);
+    }
+
+    #[test]
+    fn chunksize_does_not_change_the_output() {
+        let dir = TempDir::new();
+        let text = "SNP\tA1\tA2\tBETA\tP\tN\tINFO\tFRQ\n\
+                    rs1\tA\tG\t0.1\t0.01\t1000\t0.95\t0.3\n\
+                    rs2\tA\tT\t0.1\t0.01\t1000\t0.95\t0.3\n\
+                    rs3\tC\tT\t-0.2\t0.001\t1000\t0.5\t0.3\n\
+                    rs4\tC\tT\tNA\t0.5\t1000\t0.95\t0.3\n\
+                    rs1\tC\tT\t-0.1\t0.2\t1000\t0.95\t0.3\n\
+                    rs5\tG\tA\t0.05\t0.3\t400\t0.99\t0.2\n\
+                    rs6\tG\tA\t-0.05\t0\t1000\t0.99\t0.005\n\
+                    rs7\tT\tC\t0.3\t1e-20\t1000\t0.99\t0.4\n\
+                    rs8\tT\tG\t-0.01\t1.5\t1000\t0.99\t0.4\n\
+                    rs9\tT\tG\t-0.01\t0.9\t900\t0.99\t0.4\n\
+                    rs7\tA\tC\t0.3\t1e-20\t1000\t0.99\t0.4\n";
+        let path = dir.write("chunks.txt", text);
+        let whole = munge(&path, &MungeOptions::default().with_chisq_max(Some(80.0))).unwrap();
+        let filters = &whole.report.filters;
+        assert_eq!(
+            [
+                filters.read,
+                filters.na,
+                filters.info,
+                filters.frq,
+                filters.p,
+                filters.alleles
+            ],
+            [11, 1, 1, 1, 1, 1]
+        );
+        assert_eq!([filters.duplicates, filters.n, filters.chisq], [2, 1, 1]);
+        assert_eq!(filters.remaining, 2);
+
+        for chunksize in [1, 2, 5] {
+            let opts = MungeOptions::default()
+                .with_chisq_max(Some(80.0))
+                .with_chunksize(chunksize);
+            let mut n_chunks = 0;
+            let mut data: Option<DataFrame> = None;
+            let report = munge_with(&path, &opts, |chunk| {
+                n_chunks += 1;
+                assert!(chunk.height() <= chunksize);
+                match data.as_mut() {
+                    Some(data) => {
+                        data.vstack_mut(&chunk)?;
+                    }
+                    None => data = Some(chunk),
+                }
+                Ok(())
+            })
+            .unwrap();
+            assert!(n_chunks >= 1);
+            assert_eq!(
+                serde_json::to_string(&report).unwrap(),
+                serde_json::to_string(&whole.report).unwrap()
+            );
+            assert!(data.unwrap().equals_missing(&whole.data));
+        }
+    }
+
+    fn effect_frame(effect: &[f64], se: &[Option<f64>], z_p: &[f64]) -> DataFrame {
+        DataFrame::new(vec![
+            Column::new("SIGNED_SUMSTAT".into(), effect),
+            Column::new("SE".into(), se),
+            Column::new("Z".into(), z_p),
+        ])
+        .unwrap()
+    }
+
+    fn z(dat: &DataFrame) -> Vec<f64> {
+        dat.column("Z")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect()
+    }
+
+    #[test]
+    fn z_from_effect_over_se_falls_back_to_p() {
+        // BETA / SE, then SE missing, zero and negative
+        let mut dat = effect_frame(
+            &[0.2, 0.2, 0.2, 0.2],
+            &[Some(0.1), None, Some(0.0), Some(-0.1)],
+            &[1.9, 1.5, 1.6, 1.7],
+        );
+        let mut counts = ZFromEffectCounts::default();
+        z_from_effect(&mut dat, "BETA", &mut counts).unwrap();
+        assert_eq!(z(&dat), [2.0, 1.5, 1.6, 1.7]);
+        assert_eq!((counts.snps, counts.se, counts.compared), (4, 1, 1));
+        assert!(!counts.mislabeled());
+
+        // ln(OR) / SE, where an OR of 0 has no usable Z
+        let mut dat = effect_frame(&[0.3_f64.exp(), 0.0], &[Some(0.1), Some(0.1)], &[3.0, 0.5]);
+        let mut counts = ZFromEffectCounts::default();
+        z_from_effect(&mut dat, "OR", &mut counts).unwrap();
+        let z = z(&dat);
+        assert!((z[0] - 3.0).abs() < 1e-12);
+        assert_eq!(z[1], 0.5);
+        assert_eq!(counts.se, 1);
+    }
+
+    #[test]
+    fn warns_when_effect_over_se_disagrees_with_p() {
+        // 2.1 is within 10% of 2.0, 5.0 is not; an infinite Z from P is not compared
+        let mut dat = effect_frame(
+            &[0.21, 0.5, 0.5],
+            &[Some(0.1), Some(0.1), Some(0.1)],
+            &[2.0, 2.0, f64::INFINITY],
+        );
+        let mut counts = ZFromEffectCounts::default();
+        z_from_effect(&mut dat, "BETA", &mut counts).unwrap();
+        assert_eq!((counts.compared, counts.inconsistent), (2, 1));
+        assert!(counts.mislabeled());
+    }
+
+    #[test]
+    fn p_of_zero_is_kept_when_se_gives_z() {
+        let dir = TempDir::new();
+        let text = "SNP\tA1\tA2\tBETA\tSE\tP\tN\n\
+                    rs1\tA\tG\t0.5\t0.01\t0\t1000\n\
+                    rs2\tA\tC\t0.5\tNA\t0\t1000\n\
+                    rs3\tC\tT\t-0.02\t0.01\t0.0455\t1000\n\
+                    rs4\tG\tT\t-0.02\t0\t0.0455\t1000\n";
+        let path = dir.write("p0.txt", text);
+        let munged = munge(&path, &MungeOptions::default()).unwrap();
+        assert_eq!(munged.report.filters.p, 1);
+        let snps: Vec<_> = munged
+            .data
+            .column("SNP")
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert_eq!(snps, ["rs1", "rs3", "rs4"]);
+        let z = z(&munged.data);
+        assert_eq!(z[..2], [50.0, -2.0]);
+        // SE of 0 falls back to the Z from P, signed by BETA
+        assert!((z[2] + 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn fails_cleanly_when_every_snp_is_filtered() {
+        let dir = TempDir::new();
+        // the default --n-min is computed from the SNPs that pass the INFO, MAF and P filters
+        let text = "SNP\tA1\tA2\tZ\tP\tN\tINFO\tFRQ\n\
+                    rs1\tA\tG\t1.1\t0.01\t1000\t0.5\t0.3\n\
+                    rs2\tA\tC\t0.9\t0.2\t1000\t0.99\t0.001\n\
+                    rs3\tC\tT\t1.02\t1.5\t1000\t0.99\t0.3\n";
+        let err = munge(
+            &dir.write("all_filtered.txt", text),
+            &MungeOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "After applying filters, no SNPs remain.");
+
+        // with --N there is no first pass, and N_CAS and N_CON need a first pass of their own
+        let opts = MungeOptions::default().with_n(1000.0);
+        let err = munge(&dir.write("all_filtered.txt", text), &opts).unwrap_err();
+        assert_eq!(err.to_string(), "After applying filters, no SNPs remain.");
+        let text = "SNP\tA1\tA2\tZ\tP\tN_CAS\tN_CON\tINFO\n\
+                    rs1\tA\tG\t1.1\t0.01\t500\t500\t0.5\n";
+        let err = munge(
+            &dir.write("all_filtered_cc.txt", text),
+            &MungeOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "After applying filters, no SNPs remain.");
+    }
+
+    #[test]
+    fn log_p_columns_must_have_the_right_sign() {
+        let dir = TempDir::new();
+        let text = "SNP\tA1\tA2\tBETA\tLN_P\tN\n\
+                    rs1\tA\tG\t0.5\t-800\t1000\n\
+                    rs2\tA\tC\t-0.5\t-0.1\t1000\n";
+        let munged = munge(&dir.write("ln_p.txt", text), &MungeOptions::default()).unwrap();
+        let z = z(&munged.data);
+        assert!(z[0] > 39.0 && z[1] < 0.0);
+
+        // -ln(P) in an LN_P column, and log10(P) in a LOG10P column
+        let text = "SNP\tA1\tA2\tBETA\tLN_P\tN\nrs1\tA\tG\t0.5\t800\t1000\n";
+        assert!(munge(&dir.write("neg_ln_p.txt", text), &MungeOptions::default()).is_err());
+        let text = "SNP\tA1\tA2\tBETA\tLOG10P\tN\nrs1\tA\tG\t0.5\t-3\t1000\n";
+        assert!(munge(&dir.write("log10_p.txt", text), &MungeOptions::default()).is_err());
+
+        // LOG_P does not say which log it is, so it is not read as P
+        let text = "SNP\tA1\tA2\tBETA\tLOG_P\tN\nrs1\tA\tG\t0.5\t-3\t1000\n";
+        let err = munge(&dir.write("log_p.txt", text), &MungeOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Could not find P column.");
+    }
+
+    #[test]
+    fn writes_the_munge_report() {
+        use crate::cli::{Cli, Commands};
+        use clap::Parser;
+
+        let dir = TempDir::new();
+        let text = "SNP\tA1\tA2\tOR\tP\tN\tINFO\n\
+                    rs1\tA\tG\t1.1\t0.01\t1000\t0.95\n\
+                    rs2\tA\tC\t0.9\t0.2\t1000\t0.5\n\
+                    rs3\tC\tT\t1.02\t0.6\t1000\t0.99\n\
+                    rs4\tG\tT\t0.95\t0.3\tNA\t0.99\n\
+                    rs5\tG\tT\t1.05\t0.4\t1000\t0.99\n";
+        let ma = dir.write(
+            "report_ma.txt",
+            "SNP A1 A2\nrs1 A G\nrs3 T C\nrs5 G A\nrs6 A C\n",
+        );
+        let out = dir.path("report");
+        let sumstats = dir.write("report.txt", text);
+        let cli = Cli::parse_from([
+            "ldscrs",
+            "munge",
+            "--sumstats",
+            &sumstats,
+            "--merge-alleles",
+            &ma,
+            "--out",
+            &out,
+        ]);
+        let Commands::Munge(args) = &cli.command else {
+            unreachable!()
+        };
+        run(args, &cli.global).unwrap();
+
+        let report: serde_json::Value =
+            serde_json::from_reader(File::open(format!("{}.munge.json", out)).unwrap()).unwrap();
+        let filters = &report["filters"];
+        for (stage, count) in [
+            ("read", 5),
+            ("na", 1),
+            ("merge", 1),
+            ("info", 0),
+            ("frq", 0),
+            ("p", 0),
+            ("alleles", 0),
+            ("duplicates", 0),
+            ("n", 0),
+            ("chisq", 0),
+            ("merge_alleles_mismatch", 1),
+            ("remaining", 4),
+        ] {
+            assert_eq!(filters[stage], count, "{}", stage);
+        }
+        assert_eq!(report["column_translation"]["OR"], "SIGNED_SUMSTAT");
+        assert_eq!(report["column_translation"]["INFO"], "INFO");
+        assert_eq!(report["column_description"]["OR"], DESCRIBE_CNAME["OR"]);
+        let median_check = &report["median_check"];
+        assert_eq!(median_check["column"], "OR");
+        assert_eq!(median_check["median"], 1.05);
+        assert_eq!(median_check["expected"], 1.0);
+        assert_eq!(median_check["sensible"], true);
+
+        // the output follows --merge-alleles, with SNPs that did not survive left empty
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(File::open(format!("{}.sumstats.gz", out)).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        let rows: Vec<Vec<&str>> = text.lines().map(|l| l.split('\t').collect()).collect();
+        let snp = rows[0].iter().position(|c| *c == "SNP").unwrap();
+        let z = rows[0].iter().position(|c| *c == "Z").unwrap();
+        let snps: Vec<_> = rows[1..].iter().map(|r| (r[snp], r[z])).collect();
+        assert_eq!(
+            snps,
+            [("rs1", "2.576"), ("rs3", "0.524"), ("rs5", ""), ("rs6", "")]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::{anyhow, bail, Result};
+use nalgebra::DMatrix;
+use polars::prelude::*;
+use std::io::BufRead;
+
+use crate::utils::{get_input_reader, input_paths, read_csv};
+
+// leading columns of a (non-thin) .annot file
+const ANNOT_ID_COLS: usize = 4;
+
+/// Annotations of a `.annot` file, one row per SNP.
+#[derive(Debug, Clone)]
+pub struct Annot {
+    /// SNP column, `None` for a thin annot file which only holds annotations.
+    pub snps: Option<Vec<String>>,
+    pub colnames: Vec<String>,
+    /// SNPs x annotations.
+    pub matrix: DMatrix<f64>,
+    /// Whether every value is an integer, i.e. pandas would have read the matrix as int64.
+    pub integer: bool,
+}
+
+/// Read a whitespace-delimited `.annot` file with a header.
+///
+/// A full annot file starts with CHR, BP, SNP and CM columns and the annotations are the
+/// columns after them; with `thin` every column is an annotation.
+pub fn read_annot(path: &str, thin: bool) -> Result<Annot> {
+    let mut lines = get_input_reader(path)?.lines();
+    let header = loop {
+        match lines.next() {
+            Some(line) => {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    break line;
+                }
+            }
+            None => bail!("Empty file: {:?}", path),
+        }
+    };
+    let header = header.split_whitespace().collect::<Vec<_>>();
+    let (first, snp_col) = if thin {
+        (0, None)
+    } else {
+        let snp_col = header
+            .iter()
+            .position(|c| *c == "SNP")
+            .ok_or_else(|| anyhow!("Could not find SNP column in {}.", path))?;
+        if header.len() <= ANNOT_ID_COLS {
+            bail!("No annotations in {}.", path);
+        }
+        (ANNOT_ID_COLS, Some(snp_col))
+    };
+    let colnames = header[first..]
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>();
+
+    let mut snps = Vec::new();
+    let mut values = Vec::new();
+    let mut integer = true;
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.is_empty() {
+            continue;
+        }
+        if fields.len() != header.len() {
+            bail!(
+                "{} line {}: expected {} columns, found {}.",
+                path,
+                i + 2,
+                header.len(),
+                fields.len()
+            );
+        }
+        if let Some(snp_col) = snp_col {
+            snps.push(fields[snp_col].to_string());
+        }
+        for x in &fields[first..] {
+            integer &= x.parse::<i64>().is_ok();
+            values.push(
+                x.parse::<f64>()
+                    .map_err(|_| anyhow!("{} line {}: invalid annotation {}.", path, i + 2, x))?,
+            );
+        }
+    }
+    let n_snp = values.len() / colnames.len();
+    Ok(Annot {
+        snps: snp_col.map(|_| snps),
+        matrix: DMatrix::from_row_slice(n_snp, colnames.len(), &values),
+        colnames,
+        integer,
+    })
+}
+
+/// Read LD scores from `path`.l2.ldscore (or the 22 chromosome files of the pattern
+/// `path` if `chr_split`), sorted by CHR and BP with duplicated SNPs removed.
+///
+/// Every LD score column is read as Float64; MAF and CM columns are dropped.
+pub fn read_ldscore(path: &str, chr_split: bool) -> Result<DataFrame> {
+    let mut ldscore: Option<DataFrame> = None;
+    for fname in input_paths(path, ".l2.ldscore", chr_split)? {
+        let mut df = read_csv(
+            &fname,
+            CsvReadOptions::default().with_has_header(true),
+            None,
+        )?;
+        if df.column("MAF").is_ok() && df.column("CM").is_ok() {
+            df = df.drop_many(["MAF", "CM"]);
+        }
+        if !["CHR", "SNP", "BP"].iter().all(|c| df.column(c).is_ok()) {
+            bail!("{} must have columns CHR, SNP and BP.", fname);
+        }
+        let casts = df
+            .get_column_names()
+            .iter()
+            .map(|c| match c.as_str() {
+                "CHR" | "BP" => col(c.as_str()).cast(DataType::Int64),
+                "SNP" => col(c.as_str()).cast(DataType::String),
+                _ => col(c.as_str()).cast(DataType::Float64),
+            })
+            .collect::<Vec<_>>();
+        let df = df.lazy().select(casts).collect()?;
+        match ldscore.as_mut() {
+            Some(acc) => {
+                if acc.get_column_names() != df.get_column_names() {
+                    bail!(
+                        "{} does not have the same columns as the other LD score files.",
+                        fname
+                    );
+                }
+                acc.vstack_mut(&df)?;
+            }
+            None => ldscore = Some(df),
+        }
+    }
+    let ldscore = ldscore.unwrap();
+    let ldscore = ldscore
+        .sort(
+            ["CHR", "BP"],
+            SortMultipleOptions::default().with_maintain_order(true),
+        )?
+        .unique_stable(Some(&["SNP".into()]), UniqueKeepStrategy::First, None)?;
+    Ok(ldscore)
+}
+
+/// Read the number of SNPs per annotation from `path`.l2.M, or `path`.l2.M_5_50 with
+/// `common` (SNPs with MAF > 5%), summed over the 22 chromosomes if `chr_split`.
+pub fn read_m(path: &str, chr_split: bool, common: bool) -> Result<Vec<f64>> {
+    let suffix = if common { ".l2.M_5_50" } else { ".l2.M" };
+    let mut total: Vec<f64> = Vec::new();
+    for fname in input_paths(path, suffix, chr_split)? {
+        let mut line = String::new();
+        get_input_reader(&fname)?.read_line(&mut line)?;
+        let m = line
+            .split_whitespace()
+            .map(|x| x.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| anyhow!("Could not parse the number of SNPs in {}.", fname))?;
+        if total.is_empty() {
+            total = m;
+        } else if total.len() != m.len() {
+            bail!(
+                "{} has {} annotations, expected {}.",
+                fname,
+                m.len(),
+                total.len()
+            );
+        } else {
+            total.iter_mut().zip(m).for_each(|(t, x)| *t += x);
+        }
+    }
+    Ok(total)
+}
+
+/// Read annotations from `path`.annot (or the 22 chromosome files of the pattern `path`
+/// if `chr_split`, concatenated in chromosome order).
+pub fn read_annot_files(path: &str, chr_split: bool, thin: bool) -> Result<Annot> {
+    let mut annots = input_paths(path, ".annot", chr_split)?
+        .iter()
+        .map(|fname| read_annot(fname, thin))
+        .collect::<Result<Vec<_>>>()?;
+    if annots.len() == 1 {
+        return Ok(annots.remove(0));
+    }
+    let colnames = annots[0].colnames.clone();
+    if annots.iter().any(|a| a.colnames != colnames) {
+        bail!("The annotations of {} differ between chromosomes.", path);
+    }
+    let n_snp = annots.iter().map(|a| a.matrix.nrows()).sum();
+    let mut matrix = DMatrix::zeros(n_snp, colnames.len());
+    let mut row = 0;
+    for a in &annots {
+        matrix.rows_mut(row, a.matrix.nrows()).copy_from(&a.matrix);
+        row += a.matrix.nrows();
+    }
+    Ok(Annot {
+        snps: annots
+            .iter()
+            .map(|a| a.snps.clone())
+            .collect::<Option<Vec<_>>>()
+            .map(|s| s.concat()),
+        colnames,
+        matrix,
+        integer: annots.iter().all(|a| a.integer),
+    })
+}
+
+/// Read allele frequencies (SNP and FRQ, or MAF renamed to FRQ) from `path`.frq, or the
+/// 22 chromosome files of the pattern `path` if `chr_split`, e.g. the output of
+/// `plink --freq`.
+pub fn read_frq(path: &str, chr_split: bool) -> Result<DataFrame> {
+    let mut frq: Option<DataFrame> = None;
+    for fname in input_paths(path, ".frq", chr_split)? {
+        let df = read_frq_file(&fname)?;
+        match frq.as_mut() {
+            Some(acc) => {
+                acc.vstack_mut(&df)?;
+            }
+            None => frq = Some(df),
+        }
+    }
+    Ok(frq.unwrap())
+}
+
+/// Read SNP and FRQ (or MAF renamed to FRQ) from the .frq file `fname`.
+pub fn read_frq_file(fname: &str) -> Result<DataFrame> {
+    let mut df = read_csv(fname, CsvReadOptions::default().with_has_header(true), None)?;
+    if df.column("MAF").is_ok() {
+        df.rename("MAF", "FRQ".into())?;
+    }
+    if df.column("SNP").is_err() || df.column("FRQ").is_err() {
+        bail!("{} must have columns SNP and FRQ (or MAF).", fname);
+    }
+    Ok(df
+        .lazy()
+        .select([
+            col("SNP").cast(DataType::String),
+            col("FRQ").cast(DataType::Float64),
+        ])
+        .collect()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn reads_chr_split_files() {
+        let dir = TempDir::new();
+        let prefix = dir.path("ld");
+        for chr in 1..=22 {
+            let ld =
+                format!("CHR\tSNP\tBP\tL2\n{chr}\trs{chr}b\t20\t2\n{chr}\trs{chr}a\t10\t1.5\n");
+            dir.write(&format!("ld{}.l2.ldscore", chr), &ld);
+            dir.write(&format!("ld{}.l2.M", chr), "10\t2.5\n");
+        }
+        let ld = read_ldscore(&prefix, true).unwrap();
+        assert_eq!(ld.height(), 44);
+        let snp = ld.column("SNP").unwrap().str().unwrap();
+        assert_eq!(snp.get(0), Some("rs1a"));
+        assert_eq!(snp.get(43), Some("rs22b"));
+        assert_eq!(ld.column("L2").unwrap().dtype(), &DataType::Float64);
+        assert_eq!(read_m(&prefix, true, false).unwrap(), [220.0, 55.0]);
+        assert!(read_m(&prefix, true, true).is_err());
+    }
+}
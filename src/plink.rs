@@ -0,0 +1,338 @@
+use anyhow::{anyhow, bail, Result};
+use memmap2::Mmap;
+use nalgebra::DMatrix;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::BufRead;
+
+use crate::utils::get_input_reader;
+
+// magic number of a SNP-major .bed file
+const BED_MAGIC: [u8; 3] = [0x6c, 0x1b, 0x01];
+// 2-bit genotype code -> number of A2 alleles (None for a missing call), as ldsc counts them
+const BED_CODE: [Option<f64>; 4] = [Some(0.0), None, Some(1.0), Some(2.0)];
+
+/// One variant of a `.bim` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snp {
+    /// Chromosome, with X, Y, XY and MT coded as 23-26 like PLINK does.
+    pub chr: u8,
+    pub snp: String,
+    /// Genetic position in centimorgans.
+    pub cm: f64,
+    pub bp: u64,
+    pub a1: String,
+    pub a2: String,
+}
+
+/// One individual of a `.fam` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Individual {
+    pub fid: String,
+    pub iid: String,
+    pub father: String,
+    pub mother: String,
+    /// 1 = male, 2 = female, 0 = unknown.
+    pub sex: u8,
+    pub phenotype: String,
+}
+
+/// Parse a PLINK chromosome code.
+pub fn parse_chr(chr: &str) -> Option<u8> {
+    let chr = chr.strip_prefix("chr").unwrap_or(chr);
+    match chr {
+        "X" | "x" => Some(23),
+        "Y" | "y" => Some(24),
+        "XY" | "xy" => Some(25),
+        "MT" | "mt" | "M" | "m" => Some(26),
+        _ => chr.parse().ok(),
+    }
+}
+
+/// Read the variants of a `.bim` file.
+pub fn read_bim(path: &str) -> Result<Vec<Snp>> {
+    let mut snps = Vec::new();
+    for (i, line) in get_input_reader(path)?.lines().enumerate() {
+        let line = line?;
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.is_empty() {
+            continue;
+        }
+        if fields.len() != 6 {
+            bail!(
+                "{} line {}: expected 6 columns, found {}.",
+                path,
+                i + 1,
+                fields.len()
+            );
+        }
+        let err = |what: &str| anyhow!("{} line {}: invalid {}.", path, i + 1, what);
+        snps.push(Snp {
+            chr: parse_chr(fields[0]).ok_or_else(|| err("chromosome"))?,
+            snp: fields[1].to_string(),
+            cm: fields[2].parse().map_err(|_| err("genetic position"))?,
+            bp: fields[3].parse().map_err(|_| err("base-pair position"))?,
+            a1: fields[4].to_string(),
+            a2: fields[5].to_string(),
+        });
+    }
+    Ok(snps)
+}
+
+/// Read the individuals of a `.fam` file.
+pub fn read_fam(path: &str) -> Result<Vec<Individual>> {
+    let mut indivs = Vec::new();
+    for (i, line) in get_input_reader(path)?.lines().enumerate() {
+        let line = line?;
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.is_empty() {
+            continue;
+        }
+        if fields.len() != 6 {
+            bail!(
+                "{} line {}: expected 6 columns, found {}.",
+                path,
+                i + 1,
+                fields.len()
+            );
+        }
+        indivs.push(Individual {
+            fid: fields[0].to_string(),
+            iid: fields[1].to_string(),
+            father: fields[2].to_string(),
+            mother: fields[3].to_string(),
+            sex: fields[4]
+                .parse()
+                .map_err(|_| anyhow!("{} line {}: invalid sex.", path, i + 1))?,
+            phenotype: fields[5].to_string(),
+        });
+    }
+    Ok(indivs)
+}
+
+/// A memory-mapped SNP-major PLINK `.bed` file.
+///
+/// Like ldsc's `PlinkBEDFile`, genotypes are counted as A2 alleles, individuals can be
+/// subset and SNPs are filtered on MAF (monomorphic SNPs are always dropped), and blocks
+/// of SNPs are returned standardized, with missing calls imputed to the mean.
+pub struct PlinkBed {
+    mmap: Mmap,
+    /// Individuals in the `.fam` file.
+    n_indiv_total: usize,
+    bytes_per_snp: usize,
+    /// Indices into the `.fam` file of the individuals kept.
+    keep_indivs: Vec<usize>,
+    /// Indices into the `.bim` file of the SNPs kept.
+    kept_snps: Vec<usize>,
+    /// A2 frequency of each kept SNP.
+    freq: Vec<f64>,
+}
+
+impl PlinkBed {
+    /// Map `path`, a `.bed` file of `n_indiv` individuals and `n_snp` SNPs, keeping every
+    /// individual and every polymorphic SNP.
+    pub fn open(path: &str, n_indiv: usize, n_snp: usize) -> Result<Self> {
+        let file = File::open(path).map_err(|_| anyhow!("File not found: {:?}", path))?;
+        // SAFETY: the file is only read, and is not expected to change while mapped
+        let mmap = unsafe { Mmap::map(&file)? };
+        if mmap.len() < 3 || mmap[..3] != BED_MAGIC {
+            bail!("{} is not a SNP-major PLINK .bed file.", path);
+        }
+        let bytes_per_snp = n_indiv.div_ceil(4);
+        if mmap.len() != 3 + bytes_per_snp * n_snp {
+            bail!(
+                "{} has {} bytes, expected {} for {} individuals and {} SNPs.",
+                path,
+                mmap.len(),
+                3 + bytes_per_snp * n_snp,
+                n_indiv,
+                n_snp
+            );
+        }
+        let mut bed = PlinkBed {
+            mmap,
+            n_indiv_total: n_indiv,
+            bytes_per_snp,
+            keep_indivs: (0..n_indiv).collect(),
+            kept_snps: (0..n_snp).collect(),
+            freq: vec![],
+        };
+        bed.filter_snps(None, 0.0);
+        Ok(bed)
+    }
+
+    /// Keep only the individuals at `keep` (indices into the `.fam` file).
+    ///
+    /// Frequencies are recomputed and the MAF filter reapplied over the previously kept SNPs.
+    pub fn with_keep_indivs(mut self, keep: &[usize], maf_min: f64) -> Result<Self> {
+        if let Some(i) = keep.iter().find(|&&i| i >= self.n_indiv_total) {
+            bail!("Individual index {} is out of bounds.", i);
+        }
+        if keep.is_empty() {
+            bail!("No individuals retained for analysis.");
+        }
+        self.keep_indivs = keep.to_vec();
+        let kept = std::mem::take(&mut self.kept_snps);
+        self.filter_snps(Some(&kept), maf_min);
+        Ok(self)
+    }
+
+    /// Keep only the SNPs at `keep` (indices into the `.bim` file) with MAF above `maf_min`.
+    pub fn with_keep_snps(mut self, keep: &[usize], maf_min: f64) -> Result<Self> {
+        let n_snp = (self.mmap.len() - 3) / self.bytes_per_snp.max(1);
+        if let Some(j) = keep.iter().find(|&&j| j >= n_snp) {
+            bail!("SNP index {} is out of bounds.", j);
+        }
+        self.filter_snps(Some(keep), maf_min);
+        Ok(self)
+    }
+
+    fn filter_snps(&mut self, keep: Option<&[usize]>, maf_min: f64) {
+        let candidates = match keep {
+            Some(keep) => keep.to_vec(),
+            None => (0..(self.mmap.len() - 3) / self.bytes_per_snp.max(1)).collect(),
+        };
+        let n = self.n_indiv();
+        let freqs = candidates
+            .par_iter()
+            .map(|&j| {
+                let (sum, n_called, n_het) =
+                    self.genotypes(j)
+                        .flatten()
+                        .fold((0.0, 0, 0), |(sum, n_called, n_het), x| {
+                            (sum + x, n_called + 1, n_het + (x == 1.0) as usize)
+                        });
+                // SNPs where everyone is either het or missing are dropped too
+                let het_miss = n_het + n - n_called;
+                (sum / (2 * n_called) as f64, het_miss < n)
+            })
+            .collect::<Vec<_>>();
+        (self.kept_snps, self.freq) = candidates
+            .into_iter()
+            .zip(freqs)
+            .filter(|(_, (f, informative))| *informative && f.min(1.0 - f) > maf_min)
+            .map(|(j, (f, _))| (j, f))
+            .unzip();
+    }
+
+    /// Number of individuals kept.
+    pub fn n_indiv(&self) -> usize {
+        self.keep_indivs.len()
+    }
+
+    /// Indices into the `.bim` file of the SNPs kept, in file order.
+    pub fn kept_snps(&self) -> &[usize] {
+        &self.kept_snps
+    }
+
+    /// Indices into the `.fam` file of the individuals kept.
+    pub fn kept_indivs(&self) -> &[usize] {
+        &self.keep_indivs
+    }
+
+    /// A2 frequency of each kept SNP.
+    pub fn freq(&self) -> &[f64] {
+        &self.freq
+    }
+
+    /// Minor allele frequency of each kept SNP.
+    pub fn maf(&self) -> Vec<f64> {
+        self.freq.iter().map(|f| f.min(1.0 - f)).collect()
+    }
+
+    /// A2 counts of the kept individuals at SNP `j` of the `.bim` file.
+    fn genotypes(&self, j: usize) -> impl Iterator<Item = Option<f64>> + '_ {
+        let start = 3 + j * self.bytes_per_snp;
+        let bytes = &self.mmap[start..start + self.bytes_per_snp];
+        self.keep_indivs
+            .iter()
+            .map(move |&i| BED_CODE[((bytes[i / 4] >> (2 * (i % 4))) & 0b11) as usize])
+    }
+
+    /// Standardized genotypes of the kept SNPs `start..end` as an individuals x SNPs matrix.
+    ///
+    /// Missing calls are imputed to the SNP mean, so they are 0 after standardization.
+    pub fn standardized_block(&self, start: usize, end: usize) -> DMatrix<f64> {
+        let n = self.n_indiv();
+        let mut block = DMatrix::zeros(n, end - start);
+        block
+            .par_column_iter_mut()
+            .zip(self.kept_snps[start..end].par_iter())
+            .for_each(|(mut column, &j)| {
+                let (sum, n_called) = self
+                    .genotypes(j)
+                    .flatten()
+                    .fold((0.0, 0usize), |(sum, n), x| (sum + x, n + 1));
+                let avg = sum / n_called as f64;
+                for (x, g) in column.iter_mut().zip(self.genotypes(j)) {
+                    *x = g.unwrap_or(avg) - avg;
+                }
+                let sd = (column.norm_squared() / n as f64).sqrt();
+                column /= if sd > 0.0 { sd } else { 1.0 };
+            });
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    // 5 individuals, so each SNP takes 2 bytes
+    // snp0: 0 1 2 NA 0 -> codes 00 10 11 01 00
+    // snp1: monomorphic 2 2 2 2 2
+    // snp2: 1 1 0 0 NA
+    fn bed_bytes() -> Vec<u8> {
+        let snp0 = [0b01_11_10_00, 0b00];
+        let snp1 = [0b11_11_11_11, 0b11];
+        let snp2 = [0b00_00_10_10, 0b01];
+        [&BED_MAGIC[..], &snp0, &snp1, &snp2].concat()
+    }
+
+    #[test]
+    fn decodes_and_filters() {
+        let dir = TempDir::new();
+        let path = dir.write("decode.bed", bed_bytes());
+        let bed = PlinkBed::open(&path, 5, 3).unwrap();
+        assert_eq!(bed.kept_snps(), &[0, 2]);
+        assert_eq!(bed.freq(), &[3.0 / 8.0, 2.0 / 8.0]);
+        let g = bed.genotypes(0).collect::<Vec<_>>();
+        assert_eq!(g, [Some(0.0), Some(1.0), Some(2.0), None, Some(0.0)]);
+
+        let bed = bed.with_keep_indivs(&[2, 3, 4], 0.0).unwrap();
+        assert_eq!(bed.kept_snps(), &[0]);
+        assert!(PlinkBed::open(&path, 5, 4).is_err());
+    }
+
+    #[test]
+    fn standardizes_with_mean_imputation() {
+        let dir = TempDir::new();
+        let path = dir.write("std.bed", bed_bytes());
+        let bed = PlinkBed::open(&path, 5, 3).unwrap();
+        let block = bed.standardized_block(0, 2);
+        assert_eq!(block.shape(), (5, 2));
+        for column in block.column_iter() {
+            assert!(column.sum().abs() < 1e-12);
+            assert!((column.norm_squared() / 5.0 - 1.0).abs() < 1e-12);
+        }
+        // the missing call sits at the mean
+        assert_eq!(block[(3, 0)], 0.0);
+        assert_eq!(block[(4, 1)], 0.0);
+    }
+
+    #[test]
+    fn reads_bim_and_fam() {
+        let dir = TempDir::new();
+        let bim = dir.write("t.bim", b"1\trs1\t0\t100\tA\tG\nX rs2 0.5 200 C T\n");
+        let snps = read_bim(&bim).unwrap();
+        assert_eq!(snps[1].chr, 23);
+        assert_eq!(snps[1].cm, 0.5);
+        assert_eq!(snps[0].a2, "G");
+        let fam = dir.write("t.fam", b"f1 i1 0 0 1 -9\nf2 i2 0 0 2 1\n");
+        let indivs = read_fam(&fam).unwrap();
+        assert_eq!(indivs.len(), 2);
+        assert_eq!(indivs[1].sex, 2);
+        assert!(read_bim(&fam).is_err());
+    }
+}
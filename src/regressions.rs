@@ -0,0 +1,1182 @@
+use anyhow::{bail, Result};
+use nalgebra::{DMatrix, DVector, RowDVector};
+use statrs::distribution::{Continuous, ContinuousCDF, Normal};
+
+use crate::irwls::{irwls, weight};
+use crate::jackknife::{get_separators, separators_from_labels, Jackknife};
+use crate::stats::{p_from_t, p_from_z};
+
+/// Options of an LD score regression shared by every model.
+#[derive(Debug, Clone)]
+pub struct RegressionOptions {
+    /// Number of jackknife blocks.
+    pub n_blocks: usize,
+    /// Jackknife block of each SNP, instead of `n_blocks` blocks of equal numbers of SNPs.
+    pub blocks: Option<Vec<usize>>,
+    /// Constrain the intercept to this value instead of estimating it.
+    pub intercept: Option<f64>,
+    /// Cutoff on y for the first step of the two-step estimator.
+    pub twostep: Option<f64>,
+    /// Fit a single weighted regression with the initial weights instead of IRWLS.
+    pub old_weights: bool,
+}
+
+impl Default for RegressionOptions {
+    fn default() -> Self {
+        RegressionOptions {
+            n_blocks: 200,
+            blocks: None,
+            intercept: None,
+            twostep: None,
+            old_weights: false,
+        }
+    }
+}
+
+/// Regression weights of an LD score regression model (h2 or genetic covariance).
+pub trait Model {
+    /// Intercept under the null hypothesis of no confounding.
+    const NULL_INTERCEPT: f64;
+
+    /// Weights for the SNPs with LD scores `ld` given the current estimate `est` of the total
+    /// h2 (or genetic covariance) and the intercept; `ii` selects the SNPs of the first step
+    /// of the two-step estimator.
+    #[allow(clippy::too_many_arguments)]
+    fn weights(
+        &self,
+        ld: &DVector<f64>,
+        w_ld: &DVector<f64>,
+        n: &DVector<f64>,
+        m: f64,
+        est: f64,
+        intercept: Option<f64>,
+        ii: Option<&[bool]>,
+    ) -> Result<DVector<f64>>;
+
+    /// New IRWLS weights from the coefficients `coef` of the previous weighted fit, where the
+    /// intercept is the last coefficient unless it is constrained to `intercept`.
+    #[allow(clippy::too_many_arguments)]
+    fn update_func(
+        &self,
+        coef: &DVector<f64>,
+        ref_ld_tot: &DMatrix<f64>,
+        w_ld: &DVector<f64>,
+        n: &DVector<f64>,
+        m: f64,
+        nbar: f64,
+        intercept: Option<f64>,
+        ii: Option<&[bool]>,
+    ) -> Result<DVector<f64>> {
+        let est = m * coef[0] / nbar;
+        let intercept = match intercept {
+            Some(intercept) => {
+                if ref_ld_tot.ncols() > 1 {
+                    bail!(
+                        "Design matrix has intercept column for constrained intercept regression!"
+                    );
+                }
+                intercept
+            }
+            None => coef[1],
+        };
+        let ld = ref_ld_tot.column(0).into_owned();
+        self.weights(&ld, w_ld, n, m, est, Some(intercept), ii)
+    }
+}
+
+/// LD score regression of `y` on the LD scores, ldsc's `LD_Score_Regression`.
+///
+/// Coefficients are per-SNP h2 (or genetic covariance) of each annotation, `cat` the h2 of
+/// each category, `tot` the total, `prop` the proportion of h2 in each category and
+/// `enrichment` that proportion over the proportion of SNPs.
+#[derive(Debug, Clone)]
+pub struct LdScoreRegression {
+    pub n_annot: usize,
+    /// Number of jackknife blocks.
+    pub n_blocks: usize,
+    pub m: Vec<f64>,
+    pub constrain_intercept: bool,
+    pub intercept: f64,
+    /// `None` if the intercept is constrained.
+    pub intercept_se: Option<f64>,
+    /// Number of SNPs left out of the first step of the two-step estimator.
+    pub twostep_filtered: Option<usize>,
+    pub coef: Vec<f64>,
+    pub coef_cov: DMatrix<f64>,
+    pub coef_se: Vec<f64>,
+    pub cat: Vec<f64>,
+    pub cat_cov: DMatrix<f64>,
+    pub cat_se: Vec<f64>,
+    pub tot: f64,
+    pub tot_cov: f64,
+    pub tot_se: f64,
+    pub prop: Vec<f64>,
+    pub prop_cov: DMatrix<f64>,
+    pub prop_se: Vec<f64>,
+    pub enrichment: Vec<f64>,
+    pub m_prop: Vec<f64>,
+    pub jknife: Jackknife,
+    /// Jackknife delete values of `tot`.
+    pub tot_delete_values: Vec<f64>,
+    /// n_blocks x n_annot jackknife delete values of `coef`.
+    pub part_delete_values: DMatrix<f64>,
+    /// `None` if the intercept is constrained.
+    pub intercept_delete_values: Option<Vec<f64>>,
+}
+
+impl LdScoreRegression {
+    /// Fit `y` on the SNPs x annotations LD scores `x` with regression weight LD scores `w`,
+    /// sample sizes `n` and `m` SNPs per annotation.
+    #[allow(clippy::too_many_arguments)]
+    pub fn fit<T: Model>(
+        model: &T,
+        y: &DVector<f64>,
+        x: &DMatrix<f64>,
+        w: &DVector<f64>,
+        n: &DVector<f64>,
+        m: &[f64],
+        options: &RegressionOptions,
+        step1_ii: Option<&[bool]>,
+    ) -> Result<Self> {
+        let (n_snp, n_annot) = x.shape();
+        if y.len() != n_snp || w.len() != n_snp || n.len() != n_snp {
+            bail!("All arguments must have the same number of rows.");
+        }
+        if m.len() != n_annot {
+            bail!("M must have one entry per annotation.");
+        }
+        let n_blocks = options.n_blocks;
+        if options.blocks.as_ref().is_some_and(|b| b.len() != n_snp) {
+            bail!("Jackknife blocks must have one entry per SNP.");
+        }
+        let separators = options
+            .blocks
+            .as_deref()
+            .map(separators_from_labels)
+            .transpose()?;
+        let intercept = options.intercept;
+        let constrain_intercept = intercept.is_some();
+
+        let m_tot: f64 = m.iter().sum();
+        let mut x_tot = DMatrix::from_column_slice(n_snp, 1, x.column_sum().as_slice());
+        let tot_agg = aggregate(y, &x_tot, n, m_tot, intercept.unwrap_or(T::NULL_INTERCEPT));
+        let initial_w = model.weights(
+            &x_tot.column(0).into_owned(),
+            w,
+            n,
+            m_tot,
+            tot_agg,
+            intercept,
+            None,
+        )?;
+        // keep the condition number low
+        let nbar = n.mean();
+        let mut x = x.clone();
+        for (mut row, ni) in x.row_iter_mut().zip(n.iter()) {
+            row *= *ni;
+        }
+        x /= nbar;
+        let mut yp = y.clone();
+        match intercept {
+            None => {
+                x = append_intercept(&x);
+                x_tot = append_intercept(&x_tot);
+            }
+            Some(intercept) => yp.add_scalar_mut(-intercept),
+        }
+
+        let mut twostep_filtered = None;
+        let jknife = if let Some(ii) = step1_ii {
+            if constrain_intercept {
+                bail!("twostep is not compatible with constrain_intercept.");
+            } else if n_annot > 1 {
+                bail!("twostep not compatible with partitioned LD Score yet.");
+            }
+            let rows = (0..n_snp).filter(|&i| ii[i]).collect::<Vec<_>>();
+            twostep_filtered = Some(n_snp - rows.len());
+            let x1 = x.select_rows(&rows);
+            let yp1 = yp.select_rows(&rows);
+            let w1 = w.select_rows(&rows);
+            let n1 = n.select_rows(&rows);
+            let initial_w1 = initial_w.select_rows(&rows);
+            // as in ldsc, the first step weights use the N-scaled LD scores
+            let update_func1 = |coef: &DVector<f64>| {
+                model.update_func(coef, &x1, &w1, &n1, m_tot, nbar, None, Some(ii))
+            };
+            let separators1 = options
+                .blocks
+                .as_ref()
+                .map(|b| separators_from_labels(&rows.iter().map(|&i| b[i]).collect::<Vec<_>>()))
+                .transpose()?;
+            let step1 = irwls(&x1, &yp1, update_func1, n_blocks, &initial_w1, separators1)?;
+            let step1_int = step1.est[n_annot];
+            yp.add_scalar_mut(-step1_int);
+            x = remove_intercept(&x);
+            x_tot = remove_intercept(&x_tot);
+            let update_func2 = |coef: &DVector<f64>| {
+                model.update_func(coef, &x_tot, w, n, m_tot, nbar, Some(step1_int), None)
+            };
+            let separators = update_separators(&step1.separators, ii);
+            let step2 = irwls(
+                &x,
+                &yp,
+                update_func2,
+                n_blocks,
+                &initial_w,
+                Some(separators),
+            )?;
+            let c = initial_w.component_mul(&x.column(0)).sum()
+                / initial_w
+                    .component_mul(&x.column(0).component_mul(&x.column(0)))
+                    .sum();
+            combine_twostep_jknives(&step1, &step2, c)
+        } else if options.old_weights {
+            let initial_w = initial_w.map(f64::sqrt);
+            let x = weight(&x, &initial_w)?;
+            let y = weight(
+                &DMatrix::from_column_slice(n_snp, 1, yp.as_slice()),
+                &initial_w,
+            )?;
+            let separators = match separators {
+                Some(s) => s,
+                None => get_separators(n_snp, n_blocks)?,
+            };
+            Jackknife::lstsq(&x, &y.column(0).into_owned(), separators)?
+        } else {
+            let update_func = |coef: &DVector<f64>| {
+                model.update_func(coef, &x_tot, w, n, m_tot, nbar, intercept, None)
+            };
+            irwls(&x, &yp, update_func, n_blocks, &initial_w, separators)?
+        };
+
+        let est = &jknife.est;
+        let dv = jknife.delete_values.columns(0, n_annot);
+        let n_blocks = dv.nrows();
+        let coef = (0..n_annot).map(|j| est[j] / nbar).collect::<Vec<_>>();
+        let coef_cov = jknife.jknife_cov.view((0, 0), (n_annot, n_annot)) / nbar.powi(2);
+        let coef_se = diag_sqrt(&coef_cov);
+        let cat = m.iter().zip(&coef).map(|(m, c)| m * c).collect::<Vec<_>>();
+        let mm = DMatrix::from_fn(n_annot, n_annot, |i, j| m[i] * m[j]);
+        let cat_cov = mm.component_mul(&coef_cov);
+        let cat_se = diag_sqrt(&cat_cov);
+        let tot: f64 = cat.iter().sum();
+        let tot_cov = cat_cov.sum();
+        let tot_se = tot_cov.sqrt();
+
+        let numer_delete_values =
+            DMatrix::from_fn(n_blocks, n_annot, |b, j| m[j] * dv[(b, j)] / nbar);
+        let denom = numer_delete_values.column_sum();
+        let denom_delete_values = DMatrix::from_fn(n_blocks, n_annot, |b, _| denom[b]);
+        let prop = Jackknife::ratio(
+            RowDVector::from_iterator(n_annot, cat.iter().map(|c| c / tot)),
+            &numer_delete_values,
+            &denom_delete_values,
+        );
+        let enrichment = cat
+            .iter()
+            .zip(m)
+            .map(|(c, m)| (c / m) / (tot / m_tot))
+            .collect();
+        let m_prop = m.iter().map(|m| m / m_tot).collect();
+
+        let (intercept, intercept_se, intercept_delete_values) = match intercept {
+            Some(intercept) => (intercept, None, None),
+            None => (
+                est[n_annot],
+                Some(jknife.jknife_se[n_annot]),
+                Some(
+                    jknife
+                        .delete_values
+                        .column(n_annot)
+                        .iter()
+                        .copied()
+                        .collect(),
+                ),
+            ),
+        };
+        let tot_delete_values = (0..n_blocks)
+            .map(|b| (0..n_annot).map(|j| dv[(b, j)] * m[j]).sum::<f64>() / nbar)
+            .collect();
+        let part_delete_values = dv / nbar;
+
+        Ok(LdScoreRegression {
+            n_annot,
+            n_blocks,
+            m: m.to_vec(),
+            constrain_intercept,
+            intercept,
+            intercept_se,
+            twostep_filtered,
+            coef,
+            coef_cov,
+            coef_se,
+            cat,
+            cat_cov,
+            cat_se,
+            tot,
+            tot_cov,
+            tot_se,
+            prop: prop.est.iter().copied().collect(),
+            prop_se: prop.jknife_se.iter().copied().collect(),
+            prop_cov: prop.jknife_cov,
+            enrichment,
+            m_prop,
+            jknife,
+            tot_delete_values,
+            part_delete_values,
+            intercept_delete_values,
+        })
+    }
+}
+
+/// Moment estimate of the total h2 (or genetic covariance) used for the initial weights.
+fn aggregate(y: &DVector<f64>, x: &DMatrix<f64>, n: &DVector<f64>, m: f64, intercept: f64) -> f64 {
+    let num = m * (y.mean() - intercept);
+    let denom = x.column(0).component_mul(n).mean();
+    num / denom
+}
+
+fn append_intercept(x: &DMatrix<f64>) -> DMatrix<f64> {
+    let ncols = x.ncols();
+    x.clone().insert_column(ncols, 1.0)
+}
+
+fn remove_intercept(x: &DMatrix<f64>) -> DMatrix<f64> {
+    x.clone().remove_column(x.ncols() - 1)
+}
+
+fn diag_sqrt(cov: &DMatrix<f64>) -> Vec<f64> {
+    cov.diagonal().iter().map(|v| v.sqrt()).collect()
+}
+
+/// Map jackknife separators `s` over the SNPs selected by `ii` back to all SNPs.
+pub fn update_separators(s: &[usize], ii: &[bool]) -> Vec<usize> {
+    let maplist = (0..ii.len()).filter(|&i| ii[i]).collect::<Vec<_>>();
+    let mut t = vec![0];
+    t.extend(s[1..s.len() - 1].iter().map(|&i| maplist[i]));
+    t.push(ii.len());
+    t
+}
+
+/// Combine the jackknives of the two steps: the slope from the second step, corrected for
+/// the variance of the intercept estimated in the first.
+fn combine_twostep_jknives(step1: &Jackknife, step2: &Jackknife, c: f64) -> Jackknife {
+    let n_blocks = step1.delete_values.nrows();
+    let n_annot = step1.delete_values.ncols() - 1;
+    let step1_int = step1.est[n_annot];
+    let est = RowDVector::from_vec(vec![step2.est[0], step1_int]);
+    let delete_values = DMatrix::from_fn(n_blocks, n_annot + 1, |b, j| {
+        let int_dv = step1.delete_values[(b, n_annot)];
+        if j == n_annot {
+            int_dv
+        } else {
+            step2.delete_values[(b, j)] - c * (int_dv - step1_int)
+        }
+    });
+    Jackknife::from_delete_values(est, delete_values, step2.separators.clone())
+}
+
+/// Regression weights for h2 estimation.
+pub struct HsqModel;
+
+impl Model for HsqModel {
+    const NULL_INTERCEPT: f64 = 1.0;
+
+    fn weights(
+        &self,
+        ld: &DVector<f64>,
+        w_ld: &DVector<f64>,
+        n: &DVector<f64>,
+        m: f64,
+        hsq: f64,
+        intercept: Option<f64>,
+        _ii: Option<&[bool]>,
+    ) -> Result<DVector<f64>> {
+        let intercept = intercept.unwrap_or(Self::NULL_INTERCEPT);
+        let hsq = hsq.clamp(0.0, 1.0);
+        Ok(DVector::from_fn(ld.len(), |i, _| {
+            let ld = ld[i].max(1.0);
+            let w_ld = w_ld[i].max(1.0);
+            let c = hsq * n[i] / m;
+            let het_w = 1.0 / (2.0 * (intercept + c * ld).powi(2));
+            let oc_w = 1.0 / w_ld;
+            het_w * oc_w
+        }))
+    }
+}
+
+/// Sample and population prevalence of a binary trait, for the conversion of h2 from the
+/// observed to the liability scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prevalence {
+    /// Fraction of cases in the sample (P).
+    pub samp: f64,
+    /// Fraction of cases in the population (K).
+    pub pop: f64,
+}
+
+impl Prevalence {
+    pub fn new(samp: f64, pop: f64) -> Result<Self> {
+        if !(pop > 0.0 && pop < 1.0) {
+            bail!("K must be in the range (0,1)");
+        }
+        if !(samp > 0.0 && samp < 1.0) {
+            bail!("P must be in the range (0,1)");
+        }
+        Ok(Prevalence { samp, pop })
+    }
+
+    /// Factor from observed- to liability-scale h2 of Lee et al. (2011), ldsc's
+    /// `h2_obs_to_liab(1, P, K)`: K^2 (1 - K)^2 / (P (1 - P) phi(t)^2), with t the liability
+    /// threshold.
+    pub fn h2_liab_factor(&self) -> f64 {
+        let (p, k) = (self.samp, self.pop);
+        let normal = Normal::new(0.0, 1.0).unwrap();
+        let thresh = normal.inverse_cdf(1.0 - k);
+        k.powi(2) * (1.0 - k).powi(2) / (p * (1.0 - p) * normal.pdf(thresh).powi(2))
+    }
+}
+
+/// Factor from observed- to liability-scale gencov, ldsc's `gencov_obs_to_liab(1, ...)`:
+/// the product of the square roots of the h2 factors of the traits with a prevalence.
+pub fn gencov_liab_factor(prevalences: [Option<Prevalence>; 2]) -> f64 {
+    prevalences
+        .iter()
+        .flatten()
+        .map(|p| p.h2_liab_factor().sqrt())
+        .product()
+}
+
+/// The observed scale, and the liability scale with conversion `factor` if any.
+fn scales(factor: Option<f64>) -> Vec<(&'static str, f64)> {
+    let mut scales = vec![("Observed", 1.0)];
+    if let Some(factor) = factor {
+        scales.push(("Liability", factor));
+    }
+    scales
+}
+
+/// SNP heritability by LD score regression of chi^2 statistics, ldsc's `Hsq`.
+#[derive(Debug, Clone)]
+pub struct Hsq {
+    pub reg: LdScoreRegression,
+    pub mean_chisq: f64,
+    pub lambda_gc: f64,
+    /// (intercept - 1) / (mean chi^2 - 1), `None` if the intercept is constrained or the mean
+    /// chi^2 is not above 1.
+    pub ratio: Option<f64>,
+    pub ratio_se: Option<f64>,
+}
+
+// median of a chi^2 distribution with 1 degree of freedom
+const MEDIAN_CHISQ: f64 = 0.4549;
+
+impl Hsq {
+    pub fn new(
+        chisq: &DVector<f64>,
+        x: &DMatrix<f64>,
+        w: &DVector<f64>,
+        n: &DVector<f64>,
+        m: &[f64],
+        options: &RegressionOptions,
+    ) -> Result<Self> {
+        let step1_ii = options
+            .twostep
+            .map(|cutoff| chisq.iter().map(|c| *c < cutoff).collect::<Vec<_>>());
+        let reg =
+            LdScoreRegression::fit(&HsqModel, chisq, x, w, n, m, options, step1_ii.as_deref())?;
+        let mean_chisq = chisq.mean();
+        let lambda_gc = median(chisq.as_slice()) / MEDIAN_CHISQ;
+        let (ratio, ratio_se) = match reg.intercept_se {
+            Some(se) if mean_chisq > 1.0 => (
+                Some((reg.intercept - 1.0) / (mean_chisq - 1.0)),
+                Some(se / (mean_chisq - 1.0)),
+            ),
+            _ => (None, None),
+        };
+        Ok(Hsq {
+            reg,
+            mean_chisq,
+            lambda_gc,
+            ratio,
+            ratio_se,
+        })
+    }
+
+    /// Summary of the regression in the words and number format of ldsc.
+    /// With `prevalence`, h2 is also given on the liability scale. With `overlap`, the
+    /// per-category results are left to [`Hsq::overlap_output`].
+    pub fn summary(
+        &self,
+        ref_ld_colnames: &[String],
+        prevalence: Option<Prevalence>,
+        overlap: bool,
+    ) -> String {
+        let reg = &self.reg;
+        let scales = scales(prevalence.map(|p| p.h2_liab_factor()));
+        let mut out = Vec::new();
+        for (t, c) in &scales {
+            out.push(format!(
+                "Total {} scale h2: {} ({})",
+                t,
+                np_str(&[c * reg.tot]),
+                np_str(&[c * reg.tot_se])
+            ));
+        }
+        if reg.n_annot > 1 {
+            out.push(format!("Categories: {}", ref_ld_colnames.join(" ")));
+        }
+        if reg.n_annot > 1 && !overlap {
+            for (t, c) in &scales {
+                let scale = |v: &[f64]| v.iter().map(|x| c * x).collect::<Vec<_>>();
+                out.push(format!("{} scale h2: {}", t, np_str(&scale(&reg.cat))));
+                out.push(format!(
+                    "{} scale h2 SE: {}",
+                    t,
+                    np_str(&scale(&reg.cat_se))
+                ));
+            }
+            out.push(format!("Proportion of SNPs: {}", np_str(&reg.m_prop)));
+            out.push(format!("Proportion of h2g: {}", np_str(&reg.prop)));
+            out.push(format!("Enrichment: {}", np_str(&reg.enrichment)));
+            out.push(format!("Coefficients: {}", np_str(&reg.coef)));
+            out.push(format!("Coefficient SE: {}", np_str(&reg.coef_se)));
+        }
+        out.push(format!("Lambda GC: {}", np_str(&[self.lambda_gc])));
+        out.push(format!("Mean Chi^2: {}", np_str(&[self.mean_chisq])));
+        match reg.intercept_se {
+            None => out.push(format!(
+                "Intercept: constrained to {}",
+                np_str(&[reg.intercept])
+            )),
+            Some(se) => {
+                out.push(format!(
+                    "Intercept: {} ({})",
+                    np_str(&[reg.intercept]),
+                    np_str(&[se])
+                ));
+                match (self.ratio, self.ratio_se) {
+                    (Some(ratio), _) if ratio < 0.0 => {
+                        out.push("Ratio < 0 (usually indicates GC correction).".to_string())
+                    }
+                    (Some(ratio), Some(ratio_se)) => out.push(format!(
+                        "Ratio: {} ({})",
+                        np_str(&[ratio]),
+                        np_str(&[ratio_se])
+                    )),
+                    _ => out.push("Ratio: NA (mean chi^2 < 1)".to_string()),
+                }
+            }
+        }
+        out.join("\n")
+    }
+}
+
+/// Per-category results of partitioned h2 with overlapping annotations, the rows of ldsc's
+/// `.results` file.
+#[derive(Debug, Clone)]
+pub struct OverlapResults {
+    pub category: Vec<String>,
+    pub prop_snps: Vec<f64>,
+    pub prop_h2: Vec<f64>,
+    pub prop_h2_se: Vec<f64>,
+    pub enrichment: Vec<f64>,
+    pub enrichment_se: Vec<f64>,
+    /// P-value of the difference in per-SNP h2 between the SNPs in and out of the category,
+    /// `None` if its SE is 0.
+    pub enrichment_p: Vec<Option<f64>>,
+    pub coef: Vec<f64>,
+    pub coef_se: Vec<f64>,
+    pub coef_z: Vec<f64>,
+}
+
+impl Hsq {
+    /// Proportion of h2 and enrichment of each category counting the h2 of the SNPs it shares
+    /// with the other categories, ldsc's `_overlap_output`. `overlap` is the annotations x
+    /// annotations matrix of the number of SNPs in both categories, `m_tot` the number of SNPs.
+    pub fn overlap_output(
+        &self,
+        category_names: &[String],
+        overlap: &DMatrix<f64>,
+        m_annot: &[f64],
+        m_tot: f64,
+    ) -> OverlapResults {
+        let reg = &self.reg;
+        let n_annot = reg.n_annot;
+        let overlap_prop = DMatrix::from_fn(n_annot, n_annot, |i, j| overlap[(i, j)] / m_annot[j]);
+        let prop = DVector::from_column_slice(&reg.prop);
+        let prop_h2 = &overlap_prop * prop;
+        let prop_h2_var = (&overlap_prop * &reg.prop_cov * overlap_prop.transpose()).diagonal();
+        let prop_snps = m_annot.iter().map(|m| m / m_tot).collect::<Vec<_>>();
+
+        // per-SNP h2 in the category minus per-SNP h2 out of it
+        let overlap_diff = DMatrix::from_fn(n_annot, n_annot, |i, j| match m_tot == m_annot[i] {
+            true => 0.0,
+            false => {
+                overlap[(i, j)] / m_annot[i] - (m_annot[j] - overlap[(i, j)]) / (m_tot - m_annot[i])
+            }
+        });
+        let coef = DVector::from_column_slice(&reg.coef);
+        let diff_est = &overlap_diff * coef;
+        let diff_se = (&overlap_diff * &reg.coef_cov * overlap_diff.transpose())
+            .diagonal()
+            .map(f64::sqrt);
+        let enrichment_p = diff_est
+            .iter()
+            .zip(diff_se.iter())
+            .map(|(est, se)| match *se == 0.0 {
+                true => None,
+                false => Some(p_from_t(est / se, reg.n_blocks as f64)),
+            })
+            .collect();
+
+        OverlapResults {
+            category: category_names.to_vec(),
+            prop_h2: prop_h2.iter().cloned().collect(),
+            prop_h2_se: prop_h2_var.iter().map(|v| v.max(0.0).sqrt()).collect(),
+            enrichment: prop_h2.iter().zip(&prop_snps).map(|(h, m)| h / m).collect(),
+            enrichment_se: prop_h2_var
+                .iter()
+                .zip(&prop_snps)
+                .map(|(v, m)| v.max(0.0).sqrt() / m)
+                .collect(),
+            enrichment_p,
+            coef: reg.coef.clone(),
+            coef_se: reg.coef_se.clone(),
+            coef_z: reg
+                .coef
+                .iter()
+                .zip(&reg.coef_se)
+                .map(|(c, se)| c / se)
+                .collect(),
+            prop_snps,
+        }
+    }
+}
+
+/// Regression weights for genetic covariance estimation, which depend on the h2 and
+/// intercepts of both traits.
+pub struct GencovModel {
+    pub n1: DVector<f64>,
+    pub n2: DVector<f64>,
+    pub hsq1: f64,
+    pub hsq2: f64,
+    pub intercept_hsq1: f64,
+    pub intercept_hsq2: f64,
+}
+
+impl Model for GencovModel {
+    const NULL_INTERCEPT: f64 = 0.0;
+
+    fn weights(
+        &self,
+        ld: &DVector<f64>,
+        w_ld: &DVector<f64>,
+        _n: &DVector<f64>,
+        m: f64,
+        rho_g: f64,
+        intercept: Option<f64>,
+        ii: Option<&[bool]>,
+    ) -> Result<DVector<f64>> {
+        let (n1, n2) = match ii {
+            Some(ii) => {
+                let rows = (0..ii.len()).filter(|&i| ii[i]).collect::<Vec<_>>();
+                (self.n1.select_rows(&rows), self.n2.select_rows(&rows))
+            }
+            None => (self.n1.clone(), self.n2.clone()),
+        };
+        let intercept_gencov = intercept.unwrap_or(Self::NULL_INTERCEPT);
+        let (h1, h2) = (self.hsq1.clamp(0.0, 1.0), self.hsq2.clamp(0.0, 1.0));
+        let rho_g = rho_g.clamp(-1.0, 1.0);
+        Ok(DVector::from_fn(ld.len(), |i, _| {
+            let ld = ld[i].max(1.0);
+            let w_ld = w_ld[i].max(1.0);
+            let a = n1[i] * (h1 * ld) / m + self.intercept_hsq1;
+            let b = n2[i] * (h2 * ld) / m + self.intercept_hsq2;
+            let sqrt_n1n2 = (n1[i] * n2[i]).sqrt();
+            let c = sqrt_n1n2 * (rho_g * ld) / m + intercept_gencov;
+            let het_w = 1.0 / (a * b + c * c);
+            let oc_w = 1.0 / w_ld;
+            het_w * oc_w
+        }))
+    }
+}
+
+/// Z-score and two-sided p-value of an estimate with standard error `se`.
+pub fn p_z_norm(est: f64, se: f64) -> (f64, f64) {
+    let z = est / se;
+    (p_from_z(z), z)
+}
+
+/// Genetic covariance by LD score regression of z1 * z2, ldsc's `Gencov`.
+#[derive(Debug, Clone)]
+pub struct Gencov {
+    pub reg: LdScoreRegression,
+    pub p: f64,
+    pub z: f64,
+    pub mean_z1z2: f64,
+}
+
+impl Gencov {
+    pub fn new(
+        z1: &DVector<f64>,
+        z2: &DVector<f64>,
+        x: &DMatrix<f64>,
+        w: &DVector<f64>,
+        m: &[f64],
+        model: &GencovModel,
+        options: &RegressionOptions,
+    ) -> Result<Self> {
+        let y = z1.component_mul(z2);
+        let step1_ii = options.twostep.map(|cutoff| {
+            z1.iter()
+                .zip(z2.iter())
+                .map(|(z1, z2)| z1 * z1 < cutoff && z2 * z2 < cutoff)
+                .collect::<Vec<_>>()
+        });
+        let sqrt_n1n2 = model.n1.component_mul(&model.n2).map(f64::sqrt);
+        let reg =
+            LdScoreRegression::fit(model, &y, x, w, &sqrt_n1n2, m, options, step1_ii.as_deref())?;
+        let (p, z) = p_z_norm(reg.tot, reg.tot_se);
+        Ok(Gencov {
+            reg,
+            p,
+            z,
+            mean_z1z2: y.mean(),
+        })
+    }
+
+    /// Summary of the regression in the words and number format of ldsc.
+    /// With the `prevalences` of either trait, gencov is also given on the liability scale.
+    pub fn summary(
+        &self,
+        ref_ld_colnames: &[String],
+        prevalences: [Option<Prevalence>; 2],
+    ) -> String {
+        let reg = &self.reg;
+        let factor = match prevalences {
+            [None, None] => None,
+            _ => Some(gencov_liab_factor(prevalences)),
+        };
+        let scales = scales(factor);
+        let mut out = Vec::new();
+        for (t, c) in &scales {
+            out.push(format!(
+                "Total {} scale gencov: {} ({})",
+                t,
+                np_str(&[c * reg.tot]),
+                np_str(&[c * reg.tot_se])
+            ));
+        }
+        if reg.n_annot > 1 {
+            out.push(format!("Categories: {}", ref_ld_colnames.join(" ")));
+            for (t, c) in &scales {
+                let scale = |v: &[f64]| v.iter().map(|x| c * x).collect::<Vec<_>>();
+                out.push(format!("{} scale gencov: {}", t, np_str(&scale(&reg.cat))));
+                out.push(format!(
+                    "{} scale gencov SE: {}",
+                    t,
+                    np_str(&scale(&reg.cat_se))
+                ));
+            }
+            out.push(format!("Proportion of SNPs: {}", np_str(&reg.m_prop)));
+            out.push(format!("Proportion of gencov: {}", np_str(&reg.prop)));
+            out.push(format!("Enrichment: {}", np_str(&reg.enrichment)));
+        }
+        out.push(format!("Mean z1*z2: {}", np_str(&[self.mean_z1z2])));
+        match reg.intercept_se {
+            None => out.push(format!(
+                "Intercept: constrained to {}",
+                np_str(&[reg.intercept])
+            )),
+            Some(se) => out.push(format!(
+                "Intercept: {} ({})",
+                np_str(&[reg.intercept]),
+                np_str(&[se])
+            )),
+        }
+        out.join("\n")
+    }
+}
+
+/// Genetic correlation estimate and its block jackknife SE.
+#[derive(Debug, Clone)]
+pub struct RgEstimate {
+    pub rg_ratio: f64,
+    pub rg_jknife: f64,
+    /// Jackknife delete values of `rg_ratio`.
+    pub delete_values: Vec<f64>,
+    pub rg_se: f64,
+    pub z: f64,
+    pub p: f64,
+}
+
+// |rg| above which the estimate is reported as out of bounds
+const MAX_ABS_RG: f64 = 1.2;
+
+/// Genetic correlation of two traits, ldsc's `RG`: h2 of each trait, their genetic
+/// covariance, and rg = gencov / sqrt(h2_1 * h2_2) with a ratio jackknife SE.
+#[derive(Debug, Clone)]
+pub struct Rg {
+    pub hsq1: Hsq,
+    pub hsq2: Hsq,
+    pub gencov: Gencov,
+    pub intercept_gencov: Option<f64>,
+    /// `None` if either h2 is not positive.
+    pub rg: Option<RgEstimate>,
+}
+
+impl Rg {
+    /// Estimate rg from the z-scores and sample sizes of both traits. `intercepts` are the
+    /// constrained intercepts (if any) of the h2 of each trait and of the genetic covariance;
+    /// the intercept of `options` is ignored.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        z1: &DVector<f64>,
+        z2: &DVector<f64>,
+        x: &DMatrix<f64>,
+        w: &DVector<f64>,
+        n1: &DVector<f64>,
+        n2: &DVector<f64>,
+        m: &[f64],
+        intercepts: [Option<f64>; 3],
+        options: &RegressionOptions,
+    ) -> Result<Self> {
+        let with_intercept = |intercept| RegressionOptions {
+            intercept,
+            ..options.clone()
+        };
+        let chisq1 = z1.map(|z| z * z);
+        let chisq2 = z2.map(|z| z * z);
+        let hsq1 = Hsq::new(&chisq1, x, w, n1, m, &with_intercept(intercepts[0]))?;
+        let hsq2 = Hsq::new(&chisq2, x, w, n2, m, &with_intercept(intercepts[1]))?;
+        let model = GencovModel {
+            n1: n1.clone(),
+            n2: n2.clone(),
+            hsq1: hsq1.reg.tot,
+            hsq2: hsq2.reg.tot,
+            intercept_hsq1: hsq1.reg.intercept,
+            intercept_hsq2: hsq2.reg.intercept,
+        };
+        let gencov = Gencov::new(z1, z2, x, w, m, &model, &with_intercept(intercepts[2]))?;
+
+        let rg = if hsq1.reg.tot <= 0.0 || hsq2.reg.tot <= 0.0 {
+            None
+        } else {
+            let rg_ratio = gencov.reg.tot / (hsq1.reg.tot * hsq2.reg.tot).sqrt();
+            let n_blocks = gencov.reg.tot_delete_values.len();
+            let numer = DMatrix::from_column_slice(n_blocks, 1, &gencov.reg.tot_delete_values);
+            let denom = DMatrix::from_fn(n_blocks, 1, |b, _| {
+                (hsq1.reg.tot_delete_values[b] * hsq2.reg.tot_delete_values[b]).sqrt()
+            });
+            let jknife = Jackknife::ratio(RowDVector::from_element(1, rg_ratio), &numer, &denom);
+            let rg_se = jknife.jknife_se[0];
+            let (p, z) = p_z_norm(rg_ratio, rg_se);
+            Some(RgEstimate {
+                rg_ratio,
+                rg_jknife: jknife.jknife_est[0],
+                delete_values: jknife.delete_values.iter().copied().collect(),
+                rg_se,
+                z,
+                p,
+            })
+        };
+        Ok(Rg {
+            hsq1,
+            hsq2,
+            gencov,
+            intercept_gencov: intercepts[2],
+            rg,
+        })
+    }
+
+    /// Summary of the genetic correlation in the words and number format of ldsc.
+    pub fn summary(&self) -> String {
+        let mut out = Vec::new();
+        match &self.rg {
+            None => {
+                out.push("Genetic Correlation: nan (nan) (h2  out of bounds) ".to_string());
+                out.push("Z-score: nan (nan) (h2  out of bounds)".to_string());
+                out.push("P: nan (nan) (h2  out of bounds)".to_string());
+                out.push("WARNING: One of the h2's was out of bounds.".to_string());
+                out.push(
+                    "This usually indicates a data-munging error or that h2 or N is low."
+                        .to_string(),
+                );
+            }
+            Some(rg) if rg.rg_ratio.abs() > MAX_ABS_RG => {
+                out.push("Genetic Correlation: nan (nan) (rg out of bounds) ".to_string());
+                out.push("Z-score: nan (nan) (rg out of bounds)".to_string());
+                out.push("P: nan (nan) (rg out of bounds)".to_string());
+                out.push("WARNING: rg was out of bounds.".to_string());
+                match self.intercept_gencov {
+                    None => out.push(
+                        "This often means that h2 is not significantly different from zero."
+                            .to_string(),
+                    ),
+                    Some(_) => out.push(
+                        "This often means that you have constrained the intercepts to the wrong values."
+                            .to_string(),
+                    ),
+                }
+            }
+            Some(rg) => {
+                out.push(format!(
+                    "Genetic Correlation: {} ({})",
+                    np_str(&[rg.rg_ratio]),
+                    np_str(&[rg.rg_se])
+                ));
+                out.push(format!("Z-score: {}", np_str(&[rg.z])));
+                out.push(format!("P: {}", np_str(&[rg.p])));
+            }
+        }
+        out.join("\n").trim().to_string()
+    }
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let k = sorted.len();
+    if k % 2 == 1 {
+        sorted[k / 2]
+    } else {
+        (sorted[k / 2 - 1] + sorted[k / 2]) / 2.0
+    }
+}
+
+// digits after the decimal point ldsc prints (np.set_printoptions(precision=4))
+const PRINT_PRECISION: usize = 4;
+
+/// Format `values` like ldsc does, i.e. like numpy prints a matrix with precision 4 (legacy
+/// printing of the numpy ldsc runs with) once the brackets are removed: a common fixed-point
+/// format with trailing zeros blanked, or a common scientific format if the values span more
+/// than three orders of magnitude or are below 1e-4.
+pub fn np_str(values: &[f64]) -> String {
+    let non_zero = values
+        .iter()
+        .filter(|x| x.is_finite() && **x != 0.0)
+        .map(|x| x.abs())
+        .collect::<Vec<_>>();
+    let (max_val, min_val) = match non_zero.is_empty() {
+        true => (0.0, 0.0),
+        false => (
+            non_zero.iter().cloned().fold(f64::MIN, f64::max),
+            non_zero.iter().cloned().fold(f64::MAX, f64::min),
+        ),
+    };
+    let exp_format =
+        !non_zero.is_empty() && (max_val >= 1e8 || min_val < 0.0001 || max_val / min_val > 1000.0);
+    let special = values.iter().any(|x| !x.is_finite());
+
+    let (width, precision) = if exp_format {
+        let large_exponent = (0.0 < min_val && min_val < 1e-99) || max_val >= 1e100;
+        (
+            8 + PRINT_PRECISION + large_exponent as usize,
+            PRINT_PRECISION,
+        )
+    } else {
+        let precision = non_zero
+            .iter()
+            .map(|x| {
+                let s = format!("{:.*}", PRINT_PRECISION, x);
+                PRINT_PRECISION - (s.len() - s.trim_end_matches('0').len())
+            })
+            .max()
+            .unwrap_or(0);
+        let mut width = (max_val.trunc() as i64).to_string().len() + precision + 2;
+        if special {
+            width = width.max(4);
+        }
+        (width, precision)
+    };
+
+    let formatted = values
+        .iter()
+        .map(|x| {
+            if x.is_nan() {
+                format!("{:>width$}", "nan")
+            } else if x.is_infinite() {
+                let s = if *x > 0.0 { "inf" } else { "-inf" };
+                format!("{:>width$}", s)
+            } else if exp_format {
+                let s = format!("{:.*e}", precision, x);
+                let (mantissa, exponent) = s.split_once('e').unwrap();
+                let exponent = exponent.parse::<i32>().unwrap();
+                let sign = if exponent < 0 { '-' } else { '+' };
+                let digits = if width > 8 + PRINT_PRECISION { 3 } else { 2 };
+                let s = format!("{}e{}{:0digits$}", mantissa, sign, exponent.abs());
+                format!("{:>width$}", s)
+            } else {
+                let mut s = format!("{:.*}", precision, x);
+                if precision == 0 {
+                    s.push('.');
+                }
+                // trailing zeros are blanked, keeping the column width
+                let z = s.trim_end_matches('0');
+                let s = format!("{}{}", z, " ".repeat(s.len() - z.len()));
+                format!("{:>width$}", s)
+            }
+        })
+        .collect::<Vec<_>>();
+    formatted.join(" ").trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_liability_scale() {
+        // with P = K = 0.5 the threshold is 0 and the factor is 0.25^2 / (0.25 phi(0)^2) = pi / 2
+        let prev = Prevalence::new(0.5, 0.5).unwrap();
+        assert!((prev.h2_liab_factor() - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+        let other = Prevalence::new(0.3, 0.01).unwrap();
+        let factor = gencov_liab_factor([Some(prev), Some(other)]);
+        assert!((factor.powi(2) - prev.h2_liab_factor() * other.h2_liab_factor()).abs() < 1e-9);
+        assert_eq!(gencov_liab_factor([None, None]), 1.0);
+        assert!(Prevalence::new(0.5, 1.0).is_err());
+    }
+
+    #[test]
+    fn formats_numbers_like_ldsc() {
+        assert_eq!(np_str(&[0.10683]), "0.1068");
+        assert_eq!(np_str(&[1.0]), "1.");
+        assert_eq!(np_str(&[1.5, 2.25]), "1.5   2.25");
+        assert_eq!(np_str(&[-0.01234]), "-0.0123");
+        assert_eq!(np_str(&[1.234e-5]), "1.2340e-05");
+        assert_eq!(np_str(&[0.5, 2000.0]), "5.0000e-01   2.0000e+03");
+        assert_eq!(np_str(&[0.0]), "0.");
+        assert_eq!(np_str(&[f64::NAN]), "nan");
+    }
+
+    #[test]
+    fn maps_separators_back_to_all_snps() {
+        let ii = [true, false, true, true, false, true];
+        assert_eq!(update_separators(&[0, 2, 4], &ii), [0, 3, 6]);
+    }
+
+    #[test]
+    fn recovers_simulated_h2() {
+        // chi^2 = intercept + N h2 ld / M exactly, so the fit is exact whatever the weights
+        let n_snp = 1000;
+        let ld = DVector::from_fn(n_snp, |i, _| 1.0 + (i * 37 % 101) as f64);
+        let n = DVector::from_element(n_snp, 10000.0);
+        let (h2, intercept, m) = (0.3, 1.05, 1e4);
+        let chisq = DVector::from_fn(n_snp, |i, _| intercept + n[i] * h2 * ld[i] / m);
+        let x = DMatrix::from_column_slice(n_snp, 1, ld.as_slice());
+        let hsq = Hsq::new(&chisq, &x, &ld, &n, &[m], &RegressionOptions::default()).unwrap();
+        assert!((hsq.reg.tot - h2).abs() < 1e-10);
+        assert!((hsq.reg.intercept - intercept).abs() < 1e-10);
+        assert!(hsq.reg.tot_se < 1e-8);
+        let expected_ratio = (intercept - 1.0) / (hsq.mean_chisq - 1.0);
+        assert!((hsq.ratio.unwrap() - expected_ratio).abs() < 1e-10);
+    }
+
+    #[test]
+    fn constrained_intercept_is_subtracted_and_weighted() {
+        // with the intercept constrained to c = 1.5, the initial h2 is M (mean(y) - c) /
+        // mean(N ld) = 0.05, the weights 1 / (2 (c + h2 N ld / M)^2 ld) and the single fit of
+        // the old weights is b = sum(w ld (y - c)) / sum(w ld^2) = 29753 / 54237; dropping
+        // either block of two SNPs gives 152 / 291 and 11 / 19
+        let ld = DVector::from_vec(vec![1.0, 2.0, 3.0, 4.0]);
+        let x = DMatrix::from_column_slice(4, 1, ld.as_slice());
+        let n = DVector::from_element(4, 100.0);
+        let chisq = DVector::from_vec(vec![2.5, 2.0, 3.5, 3.0]);
+        let options = RegressionOptions {
+            n_blocks: 2,
+            intercept: Some(1.5),
+            old_weights: true,
+            ..Default::default()
+        };
+        let hsq = Hsq::new(&chisq, &x, &ld, &n, &[10.0], &options).unwrap();
+        assert!((hsq.reg.tot - 29753.0 / 542370.0).abs() < 1e-12);
+        let tot_se = (152.0 / 291.0 - 11.0 / 19.0f64).abs() / 20.0;
+        assert!((hsq.reg.tot_se - tot_se).abs() < 1e-12);
+        assert_eq!(hsq.reg.intercept, 1.5);
+        assert!(hsq.reg.constrain_intercept);
+        assert!(hsq.reg.intercept_se.is_none() && hsq.ratio.is_none());
+        let summary = hsq.summary(&["L2".to_string()], None, false);
+        assert!(
+            summary.contains("Intercept: constrained to 1.5"),
+            "{}",
+            summary
+        );
+        assert!(!summary.contains("Ratio"));
+    }
+
+    #[test]
+    fn irwls_weights_use_the_constrained_intercept() {
+        // h2 = M coef / nbar = 0.5, so the weights are 1 / (2 (1.5 + 5 ld)^2 ld)
+        let ld = DVector::from_vec(vec![1.0, 2.0, 3.0, 4.0]);
+        let x = DMatrix::from_column_slice(4, 1, ld.as_slice());
+        let n = DVector::from_element(4, 100.0);
+        let expected = ld.map(|l: f64| 1.0 / (2.0 * (1.5 + 5.0 * l).powi(2) * l));
+        let constrained = HsqModel
+            .update_func(
+                &DVector::from_vec(vec![5.0]),
+                &x,
+                &ld,
+                &n,
+                10.0,
+                100.0,
+                Some(1.5),
+                None,
+            )
+            .unwrap();
+        assert!((constrained - &expected).abs().max() < 1e-15);
+        // a free intercept is the last coefficient
+        let free = HsqModel
+            .update_func(
+                &DVector::from_vec(vec![5.0, 1.5]),
+                &x,
+                &ld,
+                &n,
+                10.0,
+                100.0,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!((free - &expected).abs().max() < 1e-15);
+    }
+
+    #[test]
+    fn rg_constrains_only_the_given_intercepts() {
+        let n_snp = 1000;
+        let ld = DVector::from_fn(n_snp, |i, _| 1.0 + (i * 37 % 101) as f64);
+        let x = DMatrix::from_column_slice(n_snp, 1, ld.as_slice());
+        let n = DVector::from_element(n_snp, 10000.0);
+        let z1 = DVector::from_fn(n_snp, |i, _| (1.0 + 0.3 * ld[i]).sqrt());
+        let z2 = DVector::from_fn(n_snp, |i, _| (1.1 + 0.2 * ld[i]).sqrt());
+        let options = RegressionOptions {
+            n_blocks: 10,
+            ..Default::default()
+        };
+        let rg = Rg::new(
+            &z1,
+            &z2,
+            &x,
+            &ld,
+            &n,
+            &n,
+            &[1e4],
+            [Some(1.0), None, Some(0.1)],
+            &options,
+        )
+        .unwrap();
+        assert_eq!(rg.hsq1.reg.intercept, 1.0);
+        assert!(rg.hsq1.reg.intercept_se.is_none());
+        // the N/A trait keeps a free intercept
+        assert!(rg.hsq2.reg.intercept_se.is_some());
+        assert!((rg.hsq2.reg.intercept - 1.1).abs() < 1e-10);
+        assert_eq!(rg.gencov.reg.intercept, 0.1);
+        assert_eq!(rg.intercept_gencov, Some(0.1));
+        let summary = rg.gencov.summary(&["L2".to_string()], [None, None]);
+        assert!(
+            summary.contains("Intercept: constrained to 0.1"),
+            "{}",
+            summary
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use statrs::distribution::{ContinuousCDF, Normal, StudentsT};
+use statrs::function::erf::erfc;
+use std::f64::consts::{LN_2, PI, SQRT_2};
+
+// below this z the normal tail is computed from erfc, above it from the Mills ratio
+const MILLS_RATIO_Z: f64 = 5.0;
+// smallest ln(p) whose exp is a normal f64
+const MIN_LN_P: f64 = -708.0;
+
+/// ln of the standard normal density.
+fn ln_norm_pdf(z: f64) -> f64 {
+    -0.5 * z * z - 0.5 * (2.0 * PI).ln()
+}
+
+/// ln of the upper tail probability of the standard normal, P(Z > z).
+///
+/// Stays accurate far beyond the point where P(Z > z) underflows f64, by evaluating
+/// the Mills ratio as a continued fraction in the tail.
+pub fn ln_norm_sf(z: f64) -> f64 {
+    if z < MILLS_RATIO_Z {
+        return (0.5 * erfc(z / SQRT_2)).ln();
+    }
+    // Q(z) = phi(z) / (z + 1 / (z + 2 / (z + 3 / (z + ...))))
+    let mut cf = z;
+    for k in (1..=60).rev() {
+        cf = z + k as f64 / cf;
+    }
+    ln_norm_pdf(z) - cf.ln()
+}
+
+/// The z >= 0 with ln P(Z > z) = `ln_q`, for `ln_q` <= ln(0.5).
+fn inv_ln_norm_sf(ln_q: f64) -> f64 {
+    if ln_q >= -LN_2 {
+        return 0.0;
+    }
+    // initial guess, then Newton steps on ln Q(z) which is smooth and concave
+    let mut z = if ln_q > MIN_LN_P {
+        -Normal::new(0.0, 1.0).unwrap().inverse_cdf(ln_q.exp())
+    } else {
+        let t = -2.0 * ln_q;
+        (t - t.ln() - (2.0 * PI).ln()).sqrt()
+    };
+    for _ in 0..50 {
+        let ln_sf = ln_norm_sf(z);
+        // d/dz ln Q(z) = -phi(z) / Q(z)
+        let step = (ln_sf - ln_q) / (ln_norm_pdf(z) - ln_sf).exp();
+        z = (z + step).max(0.0);
+        if step.abs() <= 1e-15 * z.max(1.0) {
+            break;
+        }
+    }
+    z
+}
+
+/// |Z| of a two-sided p-value given as ln(p), i.e. the square root of the 1 df
+/// chi-square quantile, computed in log space so that it stays accurate for p-values
+/// that underflow f64 (P = 1e-300 and below).
+pub fn z_from_ln_p(ln_p: f64) -> f64 {
+    if ln_p.is_nan() || ln_p > 0.0 {
+        return f64::NAN;
+    }
+    inv_ln_norm_sf(ln_p - LN_2)
+}
+
+/// Two-sided p-value of a standard normal `z`, i.e. the 1 df chi-square survival function
+/// at z^2 (0 if `z` is infinite).
+pub fn p_from_z(z: f64) -> f64 {
+    erfc(z.abs() / SQRT_2)
+}
+
+/// Two-sided p-value of a Student's t statistic `t` with `df` degrees of freedom.
+pub fn p_from_t(t: f64, df: f64) -> f64 {
+    let dist = StudentsT::new(0.0, 1.0, df).expect("df must be positive");
+    2.0 * dist.sf(t.abs())
+}
+
+/// The `p` quantile of a stream of values, in bounded memory.
+///
+/// Up to `cap` values are kept and their quantile is exact, interpolated linearly between
+/// the nearest ranks like polars' `QuantileMethod::Linear`. Past `cap` values the stream
+/// is summarised by the five markers of the P² algorithm (Jain and Chlamtac, 1985), whose
+/// estimate does not depend on storing the values. NaNs are ignored.
+pub struct QuantileEstimator {
+    p: f64,
+    cap: usize,
+    values: Vec<f64>,
+    markers: Option<PSquare>,
+}
+
+impl QuantileEstimator {
+    pub fn new(p: f64, cap: usize) -> Self {
+        Self {
+            p,
+            // P² starts from the first five values
+            cap: cap.max(5),
+            values: Vec::new(),
+            markers: None,
+        }
+    }
+
+    pub fn add(&mut self, x: f64) {
+        if x.is_nan() {
+            return;
+        }
+        if let Some(markers) = &mut self.markers {
+            markers.add(x);
+            return;
+        }
+        self.values.push(x);
+        if self.values.len() > self.cap {
+            let values = std::mem::take(&mut self.values);
+            let mut markers = PSquare::new(self.p, &values[..5]);
+            for &x in &values[5..] {
+                markers.add(x);
+            }
+            self.markers = Some(markers);
+        }
+    }
+
+    /// The quantile of the values added so far, or None if there were none.
+    pub fn quantile(mut self) -> Option<f64> {
+        if let Some(markers) = &self.markers {
+            return Some(markers.q[2]);
+        }
+        if self.values.is_empty() {
+            return None;
+        }
+        self.values.sort_unstable_by(f64::total_cmp);
+        let idx = self.p * (self.values.len() - 1) as f64;
+        let (lo, hi) = (idx.floor() as usize, idx.ceil() as usize);
+        let (lo_value, hi_value) = (self.values[lo], self.values[hi]);
+        Some(lo_value + (hi_value - lo_value) * (idx - lo as f64))
+    }
+}
+
+// marker heights, positions, desired positions and their increments of the P² algorithm
+struct PSquare {
+    q: [f64; 5],
+    n: [f64; 5],
+    desired: [f64; 5],
+    increment: [f64; 5],
+}
+
+impl PSquare {
+    fn new(p: f64, first: &[f64]) -> Self {
+        let mut q = [first[0], first[1], first[2], first[3], first[4]];
+        q.sort_unstable_by(f64::total_cmp);
+        Self {
+            q,
+            n: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increment: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    fn add(&mut self, x: f64) {
+        let (q, n) = (&mut self.q, &mut self.n);
+        // the cell holding x, widening the outer markers if need be
+        let k = if x < q[0] {
+            q[0] = x;
+            0
+        } else if x >= q[4] {
+            q[4] = x;
+            3
+        } else {
+            (1..5).find(|&i| x < q[i]).unwrap() - 1
+        };
+        for n in &mut n[k + 1..] {
+            *n += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increment) {
+            *desired += increment;
+        }
+
+        // move the middle markers that are off their desired position by a rank or more
+        for i in 1..4 {
+            let d = self.desired[i] - n[i];
+            if (d >= 1.0 && n[i + 1] - n[i] > 1.0) || (d <= -1.0 && n[i - 1] - n[i] < -1.0) {
+                let d = d.signum();
+                let parabolic = q[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]));
+                q[i] = if q[i - 1] < parabolic && parabolic < q[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+                };
+                n[i] += d;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn z_from_moderate_p() {
+        // statrs' erfc is good to ~1e-10 relative here
+        assert!((z_from_ln_p(0.05_f64.ln()) - 1.959963984540054).abs() < 1e-9);
+        assert!((z_from_ln_p(5e-8_f64.ln()) - 5.451310437845478).abs() < 1e-9);
+        assert_eq!(z_from_ln_p(0.0), 0.0);
+        assert!(z_from_ln_p(0.1).is_nan());
+        assert!((p_from_z(-1.959963984540054) - 0.05).abs() < 1e-9);
+        assert_eq!(p_from_z(f64::INFINITY), 0.0);
+    }
+
+    #[test]
+    fn t_p_values() {
+        // closed forms: Cauchy for 1 df, 1 - t / sqrt(2 + t^2) for 2 df
+        assert!((p_from_t(1.0, 1.0) - 0.5).abs() < 1e-12);
+        assert!((p_from_t(-2.0, 2.0) - (1.0 - 2.0 / 6f64.sqrt())).abs() < 1e-12);
+    }
+
+    #[test]
+    fn z_from_tiny_p() {
+        // P = 2 * Q(z) = erfc(z / sqrt(2)) while P is still representable
+        for p in [1e-20, 1e-100, 1e-300] {
+            let z = z_from_ln_p(f64::ln(p));
+            assert!((erfc(z / SQRT_2) / p - 1.0).abs() < 1e-10, "{}", p);
+        }
+        // and in log space beyond it
+        for ln_p in [-1e3, -1e4, -1e6] {
+            let z = z_from_ln_p(ln_p);
+            assert!(
+                ((ln_norm_sf(z) + LN_2) / ln_p - 1.0).abs() < 1e-12,
+                "{}",
+                ln_p
+            );
+        }
+    }
+
+    #[test]
+    fn ln_norm_sf_is_continuous() {
+        let below = ln_norm_sf(MILLS_RATIO_Z - 1e-9);
+        let above = ln_norm_sf(MILLS_RATIO_Z);
+        assert!((below - above).abs() < 1e-8);
+    }
+
+    #[test]
+    fn quantiles_are_exact_up_to_the_cap() {
+        let mut median = QuantileEstimator::new(0.5, 10);
+        let mut q90 = QuantileEstimator::new(0.9, 10);
+        for x in [4.0, 1.0, f64::NAN, 3.0, 2.0] {
+            median.add(x);
+            q90.add(x);
+        }
+        assert_eq!(median.quantile(), Some(2.5));
+        assert!((q90.quantile().unwrap() - 3.7).abs() < 1e-12);
+        assert_eq!(QuantileEstimator::new(0.5, 10).quantile(), None);
+    }
+
+    #[test]
+    fn quantiles_are_estimated_past_the_cap() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        // uniform values, shuffled by the rng, so the p quantile is about p
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut median = QuantileEstimator::new(0.5, 100);
+        let mut q90 = QuantileEstimator::new(0.9, 100);
+        for _ in 0..100_000 {
+            let x: f64 = rng.gen();
+            median.add(x);
+            q90.add(x);
+        }
+        assert!(median.values.is_empty() && median.markers.is_some());
+        assert!((median.quantile().unwrap() - 0.5).abs() < 0.01);
+        assert!((q90.quantile().unwrap() - 0.9).abs() < 0.01);
+    }
+}
@@ -0,0 +1,2368 @@
+use anyhow::{anyhow, bail, Result};
+use log::{error, info, warn};
+use nalgebra::{DMatrix, DVector, RowDVector};
+use polars::prelude::*;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+
+use crate::cli::{GlobalArgs, H2Args, H2CtsArgs, LdArgs, RgArgs};
+use crate::const_value::FLIP_ALLELES;
+use crate::jackknife::{block_labels, delete_values_to_pseudovalues};
+use crate::parse::{read_annot, read_frq_file, read_ldscore, read_m};
+use crate::regressions::{
+    np_str, Hsq, LdScoreRegression, OverlapResults, Prevalence, RegressionOptions, Rg, RgEstimate,
+};
+use crate::stats::ln_norm_sf;
+use crate::utils::{get_input_reader, input_paths, py_str, read_csv, read_header, Delimiter};
+
+// fewer regression SNPs than this almost always gives poor estimates
+const MIN_SNPS_WARN: usize = 200000;
+// chi^2 cutoff of the first step of the two-step estimator
+const TWO_STEP_CUTOFF: f64 = 30.0;
+// column of the regression weight LD scores in the merged data
+const W_LD_CNAME: &str = "LD_weights";
+// SNP identifier columns kept from the reference panel LD scores
+const LD_ID_COLS: [&str; 3] = ["SNP", "CHR", "BP"];
+// SNPs with MAF above this are counted in the overlap of annotations with --frqfile
+const MAF_5_50: f64 = 0.05;
+// lower bound of the chi^2 cutoff of partitioned h2 estimation
+const DEFAULT_CHISQ_MAX: f64 = 80.0;
+
+/// Summary statistics merged with the reference panel and regression weight LD scores, in
+/// the order of the reference panel LD scores (sorted by CHR and BP).
+pub struct LdSumstats {
+    /// Number of SNPs per annotation.
+    pub m_annot: Vec<f64>,
+    pub ref_ld_cnames: Vec<String>,
+    /// SNP, CHR, BP, the reference LD scores, the summary statistics and `LD_weights`.
+    pub data: DataFrame,
+}
+
+impl LdSumstats {
+    pub fn column(&self, name: &str) -> Result<DVector<f64>> {
+        let values = self
+            .data
+            .column(name)?
+            .f64()?
+            .into_iter()
+            .map(|v| v.unwrap_or(f64::NAN))
+            .collect::<Vec<_>>();
+        Ok(DVector::from_vec(values))
+    }
+
+    /// SNPs x annotations reference panel LD scores.
+    pub fn ref_ld(&self) -> Result<DMatrix<f64>> {
+        let columns = self
+            .ref_ld_cnames
+            .iter()
+            .map(|c| self.column(c))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(DMatrix::from_columns(&columns))
+    }
+}
+
+/// Names of the LD score columns of `df`, i.e. every column but the SNP identifiers.
+fn ld_cnames(df: &DataFrame) -> Vec<String> {
+    df.get_column_names()
+        .iter()
+        .filter(|c| !LD_ID_COLS.contains(&c.as_str()))
+        .map(|c| c.to_string())
+        .collect()
+}
+
+fn splitp(fstr: &str) -> Vec<&str> {
+    fstr.split(',').collect()
+}
+
+/// Read munged summary statistics (SNP, Z, N and, with `alleles`, A1 and A2), dropping
+/// SNPs with duplicated rs numbers and, with `dropna`, SNPs with missing values.
+pub fn read_sumstats(path: &str, alleles: bool, dropna: bool) -> Result<DataFrame> {
+    info!("Reading summary statistics from {} ...", path);
+    let mut usecols = vec!["SNP", "Z", "N"];
+    if alleles {
+        usecols.extend(["A1", "A2"]);
+    }
+    let mut schema = Schema::default();
+    for c in &usecols {
+        let dtype = match *c {
+            "SNP" | "A1" | "A2" => DataType::String,
+            _ => DataType::Float64,
+        };
+        schema.with_column((*c).into(), dtype);
+    }
+    let read_opts = CsvReadOptions::default()
+        .with_has_header(true)
+        .with_parse_options(
+            CsvParseOptions::default()
+                .with_null_values(Some(NullValues::AllColumns(vec![".".into(), "NA".into()]))),
+        )
+        .with_columns(Some(usecols.iter().map(|c| (*c).into()).collect()))
+        .with_schema_overwrite(Some(schema.into()));
+    let mut df = read_csv(path, read_opts, None)
+        .map_err(|e| anyhow!("Improperly formatted sumstats file: {}", e))?;
+    if dropna {
+        df = drop_missing(df)?;
+    }
+    info!("Read summary statistics for {} SNPs.", df.height());
+    let m = df.height();
+    let df = df.unique_stable(Some(&["SNP".into()]), UniqueKeepStrategy::First, None)?;
+    if m > df.height() {
+        info!(
+            "Dropped {} SNPs with duplicated rs numbers.",
+            m - df.height()
+        );
+    }
+    Ok(df)
+}
+
+/// Drop the rows of `df` with a null or NaN in any column, like pandas' `dropna`.
+fn drop_missing(df: DataFrame) -> Result<DataFrame> {
+    let not_nan = df
+        .get_columns()
+        .iter()
+        .filter(|c| c.dtype().is_float())
+        .map(|c| col(c.name().as_str()).is_not_nan())
+        .reduce(|a, b| a.and(b))
+        .unwrap_or(lit(true));
+    Ok(df.lazy().drop_nulls(None).filter(not_nan).collect()?)
+}
+
+/// Sideways concatenation of the LD scores of each file in `flist`, whose LD score columns
+/// get the suffix `_<i>` of their position in the list. Every file must have the same SNPs.
+fn ldscore_fromlist(flist: &[&str], chr_split: bool) -> Result<DataFrame> {
+    let mut out: Option<DataFrame> = None;
+    for (i, fh) in flist.iter().enumerate() {
+        let mut y = read_ldscore(fh, chr_split)?;
+        if let Some(first) = &out {
+            if !y.column("SNP")?.equals(first.column("SNP")?) {
+                bail!("LD Scores for concatenation must have identical SNP columns.");
+            }
+            y = y.drop_many(LD_ID_COLS);
+        }
+        for c in ld_cnames(&y) {
+            y.rename(&c, format!("{}_{}", c, i).into())?;
+        }
+        match out.as_mut() {
+            Some(acc) => {
+                acc.hstack_mut(y.get_columns())?;
+            }
+            None => out = Some(y),
+        }
+    }
+    Ok(out.unwrap())
+}
+
+/// `fh` as logged, with `[1-22]` for the chromosome if `chr_split`.
+fn chr_split_name(fh: &str, chr_split: bool) -> String {
+    match (chr_split, fh.contains('@')) {
+        (false, _) => fh.to_string(),
+        (true, true) => fh.replace('@', "[1-22]"),
+        (true, false) => format!("{}[1-22]", fh),
+    }
+}
+
+/// Read the LD scores of `not_chr_arg`, or of `chr_arg` split across 22 chromosomes.
+fn read_chr_split_files(
+    chr_arg: Option<&str>,
+    not_chr_arg: Option<&str>,
+    noun: &str,
+) -> Result<DataFrame> {
+    let out = match (not_chr_arg, chr_arg) {
+        (Some(fh), _) => {
+            info!("Reading {} from {} ...", noun, fh);
+            ldscore_fromlist(&splitp(fh), false)
+        }
+        (None, Some(fh)) => {
+            info!("Reading {} from {} ...", noun, chr_split_name(fh, true));
+            ldscore_fromlist(&splitp(fh), true)
+        }
+        (None, None) => bail!("No {} files given.", noun),
+    };
+    out.map_err(|e| e.context(format!("Error parsing {}.", noun)))
+}
+
+fn read_ref_ld(args: &LdArgs) -> Result<DataFrame> {
+    let ref_ld = read_chr_split_files(
+        args.ref_ld_chr.as_deref(),
+        args.ref_ld.as_deref(),
+        "reference panel LD Score",
+    )?;
+    info!(
+        "Read reference panel LD Scores for {} SNPs.",
+        ref_ld.height()
+    );
+    Ok(ref_ld)
+}
+
+/// Read M from `--M`, or sum it from the .l2.M_5_50 (.l2.M with `--not-M-5-50`) files of
+/// the reference panel LD scores.
+fn read_m_annot(args: &LdArgs, n_annot: usize) -> Result<Vec<f64>> {
+    let m_annot = match &args.m {
+        Some(m) => splitp(m)
+            .iter()
+            .map(|x| x.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("Could not cast --M to float: {}", e))?,
+        None => {
+            let (fh, chr_split) = match (&args.ref_ld, &args.ref_ld_chr) {
+                (Some(fh), _) => (fh, false),
+                (None, Some(fh)) => (fh, true),
+                (None, None) => bail!("Must specify either --ref-ld or --ref-ld-chr."),
+            };
+            let mut m_annot = Vec::new();
+            for f in splitp(fh) {
+                m_annot.extend(read_m(f, chr_split, !args.not_m_5_50)?);
+            }
+            m_annot
+        }
+    };
+    if m_annot.len() != n_annot {
+        bail!("# terms in --M must match # of LD Scores in --ref-ld.");
+    }
+    Ok(m_annot)
+}
+
+fn read_w_ld(args: &LdArgs) -> Result<DataFrame> {
+    let has_comma = |f: &Option<String>| f.as_ref().is_some_and(|f| f.contains(','));
+    if has_comma(&args.w_ld) || has_comma(&args.w_ld_chr) {
+        bail!("--w-ld must point to a single fileset (no commas allowed).");
+    }
+    let w_ld = read_chr_split_files(
+        args.w_ld_chr.as_deref(),
+        args.w_ld.as_deref(),
+        "regression weight LD Score",
+    )?;
+    let w_ld_cnames = ld_cnames(&w_ld);
+    if w_ld_cnames.len() != 1 {
+        bail!("--w-ld may only have one LD Score column.");
+    }
+    // prevent colname conflicts with the reference LD scores
+    let w_ld = w_ld
+        .lazy()
+        .select([col("SNP"), col(w_ld_cnames[0].as_str()).alias(W_LD_CNAME)])
+        .collect()?;
+    info!(
+        "Read regression weight LD Scores for {} SNPs.",
+        w_ld.height()
+    );
+    Ok(w_ld)
+}
+
+/// Drop the reference LD scores with zero variance, and their M.
+fn check_variance(
+    m_annot: Vec<f64>,
+    ref_ld: DataFrame,
+    ref_ld_cnames: Vec<String>,
+) -> Result<(Vec<f64>, DataFrame, Vec<String>)> {
+    let mut keep = Vec::new();
+    for c in &ref_ld_cnames {
+        let values = ref_ld
+            .column(c)?
+            .f64()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        keep.push(var != 0.0);
+    }
+    if !keep.iter().any(|k| *k) {
+        bail!("All LD Scores have zero variance.");
+    }
+    info!("Removing partitioned LD Scores with zero variance.");
+    let dropped = ref_ld_cnames
+        .iter()
+        .zip(&keep)
+        .filter(|(_, k)| !**k)
+        .map(|(c, _)| c.as_str())
+        .collect::<Vec<_>>();
+    let ref_ld = ref_ld.drop_many(dropped);
+    let m_annot = m_annot
+        .into_iter()
+        .zip(&keep)
+        .filter(|(_, k)| **k)
+        .map(|(m, _)| m)
+        .collect();
+    let ref_ld_cnames = ref_ld_cnames
+        .into_iter()
+        .zip(&keep)
+        .filter(|(_, k)| **k)
+        .map(|(c, _)| c)
+        .collect();
+    Ok((m_annot, ref_ld, ref_ld_cnames))
+}
+
+/// Inner join of `x` and `y` on SNP, in the order of `x`.
+fn merge_and_log(x: &DataFrame, y: &DataFrame, noun: &str) -> Result<DataFrame> {
+    let y_snps = y.column("SNP")?.str()?;
+    let index = y_snps
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, s)| s.map(|s| (s, i as IdxSize)))
+        .collect::<HashMap<_, _>>();
+    let (xi, yi): (Vec<IdxSize>, Vec<IdxSize>) = x
+        .column("SNP")?
+        .str()?
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, s)| s.and_then(|s| index.get(s)).map(|j| (i as IdxSize, *j)))
+        .unzip();
+    let mut out = x.take(&IdxCa::from_vec("idx".into(), xi))?;
+    let y = y.drop("SNP")?.take(&IdxCa::from_vec("idx".into(), yi))?;
+    out.hstack_mut(y.get_columns())?;
+    let msg = format!("After merging with {}, {} SNPs remain.", noun, out.height());
+    if out.height() == 0 {
+        bail!(msg);
+    }
+    info!("{}", msg);
+    Ok(out)
+}
+
+/// Read the reference panel LD scores and their M, without the annotations of zero variance.
+fn read_ref_ld_and_m(args: &LdArgs) -> Result<(Vec<f64>, DataFrame, Vec<String>)> {
+    let ref_ld = read_ref_ld(args)?;
+    let ref_ld_cnames = ld_cnames(&ref_ld);
+    let m_annot = read_m_annot(args, ref_ld_cnames.len())?;
+    check_variance(m_annot, ref_ld, ref_ld_cnames)
+}
+
+/// Read the summary statistics in `fh` and the LD scores and M of `args`, and merge them.
+pub fn read_ld_sumstats(args: &LdArgs, fh: &str, alleles: bool) -> Result<LdSumstats> {
+    let sumstats = read_sumstats(fh, alleles, true)?;
+    let (m_annot, ref_ld, ref_ld_cnames) = read_ref_ld_and_m(args)?;
+    let w_ld = read_w_ld(args)?;
+    let data = merge_and_log(&ref_ld, &sumstats, "reference panel LD")?;
+    let data = merge_and_log(&data, &w_ld, "regression SNP LD")?;
+    Ok(LdSumstats {
+        m_annot,
+        ref_ld_cnames,
+        data,
+    })
+}
+
+fn warn_length(n_snp: usize) {
+    if n_snp < MIN_SNPS_WARN {
+        warn!("WARNING: number of SNPs less than 200k; this is almost always bad.");
+    }
+}
+
+/// Estimate h2 (and partitioned h2) from the summary statistics of `--h2`.
+pub fn estimate_h2(args: &H2Args, global: &GlobalArgs) -> Result<Hsq> {
+    let frqfile = overlap_frqfile(args)?;
+    let prevalence = read_prevalences(
+        args.samp_prev.as_deref(),
+        args.pop_prev.as_deref(),
+        &[&args.h2],
+    )?[0];
+    let mut ld_sumstats = read_ld_sumstats(&args.ld, &args.h2, false)?;
+    let n_snp = ld_sumstats.data.height();
+    warn_length(n_snp);
+    let n_blocks = n_snp.min(args.ld.n_blocks);
+    let n_annot = ld_sumstats.ref_ld_cnames.len();
+    let mut options = RegressionOptions {
+        n_blocks,
+        ..h2_options(args, n_annot)
+    };
+    if let Some(chisq_max) = chisq_max(&args.ld, &ld_sumstats, n_annot > 1)? {
+        let report = filter_chisq(&mut ld_sumstats, &args.h2, chisq_max)?;
+        write_chisq_max_reports(&global.out, &[report])?;
+    }
+    log_twostep(options.twostep, args.two_step);
+    options.blocks = jackknife_blocks(&args.ld, &ld_sumstats.data)?;
+
+    let chisq = ld_sumstats.column("Z")?.map(|z| z * z);
+    let hsqhat = Hsq::new(
+        &chisq,
+        &ld_sumstats.ref_ld()?,
+        &ld_sumstats.column(W_LD_CNAME)?,
+        &ld_sumstats.column("N")?,
+        &ld_sumstats.m_annot,
+        &options,
+    )?;
+    if args.print_delete_vals {
+        let fname = format!("{}.delete", global.out);
+        info!("Printing block jackknife delete values to {}.", fname);
+        let tot = &hsqhat.reg.tot_delete_values;
+        savetxt(&fname, &DMatrix::from_column_slice(tot.len(), 1, tot))?;
+        let fname = format!("{}.part_delete", global.out);
+        info!(
+            "Printing partitioned block jackknife delete values to {}.",
+            fname
+        );
+        savetxt(&fname, &hsqhat.reg.part_delete_values)?;
+    }
+    if args.print_jackknife {
+        let fname = format!("{}.jackknife.tsv", global.out);
+        info!(
+            "Printing block jackknife delete values and pseudovalues to {}.",
+            fname
+        );
+        write_hsq_jackknife(&fname, &hsqhat, &ld_sumstats.ref_ld_cnames)?;
+    }
+    info!(
+        "{}",
+        hsqhat.summary(&ld_sumstats.ref_ld_cnames, prevalence, args.overlap_annot)
+    );
+    if args.overlap_annot {
+        let (overlap, m_tot) = read_overlap_matrix(&args.ld, frqfile)?;
+        if overlap.nrows() != n_annot {
+            bail!(
+                "The .annot files have {} annotations, but {} LD Scores are left.",
+                overlap.nrows(),
+                n_annot
+            );
+        }
+        let results = hsqhat.overlap_output(
+            &ld_sumstats.ref_ld_cnames,
+            &overlap,
+            &ld_sumstats.m_annot,
+            m_tot,
+        );
+        let fname = format!("{}.results", global.out);
+        write_overlap_results(&fname, &results, args.print_coefficients)?;
+        info!("Results printed to {}", fname);
+    }
+    Ok(hsqhat)
+}
+
+/// Jackknife blocks of the SNPs of `data` by chromosome (`--jk-chr`) or by distance
+/// (`--jk-block-kb`), or `None` for `--n-blocks` blocks of equal numbers of SNPs.
+fn jackknife_blocks(args: &LdArgs, data: &DataFrame) -> Result<Option<Vec<usize>>> {
+    if !args.jk_chr && args.jk_block_kb.is_none() {
+        return Ok(None);
+    }
+    let int_column = |name: &str| -> Result<Vec<i64>> {
+        data.column(name)?
+            .i64()?
+            .into_iter()
+            .map(|v| v.ok_or_else(|| anyhow!("Missing {} in the LD Scores.", name)))
+            .collect()
+    };
+    let max_dist = args.jk_block_kb.map(|kb| kb * 1000.0);
+    let blocks = block_labels(&int_column("CHR")?, &int_column("BP")?, max_dist)?;
+    let n_blocks = blocks.last().map_or(0, |b| b + 1);
+    match args.jk_block_kb {
+        Some(kb) => info!(
+            "Using {} jackknife blocks of less than {} kb.",
+            n_blocks, kb
+        ),
+        None => info!("Using {} jackknife blocks, one per chromosome.", n_blocks),
+    }
+    if n_blocks < 2 {
+        bail!("The block jackknife needs at least two blocks.");
+    }
+    Ok(Some(blocks))
+}
+
+/// Header of a table of jackknife delete values and pseudovalues of the quantities `names`.
+fn jackknife_header(names: &[&str]) -> Vec<String> {
+    let mut header = vec!["block".to_string()];
+    for name in names {
+        header.push(format!("{}_delete", name));
+        header.push(format!("{}_pseudo", name));
+    }
+    header
+}
+
+/// Rows of delete values and pseudovalues of the quantities `(estimate, delete values)`, one
+/// per jackknife block, with NA for a quantity that was not estimated.
+fn jackknife_rows(quantities: &[Option<(f64, &[f64])>]) -> Vec<Vec<String>> {
+    let n_blocks = quantities
+        .iter()
+        .flatten()
+        .map(|(_, dv)| dv.len())
+        .max()
+        .unwrap_or(0);
+    let mut rows = (0..n_blocks)
+        .map(|b| vec![b.to_string()])
+        .collect::<Vec<_>>();
+    for q in quantities {
+        match q {
+            Some((est, dv)) => {
+                let pseudo = delete_values_to_pseudovalues(
+                    &DMatrix::from_column_slice(dv.len(), 1, dv),
+                    &RowDVector::from_element(1, *est),
+                );
+                for (b, row) in rows.iter_mut().enumerate() {
+                    row.extend([py_str(dv[b]), py_str(pseudo[b])]);
+                }
+            }
+            None => {
+                for row in rows.iter_mut() {
+                    row.extend(["NA".to_string(), "NA".to_string()]);
+                }
+            }
+        }
+    }
+    rows
+}
+
+/// Write the jackknife delete values and pseudovalues of h2, the intercept (if estimated)
+/// and, if partitioned, the coefficient of each LD score of `cnames`.
+fn write_hsq_jackknife(path: &str, hsqhat: &Hsq, cnames: &[String]) -> Result<()> {
+    let reg = &hsqhat.reg;
+    let mut names = vec!["h2"];
+    let mut quantities = vec![Some((reg.tot, reg.tot_delete_values.as_slice()))];
+    if let Some(dv) = &reg.intercept_delete_values {
+        names.push("intercept");
+        quantities.push(Some((reg.intercept, dv.as_slice())));
+    }
+    let coef_names = cnames
+        .iter()
+        .map(|c| format!("{}_coef", c))
+        .collect::<Vec<_>>();
+    if reg.n_annot > 1 {
+        // delete values are stored column by column
+        let part = reg.part_delete_values.as_slice();
+        for (j, name) in coef_names.iter().enumerate() {
+            names.push(name);
+            quantities.push(Some((
+                reg.coef[j],
+                &part[j * reg.n_blocks..(j + 1) * reg.n_blocks],
+            )));
+        }
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{}", jackknife_header(&names).join("\t"))?;
+    for row in jackknife_rows(&quantities) {
+        writeln!(writer, "{}", row.join("\t"))?;
+    }
+    Ok(())
+}
+
+/// Regression options of h2 estimation with `n_annot` reference LD scores: the two-step
+/// estimator for a single LD Score with a free intercept, the old weights for several.
+fn h2_options(args: &H2Args, n_annot: usize) -> RegressionOptions {
+    let intercept = match args.no_intercept {
+        true => Some(1.0),
+        false => args.intercept_h2,
+    };
+    let mut options = RegressionOptions {
+        n_blocks: args.ld.n_blocks,
+        intercept,
+        twostep: args.two_step,
+        ..Default::default()
+    };
+    if n_annot == 1 {
+        if options.twostep.is_none() && intercept.is_none() {
+            options.twostep = Some(TWO_STEP_CUTOFF);
+        }
+    } else {
+        options.old_weights = true;
+    }
+    options
+}
+
+/// ldsc's default chi^2 cutoff `max(0.001 * max(N), 80)`, and the cutoff as ldsc prints
+/// it (Python keeps the int 80 when it is larger).
+fn default_chisq_max(ld_sumstats: &LdSumstats) -> Result<(f64, String)> {
+    let n_max = ld_sumstats
+        .column("N")?
+        .iter()
+        .cloned()
+        .fold(f64::MIN, f64::max);
+    Ok(match 0.001 * n_max > DEFAULT_CHISQ_MAX {
+        true => (0.001 * n_max, py_str(0.001 * n_max)),
+        false => (DEFAULT_CHISQ_MAX, DEFAULT_CHISQ_MAX.to_string()),
+    })
+}
+
+/// The chi^2 cutoff of `--chisq-max` as ldsc prints it, else the default cutoff if `default`.
+fn chisq_max(
+    args: &LdArgs,
+    ld_sumstats: &LdSumstats,
+    default: bool,
+) -> Result<Option<(f64, String)>> {
+    Ok(match args.chisq_max {
+        Some(chisq_max) => Some((chisq_max, py_str(chisq_max))),
+        None if default => Some(default_chisq_max(ld_sumstats)?),
+        None => None,
+    })
+}
+
+/// Log the cutoff of the two-step estimator, if used, as ldsc prints it (Python keeps the
+/// int 30 of the default).
+fn log_twostep(twostep: Option<f64>, two_step_arg: Option<f64>) {
+    if let Some(cutoff) = twostep {
+        info!(
+            "Using two-step estimator with cutoff at {}.",
+            two_step_arg.map_or(cutoff.to_string(), py_str)
+        );
+    }
+}
+
+/// SNPs removed by `--chisq-max` (or its default) before the regression of `traits`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChisqMaxReport {
+    pub traits: Vec<String>,
+    pub chisq_max: f64,
+    pub removed: usize,
+    pub remaining: usize,
+}
+
+/// Write the `--chisq-max` removals of a regression run to `<out>.chisq_max.json`, next to
+/// the `<out>.munge.json` QC counts of munging.
+fn write_chisq_max_reports(out: &str, reports: &[ChisqMaxReport]) -> Result<()> {
+    if reports.is_empty() {
+        return Ok(());
+    }
+    let fname = format!("{}.chisq_max.json", out);
+    let writer = BufWriter::new(File::create(&fname)?);
+    serde_json::to_writer_pretty(writer, reports)?;
+    info!("Wrote the SNPs removed by --chisq-max to {}", fname);
+    Ok(())
+}
+
+/// Remove the SNPs with chi^2 at or above `chisq_max`, logged as `chisq_max_str`.
+fn filter_chisq(
+    ld_sumstats: &mut LdSumstats,
+    trait_: &str,
+    (chisq_max, chisq_max_str): (f64, String),
+) -> Result<ChisqMaxReport> {
+    let n_snp = ld_sumstats.data.height();
+    let z = ld_sumstats.data.column("Z")?.f64()?;
+    let ii = z
+        .into_iter()
+        .map(|z| z.is_some_and(|z| z * z < chisq_max))
+        .collect::<BooleanChunked>();
+    ld_sumstats.data = ld_sumstats.data.filter(&ii)?;
+    let remain = ld_sumstats.data.height();
+    info!(
+        "Removed {} SNPs with chi^2 > {} ({} SNPs remain)",
+        n_snp - remain,
+        chisq_max_str,
+        remain
+    );
+    Ok(ChisqMaxReport {
+        traits: vec![trait_.to_string()],
+        chisq_max,
+        removed: n_snp - remain,
+        remaining: remain,
+    })
+}
+
+/// Remove the SNPs of both traits of `traits` whose product of chi^2 is at or above
+/// `chisq_max`^2.
+fn filter_chisq_product(
+    sumstats: &mut LdSumstats,
+    traits: [&str; 2],
+    chisq_max: f64,
+) -> Result<ChisqMaxReport> {
+    let n_snp = sumstats.data.height();
+    let z1 = sumstats.column("Z1")?;
+    let z2 = sumstats.column("Z2")?;
+    let ii = z1
+        .iter()
+        .zip(z2.iter())
+        .map(|(z1, z2)| below_chisq_max(*z1, *z2, Some(chisq_max)))
+        .collect::<BooleanChunked>();
+    sumstats.data = sumstats.data.filter(&ii)?;
+    let remain = sumstats.data.height();
+    info!(
+        "Removed {} SNPs with chi^2_1 * chi^2_2 > {}^2 ({} SNPs remain)",
+        n_snp - remain,
+        py_str(chisq_max),
+        remain
+    );
+    Ok(ChisqMaxReport {
+        traits: traits.iter().map(|t| t.to_string()).collect(),
+        chisq_max,
+        removed: n_snp - remain,
+        remaining: remain,
+    })
+}
+
+/// The frequency file prefix of `--overlap-annot`, read with the .annot files of the
+/// reference panel LD scores. Like ldsc, it is ignored without `--overlap-annot` or with
+/// `--not-M-5-50`, and required otherwise.
+fn overlap_frqfile(args: &H2Args) -> Result<Option<&str>> {
+    if !args.overlap_annot || args.ld.not_m_5_50 {
+        if args.frqfile.is_some() || args.frqfile_chr.is_some() {
+            info!("The frequency file is unnecessary and is being ignored.");
+        }
+        return Ok(None);
+    }
+    match (&args.ld.ref_ld, &args.frqfile, &args.frqfile_chr) {
+        (Some(_), Some(f), _) | (None, _, Some(f)) => Ok(Some(f)),
+        _ => bail!("Must set either --frqfile and --ref-ld or --frqfile-chr and --ref-ld-chr"),
+    }
+}
+
+/// The annotations x annotations matrix of the number of SNPs in both annotations of the
+/// .annot files of the reference panel LD scores, and the total number of SNPs. With
+/// `frqfile`, only SNPs with 5% < FRQ < 95% in the .frq files (with the SNPs in the same
+/// order) are counted.
+fn read_overlap_matrix(args: &LdArgs, frqfile: Option<&str>) -> Result<(DMatrix<f64>, f64)> {
+    let (fh, chr_split) = match (&args.ref_ld, &args.ref_ld_chr) {
+        (Some(fh), _) => (fh.as_str(), false),
+        (None, Some(fh)) => (fh.as_str(), true),
+        (None, None) => bail!("Must specify either --ref-ld or --ref-ld-chr."),
+    };
+    info!(
+        "Reading annot matrix from {} ...",
+        chr_split_name(fh, chr_split)
+    );
+    let read = || -> Result<(DMatrix<f64>, f64)> {
+        let annot_files = splitp(fh)
+            .iter()
+            .map(|f| input_paths(f, ".annot", chr_split))
+            .collect::<Result<Vec<_>>>()?;
+        let frq_files = frqfile
+            .map(|f| input_paths(f, ".frq", chr_split))
+            .transpose()?;
+        let mut overlap: Option<DMatrix<f64>> = None;
+        let mut m_tot = 0;
+        for c in 0..annot_files[0].len() {
+            let annots = annot_files
+                .iter()
+                .map(|files| {
+                    let (header, _) = read_header(&files[c], Some(Delimiter::Whitespace))?;
+                    read_annot(&files[c], !header.iter().any(|h| h == "SNP"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let n_snp = annots[0].matrix.nrows();
+            if annots.iter().any(|a| a.matrix.nrows() != n_snp) {
+                bail!("The .annot files of chromosome {} differ in length.", c + 1);
+            }
+            let columns = annots
+                .iter()
+                .flat_map(|a| a.matrix.column_iter().map(|v| v.into_owned()))
+                .collect::<Vec<_>>();
+            let mut matrix = DMatrix::from_columns(&columns);
+            if let Some(frq_files) = &frq_files {
+                let frq = read_frq_file(&frq_files[c])?;
+                if frq.height() != n_snp {
+                    bail!(
+                        "{} has {} SNPs, but the .annot files have {}.",
+                        frq_files[c],
+                        frq.height(),
+                        n_snp
+                    );
+                }
+                let common = frq
+                    .column("FRQ")?
+                    .f64()?
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, f)| f.is_some_and(|f| f > MAF_5_50 && f < 1.0 - MAF_5_50))
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                matrix = matrix.select_rows(&common);
+            }
+            m_tot += matrix.nrows();
+            let xtx = matrix.tr_mul(&matrix);
+            overlap = Some(match overlap {
+                Some(acc) => acc + xtx,
+                None => xtx,
+            });
+        }
+        Ok((overlap.unwrap(), m_tot as f64))
+    };
+    read().map_err(|e| e.context("Error parsing .annot file."))
+}
+
+/// Write the per-category results of `--overlap-annot` as a tab-separated table, with the
+/// coefficients if `print_coefficients`.
+fn write_overlap_results(
+    path: &str,
+    results: &OverlapResults,
+    print_coefficients: bool,
+) -> Result<()> {
+    let mut header = vec![
+        "Category",
+        "Prop._SNPs",
+        "Prop._h2",
+        "Prop._h2_std_error",
+        "Enrichment",
+        "Enrichment_std_error",
+        "Enrichment_p",
+    ];
+    if print_coefficients {
+        header.extend([
+            "Coefficient",
+            "Coefficient_std_error",
+            "Coefficient_z-score",
+        ]);
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{}", header.join("\t"))?;
+    for i in 0..results.category.len() {
+        let mut row = vec![
+            results.category[i].clone(),
+            py_str(results.prop_snps[i]),
+            py_str(results.prop_h2[i]),
+            py_str(results.prop_h2_se[i]),
+            py_str(results.enrichment[i]),
+            py_str(results.enrichment_se[i]),
+            results.enrichment_p[i].map_or("NA".to_string(), py_str),
+        ];
+        if print_coefficients {
+            row.extend([
+                py_str(results.coef[i]),
+                py_str(results.coef_se[i]),
+                py_str(results.coef_z[i]),
+            ]);
+        }
+        writeln!(writer, "{}", row.join("\t"))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write `matrix` like `np.savetxt`: one line per row, values `%.18e` separated by spaces.
+fn savetxt(path: &str, matrix: &DMatrix<f64>) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for row in matrix.row_iter() {
+        let values = row.iter().map(|x| fmt_sci(*x, 18)).collect::<Vec<_>>();
+        writeln!(writer, "{}", values.join(" "))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Coefficient of the cell-type-specific annotation of one regression of `h2-cts`.
+#[derive(Debug, Clone)]
+pub struct CtsResult {
+    pub name: String,
+    pub coef: f64,
+    pub coef_se: f64,
+    /// One-sided P-value of coef > 0.
+    pub p: f64,
+}
+
+/// Read the name and LD score prefix(es) of each cell type in the .ldcts file `path`.
+fn read_ldcts(path: &str) -> Result<Vec<(String, String)>> {
+    let mut cell_types = Vec::new();
+    for line in get_input_reader(path)?.lines() {
+        let line = line?;
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        match fields.len() {
+            0 => continue,
+            2 => cell_types.push((fields[0].to_string(), fields[1].to_string())),
+            _ => bail!(
+                "Each line of {} must have a name and LD Score prefixes: {}",
+                path,
+                line
+            ),
+        }
+    }
+    Ok(cell_types)
+}
+
+/// Sort `results` by P-value, missing P-values last, like pandas' sort_values.
+fn sort_by_p(results: &mut [CtsResult]) {
+    results.sort_by(|a, b| match (a.p.is_nan(), b.p.is_nan()) {
+        (false, false) => a.p.total_cmp(&b.p),
+        (a_nan, b_nan) => a_nan.cmp(&b_nan),
+    });
+}
+
+/// Regress chi^2 on the cell-type-specific LD scores of `ct_ld_chr` jointly with the
+/// baseline LD scores `x`, and return the coefficients of the cell-type-specific ones.
+#[allow(clippy::too_many_arguments)]
+fn cts_regression(
+    args: &H2CtsArgs,
+    ct_ld_chr: &str,
+    snp_index: &HashMap<&str, usize>,
+    chisq: &DVector<f64>,
+    x: &DMatrix<f64>,
+    w: &DVector<f64>,
+    n: &DVector<f64>,
+    m_annot: &[f64],
+    options: &RegressionOptions,
+) -> Result<Vec<(f64, f64)>> {
+    let ref_ld_cts = read_chr_split_files(Some(ct_ld_chr), None, "cts reference panel LD Score")?;
+    info!("Performing regression.");
+    let cts_cnames = ld_cnames(&ref_ld_cts);
+    let n_cts = cts_cnames.len();
+    let mut cts = DMatrix::from_element(x.nrows(), n_cts, f64::NAN);
+    let snps = ref_ld_cts.column("SNP")?.str()?;
+    for (j, c) in cts_cnames.iter().enumerate() {
+        let values = ref_ld_cts.column(c)?.f64()?;
+        for (snp, v) in snps.into_iter().zip(values) {
+            if let (Some(&i), Some(v)) = (snp.and_then(|s| snp_index.get(s)), v) {
+                cts[(i, j)] = v;
+            }
+        }
+    }
+    if cts.iter().any(|v| v.is_nan()) {
+        bail!("Missing some LD scores from cts files. Are you sure all SNPs in ref-ld-chr are also in ref-ld-chr-cts");
+    }
+    let mut m = Vec::new();
+    for f in splitp(ct_ld_chr) {
+        m.extend(read_m(f, true, !args.ld.not_m_5_50)?);
+    }
+    if m.len() != n_cts {
+        bail!("# of M values of {} must match # of LD Scores.", ct_ld_chr);
+    }
+    m.extend_from_slice(m_annot);
+    let ref_ld = DMatrix::from_fn(x.nrows(), n_cts + x.ncols(), |i, j| match j < n_cts {
+        true => cts[(i, j)],
+        false => x[(i, j - n_cts)],
+    });
+    let hsqhat = Hsq::new(chisq, &ref_ld, w, n, &m, options)?;
+    Ok((0..n_cts)
+        .map(|j| (hsqhat.reg.coef[j], hsqhat.reg.coef_se[j]))
+        .collect())
+}
+
+/// Cell-type-specific analysis: for each cell type of the .ldcts file, regress chi^2 on its
+/// LD scores jointly with the baseline LD scores of `--ref-ld-chr`, in parallel, and write
+/// the coefficients sorted by P-value.
+pub fn cell_type_specific(args: &H2CtsArgs, global: &GlobalArgs) -> Result<Vec<CtsResult>> {
+    let mut ld_sumstats = read_ld_sumstats(&args.ld, &args.h2_cts, false)?;
+    warn_length(ld_sumstats.data.height());
+    if let Some(chisq_max) = chisq_max(&args.ld, &ld_sumstats, true)? {
+        let report = filter_chisq(&mut ld_sumstats, &args.h2_cts, chisq_max)?;
+        write_chisq_max_reports(&global.out, &[report])?;
+    }
+    let n_snp = ld_sumstats.data.height();
+    let options = RegressionOptions {
+        n_blocks: n_snp.min(args.ld.n_blocks),
+        blocks: jackknife_blocks(&args.ld, &ld_sumstats.data)?,
+        intercept: match args.no_intercept {
+            true => Some(1.0),
+            false => args.intercept_h2,
+        },
+        old_weights: true,
+        ..Default::default()
+    };
+    let chisq = ld_sumstats.column("Z")?.map(|z| z * z);
+    let x = ld_sumstats.ref_ld()?;
+    let w = ld_sumstats.column(W_LD_CNAME)?;
+    let n = ld_sumstats.column("N")?;
+    let snps = ld_sumstats.data.column("SNP")?.str()?;
+    let snp_index = snps
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, s)| s.map(|s| (s, i)))
+        .collect::<HashMap<_, _>>();
+
+    let cell_types = read_ldcts(&args.ref_ld_chr_cts)?;
+    let coefs = cell_types
+        .par_iter()
+        .map(|(_, ct_ld_chr)| {
+            cts_regression(
+                args,
+                ct_ld_chr,
+                &snp_index,
+                &chisq,
+                &x,
+                &w,
+                &n,
+                &ld_sumstats.m_annot,
+                &options,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut results = Vec::new();
+    for ((name, ct_ld_chr), coefs) in cell_types.iter().zip(coefs) {
+        // as many coefficients as LD Score prefixes, like ldsc
+        let n_print = match args.print_all_cts {
+            true => splitp(ct_ld_chr).len(),
+            false => 1,
+        };
+        for (i, (coef, coef_se)) in coefs.into_iter().take(n_print).enumerate() {
+            results.push(CtsResult {
+                name: match args.print_all_cts {
+                    true => format!("{}_{}", name, i),
+                    false => name.clone(),
+                },
+                coef,
+                coef_se,
+                p: ln_norm_sf(coef / coef_se).exp(),
+            });
+        }
+    }
+    sort_by_p(&mut results);
+
+    let fname = format!("{}.cell_type_results.txt", global.out);
+    let mut writer = BufWriter::new(File::create(&fname)?);
+    writeln!(
+        writer,
+        "Name\tCoefficient\tCoefficient_std_error\tCoefficient_P_value"
+    )?;
+    for r in &results {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            r.name,
+            py_str(r.coef),
+            py_str(r.coef_se),
+            py_str(r.p)
+        )?;
+    }
+    writer.flush()?;
+    info!("Results printed to {}", fname);
+    Ok(results)
+}
+
+/// Sample fraction of cases of the summary statistics in `path`, sum(N_CAS) / sum(N), or
+/// `None` if they have no N_CAS column.
+fn read_samp_prev(path: &str) -> Result<Option<f64>> {
+    let (header, _) = read_header(path, None)?;
+    if !header.iter().any(|c| c == "N_CAS") {
+        return Ok(None);
+    }
+    let mut schema = Schema::default();
+    for c in ["N", "N_CAS"] {
+        schema.with_column(c.into(), DataType::Float64);
+    }
+    let read_opts = CsvReadOptions::default()
+        .with_has_header(true)
+        .with_parse_options(
+            CsvParseOptions::default()
+                .with_null_values(Some(NullValues::AllColumns(vec![".".into(), "NA".into()]))),
+        )
+        .with_columns(Some(["N".into(), "N_CAS".into()].into()))
+        .with_schema_overwrite(Some(schema.into()));
+    let df = read_csv(path, read_opts, None)?;
+    let (mut n_cas, mut n) = (0.0, 0.0);
+    for (cas, tot) in df
+        .column("N_CAS")?
+        .f64()?
+        .into_iter()
+        .zip(df.column("N")?.f64()?)
+    {
+        if let (Some(cas), Some(tot)) = (cas, tot) {
+            n_cas += cas;
+            n += tot;
+        }
+    }
+    Ok((n > 0.0).then_some(n_cas / n))
+}
+
+/// Parse a comma-separated list of one value per trait, with N/A (NaN) for a missing value.
+fn split_floats(arg: &str, flag: &str, n: usize) -> Result<Vec<f64>> {
+    let values = splitp(&arg.replace("N/A", "nan"))
+        .iter()
+        .map(|x| x.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("Could not cast {} to float: {}", flag, e))?;
+    if values.len() != n {
+        bail!("{} must have one value per phenotype ({}).", flag, n);
+    }
+    Ok(values)
+}
+
+/// Prevalences of each trait in `paths` from `--samp-prev` and `--pop-prev`, `None` for a
+/// trait without a population prevalence. A missing sample prevalence is the fraction of
+/// cases of the N_CAS column of the summary statistics.
+fn read_prevalences(
+    samp_prev: Option<&str>,
+    pop_prev: Option<&str>,
+    paths: &[&str],
+) -> Result<Vec<Option<Prevalence>>> {
+    let n = paths.len();
+    let pop_prev = match (samp_prev, pop_prev) {
+        (_, Some(pop_prev)) => split_floats(pop_prev, "--pop-prev", n)?,
+        (None, None) => return Ok(vec![None; n]),
+        (Some(_), None) => bail!("Must set both or neither of --samp-prev and --pop-prev."),
+    };
+    let samp_prev = match samp_prev {
+        Some(samp_prev) => split_floats(samp_prev, "--samp-prev", n)?,
+        None => vec![f64::NAN; n],
+    };
+    let mut prevalences = Vec::with_capacity(n);
+    for ((path, k), p) in paths.iter().zip(pop_prev).zip(samp_prev) {
+        if k.is_nan() {
+            prevalences.push(None);
+            continue;
+        }
+        let p = match p.is_nan() {
+            false => p,
+            true => match read_samp_prev(path)? {
+                Some(p) => {
+                    info!(
+                        "Using sample prevalence {} from the N_CAS column of {}.",
+                        py_str(p),
+                        path
+                    );
+                    p
+                }
+                None => bail!(
+                    "No sample prevalence for {}: set --samp-prev or munge it with N_CAS and N_CON.",
+                    path
+                ),
+            },
+        };
+        prevalences.push(Some(Prevalence::new(p, k)?));
+    }
+    Ok(prevalences)
+}
+
+fn parse_rg(rg: &str) -> Result<Vec<&str>> {
+    let rg_paths = splitp(rg);
+    if rg_paths.len() < 2 {
+        bail!("Must specify at least two phenotypes for rg estimation.");
+    }
+    Ok(rg_paths)
+}
+
+/// Keep the rows of `data` selected by `ii`, logging `msg` with `{N}` the number kept.
+fn select_and_log(data: &DataFrame, ii: &[bool], msg: &str) -> Result<DataFrame> {
+    let new_len = ii.iter().filter(|x| **x).count();
+    let msg = msg.replace("{N}", &new_len.to_string());
+    if new_len == 0 {
+        bail!(msg);
+    }
+    info!("{}", msg);
+    Ok(data.filter(&ii.iter().copied().collect::<BooleanChunked>())?)
+}
+
+/// Merge the summary statistics of another trait in `p2` into those of the first trait,
+/// keeping the SNPs whose alleles match and aligning Z2 to the alleles of the first trait.
+fn read_other_sumstats(args: &RgArgs, p2: &str, sumstats: &LdSumstats) -> Result<LdSumstats> {
+    let mut other = read_sumstats(p2, true, false)?;
+    for (old, new) in [("A1", "A1x"), ("A2", "A2x"), ("N", "N2"), ("Z", "Z2")] {
+        other.rename(old, new.into())?;
+    }
+    let data = merge_and_log(&sumstats.data, &other, "summary statistics")?;
+    let mut data = drop_missing(data)?;
+    if !args.no_check_alleles {
+        let alleles = data
+            .clone()
+            .lazy()
+            .select([concat_str(
+                [col("A1"), col("A2"), col("A1x"), col("A2x")],
+                "",
+                false,
+            )])
+            .collect()?;
+        let alleles = alleles.get_columns()[0]
+            .str()?
+            .into_iter()
+            .map(|a| a.unwrap_or_default().to_string())
+            .collect::<Vec<_>>();
+        let ii = alleles
+            .iter()
+            .map(|a| FLIP_ALLELES.contains_key(a.as_str()))
+            .collect::<Vec<_>>();
+        data = select_and_log(&data, &ii, "{N} SNPs with valid alleles.")?;
+        let z2 = data
+            .column("Z2")?
+            .f64()?
+            .into_iter()
+            .zip(alleles.iter().zip(&ii).filter(|(_, k)| **k))
+            .map(|(z, (a, _))| z.map(|z| if FLIP_ALLELES[a.as_str()] { -z } else { z }))
+            .collect::<Float64Chunked>();
+        data.with_column(z2.into_series().with_name("Z2".into()))?;
+    }
+    let data = data.drop_many(["A1", "A1x", "A2", "A2x"]);
+    warn_length(data.height());
+    Ok(LdSumstats {
+        m_annot: sumstats.m_annot.clone(),
+        ref_ld_cnames: sumstats.ref_ld_cnames.clone(),
+        data,
+    })
+}
+
+/// The constrained h2 and gencov intercepts of each trait of `--rg`, if given (N/A leaves
+/// the intercept of a trait free). `--no-intercept` constrains them to 1 and 0.
+#[allow(clippy::type_complexity)]
+fn rg_intercepts(
+    args: &RgArgs,
+    n_pheno: usize,
+) -> Result<(Option<Vec<Option<f64>>>, Option<Vec<Option<f64>>>)> {
+    if args.no_intercept {
+        return Ok((
+            Some(vec![Some(1.0); n_pheno]),
+            Some(vec![Some(0.0); n_pheno]),
+        ));
+    }
+    let split = |arg: &Option<String>, flag| -> Result<Option<Vec<Option<f64>>>> {
+        arg.as_deref()
+            .map(|a| {
+                Ok(split_floats(a, flag, n_pheno)?
+                    .into_iter()
+                    .map(|x| (!x.is_nan()).then_some(x))
+                    .collect())
+            })
+            .transpose()
+    };
+    Ok((
+        split(&args.intercept_h2, "--intercept-h2")?,
+        split(&args.intercept_gencov, "--intercept-gencov")?,
+    ))
+}
+
+/// Estimate h2 of both traits, their genetic covariance and rg.
+fn rg(
+    args: &LdArgs,
+    sumstats: LdSumstats,
+    options: &RegressionOptions,
+    intercepts: [Option<f64>; 3],
+) -> Result<Rg> {
+    let n_snp = sumstats.data.height();
+    let options = RegressionOptions {
+        n_blocks: n_snp.min(options.n_blocks),
+        blocks: jackknife_blocks(args, &sumstats.data)?,
+        ..options.clone()
+    };
+    Rg::new(
+        &sumstats.column("Z1")?,
+        &sumstats.column("Z2")?,
+        &sumstats.ref_ld()?,
+        &sumstats.column(W_LD_CNAME)?,
+        &sumstats.column("N1")?,
+        &sumstats.column("N2")?,
+        &sumstats.m_annot,
+        intercepts,
+        &options,
+    )
+}
+
+/// Write the jackknife delete values and pseudovalues of the h2 of both traits, their
+/// genetic covariance, rg and the estimated intercepts of the first trait and each of
+/// `p2s`, NA where not estimated.
+fn write_rg_jackknife(path: &str, p2s: &[&str], rgs: &[Option<Rg>]) -> Result<()> {
+    let names = [
+        "h2_1",
+        "h2_2",
+        "gencov",
+        "rg",
+        "intercept_1",
+        "intercept_2",
+        "intercept_gencov",
+    ];
+    fn tot(reg: &LdScoreRegression) -> Option<(f64, &[f64])> {
+        Some((reg.tot, reg.tot_delete_values.as_slice()))
+    }
+    fn intercept(reg: &LdScoreRegression) -> Option<(f64, &[f64])> {
+        reg.intercept_delete_values
+            .as_deref()
+            .map(|dv| (reg.intercept, dv))
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut header = vec!["p2".to_string()];
+    header.extend(jackknife_header(&names));
+    writeln!(writer, "{}", header.join("\t"))?;
+    for (p2, rghat) in p2s.iter().zip(rgs) {
+        let Some(rghat) = rghat else { continue };
+        let quantities = [
+            tot(&rghat.hsq1.reg),
+            tot(&rghat.hsq2.reg),
+            tot(&rghat.gencov.reg),
+            rghat
+                .rg
+                .as_ref()
+                .map(|r| (r.rg_ratio, r.delete_values.as_slice())),
+            intercept(&rghat.hsq1.reg),
+            intercept(&rghat.hsq2.reg),
+            intercept(&rghat.gencov.reg),
+        ];
+        for row in jackknife_rows(&quantities) {
+            writeln!(writer, "{}\t{}", p2, row.join("\t"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Regression options of rg estimation with `n_annot` reference LD scores and, if
+/// `constrained`, constrained h2 intercepts.
+fn rg_options(args: &RgArgs, n_annot: usize, constrained: bool) -> RegressionOptions {
+    let mut options = RegressionOptions {
+        n_blocks: args.ld.n_blocks,
+        twostep: args.two_step,
+        ..Default::default()
+    };
+    if n_annot == 1 && options.twostep.is_none() && !constrained {
+        options.twostep = Some(TWO_STEP_CUTOFF);
+    }
+    log_twostep(options.twostep, args.two_step);
+    options
+}
+
+/// Whether a SNP with z-scores `z1` and `z2` passes `--chisq-max`, which ldsc applies to
+/// the product of the chi^2 of both traits.
+fn below_chisq_max(z1: f64, z2: f64, chisq_max: Option<f64>) -> bool {
+    chisq_max.is_none_or(|c| (z1 * z2).powi(2) < c.powi(2))
+}
+
+/// `title` underlined with dashes, after an empty line.
+fn heading(title: &str) -> String {
+    format!("\n{}\n{}", title, "-".repeat(title.len()))
+}
+
+/// Log the results of the rg of the first trait and trait `i + 2`, whose prevalences (if
+/// binary) are `prevalences`.
+fn print_gencor(
+    rghat: &Rg,
+    ref_ld_cnames: &[String],
+    i: usize,
+    n_pheno: usize,
+    prevalences: [Option<Prevalence>; 2],
+    print_hsq1: bool,
+) {
+    if print_hsq1 {
+        info!("{}", heading("Heritability of phenotype 1"));
+        info!(
+            "{}",
+            rghat.hsq1.summary(ref_ld_cnames, prevalences[0], false)
+        );
+    }
+    info!(
+        "{}",
+        heading(&format!("Heritability of phenotype {}/{}", i + 2, n_pheno))
+    );
+    info!(
+        "{}",
+        rghat.hsq2.summary(ref_ld_cnames, prevalences[1], false)
+    );
+    info!("{}", heading("Genetic Covariance"));
+    info!("{}", rghat.gencov.summary(ref_ld_cnames, prevalences));
+    info!("{}", heading("Genetic Correlation"));
+    info!("{}\n", rghat.summary());
+}
+
+/// Estimate rg between the first trait of `--rg` and each of the others.
+///
+/// A trait that fails is reported and skipped (`None`), so that one bad file does not
+/// stop a long list.
+pub fn estimate_rg(args: &RgArgs, global: &GlobalArgs) -> Result<Vec<Option<Rg>>> {
+    let rg_paths = parse_rg(&args.rg)?;
+    let n_pheno = rg_paths.len();
+    let prevalences = read_prevalences(
+        args.samp_prev.as_deref(),
+        args.pop_prev.as_deref(),
+        &rg_paths,
+    )?;
+    let (intercept_h2, intercept_gencov) = rg_intercepts(args, n_pheno)?;
+    let intercept_gencov = intercept_gencov.unwrap_or_else(|| vec![None; n_pheno]);
+    let mut sumstats = read_ld_sumstats(&args.ld, rg_paths[0], true)?;
+    sumstats.data.rename("Z", "Z1".into())?;
+    sumstats.data.rename("N", "N1".into())?;
+    let options = rg_options(args, sumstats.ref_ld_cnames.len(), intercept_h2.is_some());
+    let intercept_h2 = intercept_h2.unwrap_or_else(|| vec![None; n_pheno]);
+
+    let mut rgs = Vec::new();
+    let mut chisq_reports = Vec::new();
+    for (i, p2) in rg_paths[1..].iter().enumerate() {
+        info!("Computing rg for phenotype {}/{}", i + 2, n_pheno);
+        let intercepts = [
+            intercept_h2[0],
+            intercept_h2[i + 1],
+            intercept_gencov[i + 1],
+        ];
+        let rghat = read_other_sumstats(args, p2, &sumstats).and_then(|mut other| {
+            if let Some(chisq_max) = args.ld.chisq_max {
+                let traits = [rg_paths[0], p2];
+                chisq_reports.push(filter_chisq_product(&mut other, traits, chisq_max)?);
+            }
+            rg(&args.ld, other, &options, intercepts)
+        });
+        match rghat {
+            Ok(rghat) => {
+                print_gencor(
+                    &rghat,
+                    &sumstats.ref_ld_cnames,
+                    i,
+                    n_pheno,
+                    [prevalences[0], prevalences[i + 1]],
+                    i == 0,
+                );
+                rgs.push(Some(rghat));
+            }
+            Err(e) => {
+                error!(
+                    "ERROR computing rg for phenotype {}/{}, from file {}.\n{:#}\n",
+                    i + 2,
+                    n_pheno,
+                    p2,
+                    e
+                );
+                rgs.push(None);
+            }
+        }
+    }
+    info!(
+        "\nSummary of Genetic Correlation Results\n{}",
+        rg_table(&rg_paths, &rgs, &prevalences[1..])
+    );
+    write_chisq_max_reports(&global.out, &chisq_reports)?;
+    if args.print_jackknife {
+        let fname = format!("{}.jackknife.tsv", global.out);
+        info!(
+            "Printing block jackknife delete values and pseudovalues to {}.",
+            fname
+        );
+        write_rg_jackknife(&fname, &rg_paths[1..], &rgs)?;
+    }
+    Ok(rgs)
+}
+
+// decimals pandas prints (pd.set_option('precision', 4) in ldsc)
+const TABLE_PRECISION: usize = 4;
+
+/// Python's `'{: .4g}'.format(x)`.
+fn fmt_g(x: f64) -> String {
+    if !x.is_finite() {
+        return format!(" {}", py_str(x));
+    }
+    let sign = if x < 0.0 { "-" } else { " " };
+    if x == 0.0 {
+        return format!("{}0", sign);
+    }
+    let sci = format!("{:.*e}", TABLE_PRECISION - 1, x.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp = exp.parse::<i32>().unwrap();
+    let trim = |s: &str| match s.contains('.') {
+        true => s.trim_end_matches('0').trim_end_matches('.').to_string(),
+        false => s.to_string(),
+    };
+    if exp < -4 || exp >= TABLE_PRECISION as i32 {
+        let exp_sign = if exp < 0 { '-' } else { '+' };
+        format!("{}{}e{}{:02}", sign, trim(mantissa), exp_sign, exp.abs())
+    } else {
+        let decimals = (TABLE_PRECISION as i32 - 1 - exp) as usize;
+        format!("{}{}", sign, trim(&format!("{:.*}", decimals, x.abs())))
+    }
+}
+
+/// C's `%.<precision>e`, e.g. `1.5e-03`.
+fn fmt_sci(x: f64, precision: usize) -> String {
+    if !x.is_finite() {
+        return py_str(x);
+    }
+    let sci = format!("{:.*e}", precision, x);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp = exp.parse::<i32>().unwrap();
+    let exp_sign = if exp < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, exp_sign, exp.abs())
+}
+
+/// Python's `'{: .4e}'.format(x)`.
+fn fmt_e(x: f64) -> String {
+    let sign = if x < 0.0 { "" } else { " " };
+    format!("{}{}", sign, fmt_sci(x, TABLE_PRECISION))
+}
+
+/// Format a column of numbers like pandas' `DataFrame.to_string` does with precision 4: a
+/// column with missing values is an object column of `{: .4g}` floats and " NA"; otherwise
+/// `{: .4f}` with common trailing zeros trimmed, or `{: .4e}` if any value would round to 0.
+fn format_column(values: &[Option<f64>]) -> Vec<String> {
+    if values.iter().any(|v| v.is_none()) {
+        return values
+            .iter()
+            .map(|v| match v {
+                Some(x) => fmt_g(*x),
+                None => " NA".to_string(),
+            })
+            .collect();
+    }
+    let values = values.iter().map(|v| v.unwrap()).collect::<Vec<_>>();
+    let na_rep = "NaN";
+    let fixed = values
+        .iter()
+        .map(|x| match x.is_nan() {
+            true => na_rep.to_string(),
+            false => format!(
+                "{}{:.*}",
+                if *x < 0.0 { "" } else { " " },
+                TABLE_PRECISION,
+                x
+            ),
+        })
+        .collect::<Vec<_>>();
+    let abs_vals = values.iter().filter(|x| !x.is_nan()).map(|x| x.abs());
+    let has_large_values = abs_vals.clone().any(|x| x > 1e6);
+    let has_small_values = abs_vals
+        .clone()
+        .any(|x| x > 0.0 && x < 10f64.powi(-(TABLE_PRECISION as i32)));
+    let too_long = fixed.iter().map(|s| s.len()).max().unwrap_or(0) > TABLE_PRECISION + 6;
+    if has_small_values || (too_long && has_large_values) {
+        return values
+            .iter()
+            .map(|x| match x.is_nan() {
+                true => na_rep.to_string(),
+                false => fmt_e(*x),
+            })
+            .collect();
+    }
+    let mut trimmed = fixed;
+    loop {
+        let non_na = trimmed.iter().filter(|x| *x != na_rep).collect::<Vec<_>>();
+        if non_na.is_empty() || !non_na.iter().all(|x| x.ends_with('0')) {
+            break;
+        }
+        trimmed = trimmed
+            .into_iter()
+            .map(|x| match x == na_rep {
+                true => x,
+                false => x[..x.len() - 1].to_string(),
+            })
+            .collect();
+    }
+    trimmed
+        .into_iter()
+        .map(|x| match x.ends_with('.') {
+            true => format!("{}0", x),
+            false => x,
+        })
+        .collect()
+}
+
+/// The table of rg results ldsc prints at the end, with one row per pair of traits.
+///
+/// With the prevalence of any trait, the liability-scale h2 of the second trait of each pair
+/// follows its observed-scale h2.
+fn rg_table(rg_paths: &[&str], rgs: &[Option<Rg>], prevalences: &[Option<Prevalence>]) -> String {
+    let est = |f: fn(&RgEstimate) -> f64| {
+        rgs.iter()
+            .map(|r| r.as_ref().and_then(|r| r.rg.as_ref()).map(f))
+            .collect::<Vec<_>>()
+    };
+    let reg = |f: fn(&Rg) -> Option<f64>| {
+        rgs.iter()
+            .map(|r| r.as_ref().and_then(f))
+            .collect::<Vec<_>>()
+    };
+    let mut columns = vec![
+        (
+            "p1",
+            rgs.iter().map(|_| format!(" {}", rg_paths[0])).collect(),
+        ),
+        (
+            "p2",
+            rg_paths[1..].iter().map(|p| format!(" {}", p)).collect(),
+        ),
+    ];
+    let mut numeric: Vec<(&str, Vec<Option<f64>>)> = vec![
+        ("rg", est(|r| r.rg_ratio)),
+        ("se", est(|r| r.rg_se)),
+        ("z", est(|r| r.z)),
+        ("p", est(|r| r.p)),
+        ("h2_obs", reg(|r| Some(r.hsq2.reg.tot))),
+        ("h2_obs_se", reg(|r| Some(r.hsq2.reg.tot_se))),
+    ];
+    if prevalences.iter().any(|p| p.is_some()) {
+        let liab = |f: fn(&Rg) -> f64| {
+            rgs.iter()
+                .zip(prevalences)
+                .map(|(r, p)| Some(f(r.as_ref()?) * p.as_ref()?.h2_liab_factor()))
+                .collect::<Vec<_>>()
+        };
+        numeric.push(("h2_liab", liab(|r| r.hsq2.reg.tot)));
+        numeric.push(("h2_liab_se", liab(|r| r.hsq2.reg.tot_se)));
+    }
+    numeric.extend([
+        ("h2_int", reg(|r| Some(r.hsq2.reg.intercept))),
+        ("h2_int_se", reg(|r| r.hsq2.reg.intercept_se)),
+        ("gcov_int", reg(|r| Some(r.gencov.reg.intercept))),
+        ("gcov_int_se", reg(|r| r.gencov.reg.intercept_se)),
+    ]);
+    columns.extend(
+        numeric
+            .into_iter()
+            .map(|(name, values)| (name, format_column(&values))),
+    );
+    let widths = columns
+        .iter()
+        .map(|(name, cells)| {
+            cells
+                .iter()
+                .map(|c| c.len())
+                .chain([name.len()])
+                .max()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let mut lines = vec![columns
+        .iter()
+        .zip(&widths)
+        .map(|((name, _), w)| format!("{:>w$}", name))
+        .collect::<Vec<_>>()
+        .join(" ")];
+    for i in 0..rgs.len() {
+        lines.push(
+            columns
+                .iter()
+                .zip(&widths)
+                .map(|((_, cells), w)| format!("{:>w$}", cells[i]))
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+    }
+    format!("{}\n", lines.join("\n"))
+}
+
+/// Genetic correlations between every pair of traits of `rg --matrix`. The matrices are
+/// symmetric, in the order of `traits`; missing estimates (and the SE and P of the
+/// diagonal) are `None`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RgMatrix {
+    pub traits: Vec<String>,
+    pub rg: Vec<Vec<Option<f64>>>,
+    pub se: Vec<Vec<Option<f64>>>,
+    pub p: Vec<Vec<Option<f64>>>,
+}
+
+/// Z-scores and sample sizes of one trait, aligned to the rows of the LD scores.
+struct AlignedSumstats {
+    /// Z-scores signed with respect to the canonical alleles, NaN where the SNP is missing.
+    z: Vec<f64>,
+    n: Vec<f64>,
+    /// Canonical alleles of each SNP, `None` if alleles are not checked.
+    alleles: Vec<Option<&'static str>>,
+}
+
+/// For each pair of non-strand-ambiguous alleles, the canonical pair of the alleles that
+/// match it up to a strand and/or reference flip, and whether the reference allele is
+/// flipped relative to the canonical pair. Two SNPs match as in `FLIP_ALLELES` if and only
+/// if they have the same canonical alleles, and are flipped relative to each other if
+/// exactly one of them is flipped.
+fn canonical_alleles() -> HashMap<&'static str, (&'static str, bool)> {
+    let mut canonical = HashMap::<&'static str, &'static str>::new();
+    for key in FLIP_ALLELES.keys() {
+        let (a, b) = key.split_at(2);
+        let c = canonical.entry(a).or_insert(b);
+        if b < *c {
+            *c = b;
+        }
+    }
+    canonical
+        .into_iter()
+        .map(|(a, c)| (a, (c, FLIP_ALLELES[format!("{}{}", c, a).as_str()])))
+        .collect()
+}
+
+/// Read the summary statistics in `path` and align them to the `n_snp` rows of the LD
+/// scores indexed by `snp_index`, signing Z by the canonical alleles of each SNP.
+fn align_sumstats(
+    path: &str,
+    snp_index: &HashMap<&str, Vec<usize>>,
+    n_snp: usize,
+    canonical: Option<&HashMap<&'static str, (&'static str, bool)>>,
+) -> Result<AlignedSumstats> {
+    let df = read_sumstats(path, true, true)?;
+    let mut aligned = AlignedSumstats {
+        z: vec![f64::NAN; n_snp],
+        n: vec![f64::NAN; n_snp],
+        alleles: vec![None; n_snp],
+    };
+    let (mut n_merged, mut n_valid) = (0, 0);
+    let rows = df
+        .column("SNP")?
+        .str()?
+        .into_iter()
+        .zip(df.column("A1")?.str()?)
+        .zip(df.column("A2")?.str()?)
+        .zip(df.column("Z")?.f64()?)
+        .zip(df.column("N")?.f64()?);
+    for ((((snp, a1), a2), z), n) in rows {
+        let (Some(rows), Some(z), Some(n)) = (snp.and_then(|s| snp_index.get(s)), z, n) else {
+            continue;
+        };
+        n_merged += rows.len();
+        let (alleles, sign) = match canonical {
+            Some(canonical) => {
+                let a = format!("{}{}", a1.unwrap_or_default(), a2.unwrap_or_default());
+                match canonical.get(a.as_str()) {
+                    Some((c, flip)) => (Some(*c), if *flip { -1.0 } else { 1.0 }),
+                    None => continue,
+                }
+            }
+            None => (None, 1.0),
+        };
+        n_valid += rows.len();
+        for &i in rows {
+            aligned.z[i] = sign * z;
+            aligned.n[i] = n;
+            aligned.alleles[i] = alleles;
+        }
+    }
+    let msg = format!(
+        "After merging with the LD Scores, {} SNPs remain.",
+        n_merged
+    );
+    if n_merged == 0 {
+        bail!(msg);
+    }
+    info!("{}", msg);
+    if canonical.is_some() {
+        if n_valid == 0 {
+            bail!("0 SNPs with valid alleles.");
+        }
+        info!("{} SNPs with valid alleles.", n_valid);
+    }
+    Ok(aligned)
+}
+
+/// Estimate rg between the aligned traits `t1` and `t2`, named `traits`, on the SNPs present
+/// in both whose alleles match and that pass `chisq_max`, with the SNPs `chisq_max` removed.
+/// The jackknife blocks of `options`, if any, are those of all SNPs of `ld`.
+#[allow(clippy::too_many_arguments)]
+fn rg_pair(
+    ld: &LdSumstats,
+    x: &DMatrix<f64>,
+    w: &DVector<f64>,
+    t1: &AlignedSumstats,
+    t2: &AlignedSumstats,
+    traits: [&str; 2],
+    chisq_max: Option<f64>,
+    options: &RegressionOptions,
+    intercepts: [Option<f64>; 3],
+) -> Result<(Rg, Option<ChisqMaxReport>)> {
+    let (idx, removed): (Vec<_>, Vec<_>) = (0..ld.data.height())
+        .filter(|&i| t1.z[i].is_finite() && t2.z[i].is_finite() && t1.alleles[i] == t2.alleles[i])
+        .partition(|&i| below_chisq_max(t1.z[i], t2.z[i], chisq_max));
+    if idx.is_empty() {
+        bail!("No SNPs shared by both traits.");
+    }
+    let select = |v: &[f64]| DVector::from_iterator(idx.len(), idx.iter().map(|&i| v[i]));
+    let options = RegressionOptions {
+        n_blocks: idx.len().min(options.n_blocks),
+        blocks: options
+            .blocks
+            .as_ref()
+            .map(|b| idx.iter().map(|&i| b[i]).collect()),
+        ..options.clone()
+    };
+    let rghat = Rg::new(
+        &select(&t1.z),
+        &select(&t2.z),
+        &x.select_rows(&idx),
+        &w.select_rows(&idx),
+        &select(&t1.n),
+        &select(&t2.n),
+        &ld.m_annot,
+        intercepts,
+        &options,
+    )?;
+    let report = chisq_max.map(|chisq_max| ChisqMaxReport {
+        traits: traits.iter().map(|t| t.to_string()).collect(),
+        chisq_max,
+        removed: removed.len(),
+        remaining: idx.len(),
+    });
+    Ok((rghat, report))
+}
+
+/// Estimate rg between every pair of traits of `--rg`, reading the LD scores and each
+/// trait once and evaluating the pairs in parallel, and write the rg, SE and P matrices.
+///
+/// Each pair is estimated on the SNPs shared by both traits, as `rg` does for the first
+/// trait and each of the others. A trait or pair that fails is reported and left missing.
+pub fn estimate_rg_matrix(args: &RgArgs, global: &GlobalArgs) -> Result<RgMatrix> {
+    let rg_paths = parse_rg(&args.rg)?;
+    let n_pheno = rg_paths.len();
+    let ref_ld = read_ref_ld(&args.ld)?;
+    let ref_ld_cnames = ld_cnames(&ref_ld);
+    let m_annot = read_m_annot(&args.ld, ref_ld_cnames.len())?;
+    let w_ld = read_w_ld(&args.ld)?;
+    let data = merge_and_log(&ref_ld, &w_ld, "regression SNP LD")?;
+    // every pair is regressed on these SNPs, so the LD scores must vary over them
+    let (m_annot, data, ref_ld_cnames) = check_variance(m_annot, data, ref_ld_cnames)?;
+    let ld = LdSumstats {
+        m_annot,
+        data,
+        ref_ld_cnames,
+    };
+    let x = ld.ref_ld()?;
+    let w = ld.column(W_LD_CNAME)?;
+    let snps = ld.data.column("SNP")?.str()?;
+    // a SNP duplicated in the LD scores gets the summary statistics in every row, as in rg
+    let mut snp_index = HashMap::<&str, Vec<usize>>::new();
+    for (i, snp) in snps.into_iter().enumerate() {
+        if let Some(snp) = snp {
+            snp_index.entry(snp).or_default().push(i);
+        }
+    }
+    let canonical = canonical_alleles();
+    let canonical = (!args.no_check_alleles).then_some(&canonical);
+
+    let traits = rg_paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            info!("Aligning phenotype {}/{}", i + 1, n_pheno);
+            match align_sumstats(path, &snp_index, ld.data.height(), canonical) {
+                Ok(aligned) => Some(aligned),
+                Err(e) => {
+                    error!(
+                        "ERROR reading phenotype {}/{}, from file {}.\n{:#}\n",
+                        i + 1,
+                        n_pheno,
+                        path,
+                        e
+                    );
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let (intercept_h2, intercept_gencov) = rg_intercepts(args, n_pheno)?;
+    let mut options = rg_options(args, ld.ref_ld_cnames.len(), intercept_h2.is_some());
+    options.blocks = jackknife_blocks(&args.ld, &ld.data)?;
+    let intercept_h2 = intercept_h2.unwrap_or_else(|| vec![None; n_pheno]);
+    // the same for every pair: only --no-intercept constrains it in matrix mode
+    let intercept_gencov = intercept_gencov.and_then(|i| i[0]);
+    let pairs = (0..n_pheno)
+        .flat_map(|i| (i + 1..n_pheno).map(move |j| (i, j)))
+        .filter(|(i, j)| traits[*i].is_some() && traits[*j].is_some())
+        .collect::<Vec<_>>();
+    info!(
+        "Computing rg for {} pairs of phenotypes on {} threads.",
+        pairs.len(),
+        rayon::current_num_threads()
+    );
+    let estimates = pairs
+        .par_iter()
+        .map(|&(i, j)| {
+            let (t1, t2) = (traits[i].as_ref().unwrap(), traits[j].as_ref().unwrap());
+            let intercepts = [intercept_h2[i], intercept_h2[j], intercept_gencov];
+            let names = [rg_paths[i], rg_paths[j]];
+            let chisq_max = args.ld.chisq_max;
+            match rg_pair(&ld, &x, &w, t1, t2, names, chisq_max, &options, intercepts) {
+                Ok((rghat, report)) => {
+                    match &rghat.rg {
+                        Some(r) => info!(
+                            "Genetic correlation of {} and {}: {} ({})",
+                            rg_paths[i],
+                            rg_paths[j],
+                            np_str(&[r.rg_ratio]),
+                            np_str(&[r.rg_se])
+                        ),
+                        None => info!(
+                            "Genetic correlation of {} and {}: NA (h2 out of bounds)",
+                            rg_paths[i], rg_paths[j]
+                        ),
+                    }
+                    (rghat.rg, report)
+                }
+                Err(e) => {
+                    error!(
+                        "ERROR computing rg for {} and {}.\n{:#}\n",
+                        rg_paths[i], rg_paths[j], e
+                    );
+                    (None, None)
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    let (estimates, chisq_reports): (Vec<_>, Vec<_>) = estimates.into_iter().unzip();
+
+    let mut matrix = RgMatrix {
+        traits: rg_paths.iter().map(|p| p.to_string()).collect(),
+        rg: vec![vec![None; n_pheno]; n_pheno],
+        se: vec![vec![None; n_pheno]; n_pheno],
+        p: vec![vec![None; n_pheno]; n_pheno],
+    };
+    for (i, trait_) in traits.iter().enumerate() {
+        if trait_.is_some() {
+            matrix.rg[i][i] = Some(1.0);
+        }
+    }
+    for ((i, j), est) in pairs.into_iter().zip(estimates) {
+        if let Some(r) = est {
+            for (a, b) in [(i, j), (j, i)] {
+                matrix.rg[a][b] = Some(r.rg_ratio);
+                matrix.se[a][b] = Some(r.rg_se);
+                matrix.p[a][b] = Some(r.p);
+            }
+        }
+    }
+    write_rg_matrix(&matrix, &global.out)?;
+    let chisq_reports = chisq_reports.into_iter().flatten().collect::<Vec<_>>();
+    write_chisq_max_reports(&global.out, &chisq_reports)?;
+    Ok(matrix)
+}
+
+/// Write the matrices of `matrix` to `{out}.rg.tsv`, `{out}.rg_se.tsv`, `{out}.rg_p.tsv`
+/// and all of them to `{out}.rg.json`.
+fn write_rg_matrix(matrix: &RgMatrix, out: &str) -> Result<()> {
+    for (suffix, values) in [
+        ("rg", &matrix.rg),
+        ("rg_se", &matrix.se),
+        ("rg_p", &matrix.p),
+    ] {
+        let fname = format!("{}.{}.tsv", out, suffix);
+        info!("Writing {} matrix to {}.", suffix, fname);
+        let mut writer = BufWriter::new(File::create(fname)?);
+        writeln!(writer, "trait\t{}", matrix.traits.join("\t"))?;
+        for (name, row) in matrix.traits.iter().zip(values) {
+            let cells = row
+                .iter()
+                .map(|v| v.map_or("NA".to_string(), |v| v.to_string()))
+                .collect::<Vec<_>>();
+            writeln!(writer, "{}\t{}", name, cells.join("\t"))?;
+        }
+        writer.flush()?;
+    }
+    let fname = format!("{}.rg.json", out);
+    info!("Writing rg matrices to {}.", fname);
+    let writer = BufWriter::new(File::create(fname)?);
+    serde_json::to_writer_pretty(writer, matrix)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn canonical_alleles_agree_with_flip_alleles() {
+        let canonical = canonical_alleles();
+        assert_eq!(canonical.len(), 8);
+        for (a, (ca, fa)) in &canonical {
+            for (b, (cb, fb)) in &canonical {
+                let key = format!("{}{}", a, b);
+                assert_eq!(ca == cb, FLIP_ALLELES.contains_key(key.as_str()));
+                if ca == cb {
+                    assert_eq!(fa != fb, FLIP_ALLELES[key.as_str()]);
+                }
+            }
+        }
+    }
+
+    fn rg_cli(args: &[&str]) -> (RgArgs, GlobalArgs) {
+        use crate::cli::{Cli, Commands};
+        use clap::Parser;
+
+        let cli = Cli::parse_from(["ldscrs", "rg"].iter().chain(args));
+        match cli.command {
+            Commands::Rg(args) => (args, cli.global),
+            _ => unreachable!(),
+        }
+    }
+
+    fn rg_args(extra: &[&str]) -> RgArgs {
+        let args = ["--rg", "a,b", "--ref-ld", "ld", "--w-ld", "w"];
+        rg_cli(&[&args[..], extra].concat()).0
+    }
+
+    fn strings(dat: &DataFrame, name: &str) -> Vec<String> {
+        dat.column(name)
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    fn floats(dat: &DataFrame, name: &str) -> Vec<f64> {
+        dat.column(name)
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect()
+    }
+
+    #[test]
+    fn other_sumstats_are_aligned_to_the_first_trait() {
+        let dir = TempDir::new();
+        let data = df!(
+            "SNP" => ["rs1", "rs2", "rs3", "rs4", "rs5"],
+            "A1" => ["A"; 5],
+            "A2" => ["G"; 5],
+            "Z" => [1.0; 5],
+            "N" => [1000.0; 5],
+            "L2" => [1.0, 2.0, 3.0, 4.0, 5.0],
+        )
+        .unwrap();
+        let sumstats = LdSumstats {
+            m_annot: vec![5.0],
+            ref_ld_cnames: vec!["L2".to_string()],
+            data,
+        };
+        // matching, reference-flipped, strand-flipped, mismatched, and both flipped
+        let p2 = dir.write(
+            "rg_alleles.sumstats",
+            "SNP\tA1\tA2\tN\tZ\n\
+             rs1\tA\tG\t500\t2.0\n\
+             rs2\tG\tA\t500\t2.0\n\
+             rs3\tT\tC\t500\t2.0\n\
+             rs4\tA\tC\t500\t2.0\n\
+             rs5\tC\tT\t500\t2.0\n",
+        );
+
+        let merged = read_other_sumstats(&rg_args(&[]), &p2, &sumstats).unwrap();
+        assert_eq!(strings(&merged.data, "SNP"), ["rs1", "rs2", "rs3", "rs5"]);
+        assert_eq!(floats(&merged.data, "Z2"), [2.0, -2.0, 2.0, -2.0]);
+        assert_eq!(floats(&merged.data, "Z"), [1.0; 4]);
+        assert!(merged.data.column("A1").is_err());
+
+        // without the check every SNP is kept as it is
+        let merged =
+            read_other_sumstats(&rg_args(&["--no-check-alleles"]), &p2, &sumstats).unwrap();
+        assert_eq!(floats(&merged.data, "Z2"), [2.0; 5]);
+    }
+
+    #[test]
+    fn na_intercepts_are_left_free() {
+        let args = rg_args(&[
+            "--intercept-h2",
+            "1.05,N/A",
+            "--intercept-gencov",
+            "N/A,0.1",
+        ]);
+        let (h2, gencov) = rg_intercepts(&args, 2).unwrap();
+        assert_eq!(h2, Some(vec![Some(1.05), None]));
+        assert_eq!(gencov, Some(vec![None, Some(0.1)]));
+        assert!(rg_options(&args, 1, true).twostep.is_none());
+
+        let (h2, gencov) = rg_intercepts(&rg_args(&[]), 2).unwrap();
+        assert!(h2.is_none() && gencov.is_none());
+        let (h2, gencov) = rg_intercepts(&rg_args(&["--no-intercept"]), 2).unwrap();
+        assert_eq!(h2, Some(vec![Some(1.0); 2]));
+        assert_eq!(gencov, Some(vec![Some(0.0); 2]));
+        assert!(rg_intercepts(&rg_args(&["--intercept-h2", "1.0"]), 2).is_err());
+    }
+
+    /// Write LD scores, weights and three traits with correlated Z-scores for `n_snp` SNPs,
+    /// and return the prefixes of the LD scores and weights, and the sumstats files.
+    fn write_rg_fixture(dir: &TempDir, name: &str, n_snp: usize) -> (String, String, Vec<String>) {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut normal = || {
+            let (u1, u2): (f64, f64) = (rng.gen(), rng.gen());
+            (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+        };
+        let mut ref_ld = String::from("CHR\tSNP\tBP\tL2\tFLATL2\n");
+        let mut w_ld = String::from("CHR\tSNP\tBP\tL2\n");
+        let mut traits = vec![String::from("SNP\tA1\tA2\tN\tZ\n"); 3];
+        for i in 0..n_snp {
+            let chr = 1 + 2 * i / n_snp;
+            let l2 = 1.0 + 50.0 * (i % 97) as f64 / 97.0;
+            let line = format!("{}\trs{}\t{}\t{}", chr, i, 1000 * i, l2);
+            ref_ld += &format!("{}\t5\n", line);
+            if i == n_snp / 3 {
+                // a SNP duplicated in the reference LD scores
+                ref_ld += &format!("{}\t5\n", line);
+            }
+            w_ld += &format!("{}\n", line);
+
+            let scale = (0.5 * 10000.0 * l2 / n_snp as f64).sqrt();
+            let (a, b) = (normal(), normal());
+            let g = [a, 0.6 * a + 0.8 * b, -0.3 * a + normal()];
+            for (t, (text, g)) in traits.iter_mut().zip(g).enumerate() {
+                let z = scale * g + normal();
+                // the second trait has some reference-flipped and strand-flipped SNPs
+                let (a1, a2, z) = match (t, i % 7, i % 11) {
+                    (1, 0, _) => ("G", "A", -z),
+                    (1, _, 0) => ("T", "C", z),
+                    _ => ("A", "G", z),
+                };
+                // and the third some missing SNPs
+                if t != 2 || i % 5 != 0 {
+                    *text += &format!("rs{}\t{}\t{}\t10000\t{}\n", i, a1, a2, z);
+                }
+            }
+        }
+        let ref_path = dir.write(&format!("{}_ref.l2.ldscore", name), &ref_ld);
+        let m = format!("{}\t{}\n", n_snp, n_snp);
+        dir.write(&format!("{}_ref.l2.M_5_50", name), &m);
+        let w_path = dir.write(&format!("{}_w.l2.ldscore", name), &w_ld);
+        let prefix = |p: String| p.trim_end_matches(".l2.ldscore").to_string();
+        let traits = traits
+            .iter()
+            .enumerate()
+            .map(|(t, text)| dir.write(&format!("{}_{}.sumstats", name, t), text))
+            .collect();
+        (prefix(ref_path), prefix(w_path), traits)
+    }
+
+    #[test]
+    fn matrix_rg_equals_pairwise_rg() {
+        let dir = TempDir::new();
+        let (ref_ld, w_ld, traits) = write_rg_fixture(&dir, "fixture", 2000);
+        let out = dir.path("fixture");
+        let rg_paths = traits.join(",");
+        let args = [
+            "--rg",
+            &rg_paths,
+            "--ref-ld",
+            &ref_ld,
+            "--w-ld",
+            &w_ld,
+            "--n-blocks",
+            "20",
+            "--out",
+            &out,
+        ];
+        let (pairwise, global) = rg_cli(&args);
+        let pairwise = estimate_rg(&pairwise, &global).unwrap();
+        let (matrix, global) = rg_cli(&[&args[..], &["--matrix"]].concat());
+        let matrix = estimate_rg_matrix(&matrix, &global).unwrap();
+
+        assert_eq!(matrix.traits.len(), 3);
+        for (j, rghat) in pairwise.iter().enumerate() {
+            let estimate = rghat.as_ref().unwrap().rg.as_ref().unwrap();
+            let (rg, se) = (matrix.rg[0][j + 1].unwrap(), matrix.se[0][j + 1].unwrap());
+            assert!(
+                (rg - estimate.rg_ratio).abs() < 1e-9,
+                "{} {}",
+                rg,
+                estimate.rg_ratio
+            );
+            assert!(
+                (se - estimate.rg_se).abs() < 1e-9,
+                "{} {}",
+                se,
+                estimate.rg_se
+            );
+        }
+    }
+
+    /// Write LD scores split across the 22 chromosomes, `n_per_chr` SNPs each, with columns
+    /// `cnames` of values `ld(i, j)` for SNP i, and return their prefix.
+    fn write_chr_split_ld(
+        dir: &TempDir,
+        name: &str,
+        cnames: &[&str],
+        n_per_chr: usize,
+        ld: impl Fn(usize, usize) -> f64,
+    ) -> String {
+        for chr in 1..=22 {
+            let mut text = format!("CHR\tSNP\tBP\t{}\n", cnames.join("\t"));
+            for k in 0..n_per_chr {
+                let i = (chr - 1) * n_per_chr + k;
+                let values = (0..cnames.len()).map(|j| ld(i, j).to_string());
+                let values = values.collect::<Vec<_>>().join("\t");
+                text += &format!("{}\trs{}\t{}\t{}\n", chr, i, 1000 * k, values);
+            }
+            dir.write(&format!("{}{}.l2.ldscore", name, chr), &text);
+            let m = vec![n_per_chr.to_string(); cnames.len()];
+            dir.write(&format!("{}{}.l2.M_5_50", name, chr), m.join("\t"));
+        }
+        dir.path(name)
+    }
+
+    #[test]
+    fn reads_ldcts_files() {
+        let dir = TempDir::new();
+        let path = dir.write("cts.ldcts", "ct_a\ta1,a2\n\nct_b  b\n");
+        let cell_types = read_ldcts(&path).unwrap();
+        assert_eq!(
+            cell_types,
+            [
+                ("ct_a".to_string(), "a1,a2".to_string()),
+                ("ct_b".to_string(), "b".to_string())
+            ]
+        );
+
+        let path = dir.write("bad.ldcts", "ct_a\ta1\tb1\n");
+        let err = read_ldcts(&path).unwrap_err().to_string();
+        assert!(err.contains("ct_a\ta1\tb1"), "{}", err);
+    }
+
+    #[test]
+    fn cts_results_are_sorted_with_missing_p_values_last() {
+        let mut results = [0.5, f64::NAN, 0.1, f64::NAN, 0.3]
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| CtsResult {
+                name: format!("ct{}", i),
+                coef: 0.0,
+                coef_se: 0.0,
+                p,
+            })
+            .collect::<Vec<_>>();
+        sort_by_p(&mut results);
+        let names = results.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["ct2", "ct4", "ct0", "ct1", "ct3"]);
+    }
+
+    #[test]
+    fn print_all_cts_prints_one_coefficient_per_prefix() {
+        use crate::cli::{Cli, Commands};
+        use clap::Parser;
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let dir = TempDir::new();
+        let n_per_chr = 50;
+        let base = |i: usize| 1.0 + (i % 97) as f64 / 2.0;
+        let cts = |i: usize, m: usize| (i % m) as f64;
+        let base_ld = write_chr_split_ld(&dir, "cts_base", &["L2"], n_per_chr, |i, _| base(i));
+        let a1 = write_chr_split_ld(&dir, "cts_a1", &["A1L2"], n_per_chr, |i, _| cts(i, 13));
+        // the second prefix of ct_a has two LD Score columns
+        let a2 = write_chr_split_ld(&dir, "cts_a2", &["A2L2", "A3L2"], n_per_chr, |i, j| {
+            cts(i, [17, 19][j])
+        });
+        let b = write_chr_split_ld(&dir, "cts_b", &["BL2"], n_per_chr, |i, _| cts(i, 23));
+        let ldcts = dir.write(
+            "cts_fixture.ldcts",
+            format!("ct_a\t{},{}\nct_b\t{}\n", a1, a2, b),
+        );
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut sumstats = String::from("SNP\tA1\tA2\tN\tZ\n");
+        for i in 0..22 * n_per_chr {
+            let (u1, u2): (f64, f64) = (rng.gen(), rng.gen());
+            let normal = (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+            let chisq = 1.0 + 0.02 * base(i) + 0.1 * cts(i, 13);
+            sumstats += &format!("rs{}\tA\tG\t10000\t{}\n", i, chisq.sqrt() * normal);
+        }
+        let sumstats = dir.write("cts_fixture.sumstats", &sumstats);
+
+        let out = dir.path("cts_fixture");
+        let run = |extra: &[&str]| {
+            let args = [
+                "ldscrs",
+                "h2-cts",
+                "--h2-cts",
+                &sumstats,
+                "--ref-ld-chr",
+                &base_ld,
+                "--w-ld-chr",
+                &base_ld,
+                "--ref-ld-chr-cts",
+                &ldcts,
+                "--n-blocks",
+                "20",
+                "--out",
+                &out,
+            ];
+            let cli = Cli::parse_from(args.iter().chain(extra));
+            match cli.command {
+                Commands::H2Cts(args) => cell_type_specific(&args, &cli.global).unwrap(),
+                _ => unreachable!(),
+            }
+        };
+
+        let first = run(&[]);
+        let mut names = first.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["ct_a", "ct_b"]);
+        assert!(first.windows(2).all(|r| r[0].p <= r[1].p));
+
+        let all = run(&["--print-all-cts"]);
+        let mut names = all.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["ct_a_0", "ct_a_1", "ct_b_0"]);
+        assert!(all.windows(2).all(|r| r[0].p <= r[1].p));
+        let coef = |results: &[CtsResult], name: &str| {
+            results.iter().find(|r| r.name == name).unwrap().coef
+        };
+        assert_eq!(coef(&first, "ct_a"), coef(&all, "ct_a_0"));
+        assert_eq!(coef(&first, "ct_b"), coef(&all, "ct_b_0"));
+    }
+
+    #[test]
+    fn chisq_max_removals_are_reported_for_every_pair() {
+        let dir = TempDir::new();
+        let (ref_ld, w_ld, traits) = write_rg_fixture(&dir, "chisq", 2000);
+        let out = dir.path("chisq");
+        let rg_paths = traits.join(",");
+        let args = [
+            "--rg",
+            &rg_paths,
+            "--ref-ld",
+            &ref_ld,
+            "--w-ld",
+            &w_ld,
+            "--n-blocks",
+            "20",
+            "--chisq-max",
+            "10",
+            "--out",
+            &out,
+        ];
+        let read_reports = || -> Vec<serde_json::Value> {
+            let file = File::open(format!("{}.chisq_max.json", out)).unwrap();
+            serde_json::from_reader(file).unwrap()
+        };
+        let (pairwise, global) = rg_cli(&args);
+        let pairwise = estimate_rg(&pairwise, &global).unwrap();
+        let pairwise_reports = read_reports();
+        let (matrix, global) = rg_cli(&[&args[..], &["--matrix"]].concat());
+        let matrix = estimate_rg_matrix(&matrix, &global).unwrap();
+        let matrix_reports = read_reports();
+
+        assert_eq!(pairwise_reports.len(), 2);
+        assert_eq!(matrix_reports.len(), 3);
+        assert_eq!(pairwise_reports[..], matrix_reports[..2]);
+        for report in &pairwise_reports {
+            assert_eq!(report["chisq_max"], 10.0);
+            assert!(report["removed"].as_u64().unwrap() > 0);
+        }
+        assert_eq!(pairwise_reports[1]["traits"][1], traits[2].as_str());
+        for (j, rghat) in pairwise.iter().enumerate() {
+            let estimate = rghat.as_ref().unwrap().rg.as_ref().unwrap();
+            assert!((matrix.rg[0][j + 1].unwrap() - estimate.rg_ratio).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn default_chisq_max_is_the_larger_of_80_and_n_over_1000() {
+        let sumstats = |n: [f64; 2]| LdSumstats {
+            m_annot: vec![],
+            ref_ld_cnames: vec![],
+            data: df!("N" => n).unwrap(),
+        };
+        assert_eq!(
+            default_chisq_max(&sumstats([5e4, 7e4])).unwrap(),
+            (80.0, "80".to_string())
+        );
+        assert_eq!(
+            default_chisq_max(&sumstats([1e5, 2e5])).unwrap(),
+            (200.0, "200.0".to_string())
+        );
+    }
+
+    #[test]
+    fn chisq_max_applies_to_the_product_of_both_chisq() {
+        assert!(below_chisq_max(3.0, 2.9, Some(9.0)));
+        assert!(!below_chisq_max(3.0, 3.0, Some(9.0)));
+        // a large chi^2 of one trait is kept if the other is small
+        assert!(below_chisq_max(10.0, 0.1, Some(9.0)));
+        assert!(below_chisq_max(100.0, 100.0, None));
+
+        let mut sumstats = LdSumstats {
+            m_annot: vec![],
+            ref_ld_cnames: vec![],
+            data: df!("Z" => [1.0, -9.0, 10.0, 2.0]).unwrap(),
+        };
+        let report = filter_chisq(&mut sumstats, "t", (81.0, "81".to_string())).unwrap();
+        assert_eq!(floats(&sumstats.data, "Z"), [1.0, 2.0]);
+        assert_eq!((report.removed, report.remaining), (2, 2));
+    }
+
+    fn h2_args(extra: &[&str]) -> H2Args {
+        use crate::cli::{Cli, Commands};
+        use clap::Parser;
+
+        let args = ["ldscrs", "h2", "--h2", "a", "--ref-ld", "ld", "--w-ld", "w"];
+        match Cli::parse_from(args.iter().chain(extra)).command {
+            Commands::H2(args) => args,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn two_step_is_the_default_for_a_single_free_regression() {
+        assert_eq!(h2_options(&h2_args(&[]), 1).twostep, Some(TWO_STEP_CUTOFF));
+        assert_eq!(
+            h2_options(&h2_args(&["--two-step", "20"]), 1).twostep,
+            Some(20.0)
+        );
+        assert_eq!(h2_options(&h2_args(&["--no-intercept"]), 1).twostep, None);
+        assert_eq!(
+            h2_options(&h2_args(&["--intercept-h2", "1.1"]), 1).twostep,
+            None
+        );
+        let partitioned = h2_options(&h2_args(&[]), 2);
+        assert!(partitioned.twostep.is_none() && partitioned.old_weights);
+
+        assert_eq!(
+            rg_options(&rg_args(&[]), 1, false).twostep,
+            Some(TWO_STEP_CUTOFF)
+        );
+        assert_eq!(rg_options(&rg_args(&[]), 1, true).twostep, None);
+        assert_eq!(rg_options(&rg_args(&[]), 2, false).twostep, None);
+        assert_eq!(
+            rg_options(&rg_args(&["--two-step", "15"]), 2, false).twostep,
+            Some(15.0)
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use anyhow::Result;
+use clap::ValueEnum;
+use polars::prelude::*;
 use std::{
     fs::File,
-    io::{BufRead, BufReader, Read},
+    io::{BufRead, BufReader, Cursor, Read},
     path::Path,
 };
 
@@ -20,61 +22,574 @@ fn get_magic_num(path: &str) -> Result<[u8; MAGIC_MAX_LEN]> {
     Ok(buffer)
 }
 
-fn is_gzipped(path: &str) -> Result<bool> {
-    let buffer = get_magic_num(path)?;
-    let gz_or_not =
-        buffer[0] == GZ_MAGIC[0] && buffer[1] == GZ_MAGIC[1] && buffer[2] == GZ_MAGIC[2];
-    Ok(gz_or_not || Path::new(path).extension().is_some_and(|ext| ext == "gz"))
-}
-
-fn is_bzipped(path: &str) -> Result<bool> {
-    let buffer = get_magic_num(path)?;
-    let bz_or_not =
-        buffer[0] == BZ_MAGIC[0] && buffer[1] == BZ_MAGIC[1] && buffer[2] == BZ_MAGIC[2];
-    Ok(bz_or_not || Path::new(path).extension().is_some_and(|ext| ext == "bz2"))
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Gzip,
+    Bzip2,
+    Xz,
+    Plain,
 }
 
-fn is_xz(path: &str) -> Result<bool> {
+// magic number takes priority, the file extension is only a fallback
+fn detect_codec(path: &str) -> Result<Codec> {
     let buffer = get_magic_num(path)?;
-    let xz_or_not = buffer[0] == XZ_MAGIC[0]
-        && buffer[1] == XZ_MAGIC[1]
-        && buffer[2] == XZ_MAGIC[2]
-        && buffer[3] == XZ_MAGIC[3]
-        && buffer[4] == XZ_MAGIC[4]
-        && buffer[5] == XZ_MAGIC[5];
-    Ok(xz_or_not || Path::new(path).extension().is_some_and(|ext| ext == "xz"))
+    let ext = Path::new(path).extension().and_then(|ext| ext.to_str());
+    let codec = if buffer.starts_with(&XZ_MAGIC) {
+        Codec::Xz
+    } else if buffer.starts_with(&GZ_MAGIC) {
+        Codec::Gzip
+    } else if buffer.starts_with(&BZ_MAGIC) {
+        Codec::Bzip2
+    } else {
+        match ext {
+            Some("xz") => Codec::Xz,
+            Some("gz") => Codec::Gzip,
+            Some("bz2") => Codec::Bzip2,
+            _ => Codec::Plain,
+        }
+    };
+    Ok(codec)
 }
 
+/// Open `path` for buffered reading, transparently decoding gzip, bzip2 and xz
+/// (including multi-member/multi-stream files).
+///
+/// Every input file should be read through this, so that compressed and plain
+/// inputs are handled the same way.
 pub fn get_input_reader(path: &str) -> Result<Box<dyn BufRead + Send>> {
-    let reader: Box<dyn BufRead + Send> = match File::open(path) {
-        Ok(file) => {
-            if is_xz(path)? {
-                // decode xz compressed file
-                Box::new(BufReader::with_capacity(
-                    BUFFER_SIZE,
-                    xz2::read::XzDecoder::new_multi_decoder(file),
-                ))
-            } else if is_gzipped(path)? {
-                // decode gzip compressed file
-                Box::new(BufReader::with_capacity(
-                    BUFFER_SIZE,
-                    flate2::read::MultiGzDecoder::new(file),
-                ))
-            } else if is_bzipped(path)? {
-                // decode bzip2 compressed file
-                Box::new(BufReader::with_capacity(
-                    BUFFER_SIZE,
-                    bzip2::read::MultiBzDecoder::new(file),
-                ))
-            } else {
-                // stdin flag "-" covered
-                Box::new(BufReader::with_capacity(BUFFER_SIZE, file))
-            }
-        }
+    let file = match File::open(path) {
+        Ok(file) => file,
         Err(_) => {
             anyhow::bail!("File not found: {:?}", path);
         }
     };
+    let reader: Box<dyn BufRead + Send> = match detect_codec(path)? {
+        // decode xz compressed file
+        Codec::Xz => Box::new(BufReader::with_capacity(
+            BUFFER_SIZE,
+            xz2::read::XzDecoder::new_multi_decoder(file),
+        )),
+        // decode gzip compressed file
+        Codec::Gzip => Box::new(BufReader::with_capacity(
+            BUFFER_SIZE,
+            flate2::read::MultiGzDecoder::new(file),
+        )),
+        // decode bzip2 compressed file
+        Codec::Bzip2 => Box::new(BufReader::with_capacity(
+            BUFFER_SIZE,
+            bzip2::read::MultiBzDecoder::new(file),
+        )),
+        Codec::Plain => Box::new(BufReader::with_capacity(BUFFER_SIZE, file)),
+    };
 
     Ok(reader)
 }
+
+// chromosomes expanded from a per-chromosome "@" pattern
+const CHROMOSOMES: std::ops::RangeInclusive<u8> = 1..=22;
+// compression suffixes tried, in order, when resolving a path
+const COMPRESSION_SUFFIXES: [&str; 4] = ["", ".gz", ".bz2", ".xz"];
+
+/// Substitute chromosome `chr` for the `@` in `pattern`, or append it if there is no `@`
+/// (so `eur_w_ld_chr/` becomes `eur_w_ld_chr/1`), like ldsc's `sub_chr`.
+pub fn sub_chr(pattern: &str, chr: u8) -> String {
+    if pattern.contains('@') {
+        pattern.replace('@', &chr.to_string())
+    } else {
+        format!("{}{}", pattern, chr)
+    }
+}
+
+/// `path`, or `path` with a compression suffix (.gz, .bz2, .xz), whichever exists first.
+pub fn resolve_path(path: &str) -> Option<String> {
+    COMPRESSION_SUFFIXES
+        .iter()
+        .map(|ext| format!("{}{}", path, ext))
+        .find(|p| Path::new(p).is_file())
+}
+
+/// Expand the per-chromosome `pattern` (see [`sub_chr`]) followed by `suffix`
+/// (e.g. `.l2.ldscore`) to the files of chromosomes 1-22, in chromosome order.
+///
+/// Every chromosome must be present, possibly compressed.
+pub fn expand_chr(pattern: &str, suffix: &str) -> Result<Vec<String>> {
+    let mut paths = Vec::new();
+    let mut missing = Vec::new();
+    for chr in CHROMOSOMES {
+        match resolve_path(&format!("{}{}", sub_chr(pattern, chr), suffix)) {
+            Some(path) => paths.push(path),
+            None => missing.push(chr.to_string()),
+        }
+    }
+    if !missing.is_empty() {
+        let shown = match pattern.contains('@') {
+            true => pattern.to_string(),
+            false => format!("{}@", pattern),
+        };
+        anyhow::bail!(
+            "Could not find {}{} for chromosome(s) {}.",
+            shown,
+            suffix,
+            missing.join(", ")
+        );
+    }
+    Ok(paths)
+}
+
+/// Resolve `path` followed by `suffix` to the file(s) to read: the 22 chromosome files if
+/// `chr_split`, else the single (possibly compressed) file.
+pub fn input_paths(path: &str, suffix: &str, chr_split: bool) -> Result<Vec<String>> {
+    if chr_split {
+        return expand_chr(path, suffix);
+    }
+    let full = format!("{}{}", path, suffix);
+    match resolve_path(&full) {
+        Some(path) => Ok(vec![path]),
+        None => anyhow::bail!("File not found: {:?}", full),
+    }
+}
+
+/// Field delimiter of a text table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Delimiter {
+    /// A single tab.
+    Tab,
+    /// Any run of spaces and/or tabs, like pandas' `delim_whitespace=True`.
+    Whitespace,
+    Comma,
+    Semicolon,
+}
+
+impl Delimiter {
+    /// Guess the delimiter from the first lines (header included) of a table.
+    ///
+    /// A single-character delimiter wins if it splits every line into the same
+    /// number (> 1) of fields; tab is tried first, then comma and semicolon.
+    /// Otherwise fields are split on runs of whitespace.
+    pub fn sniff(lines: &[&str]) -> Self {
+        let lines = lines
+            .iter()
+            .map(|l| l.trim_end_matches(['\r', '\n']))
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>();
+        // number of fields if every line has the same number of them
+        let n_fields = |delim: Delimiter| {
+            let counts = lines
+                .iter()
+                .map(|l| delim.split(l).len())
+                .collect::<Vec<_>>();
+            match counts.first() {
+                Some(n) if counts.iter().all(|c| c == n) => *n,
+                _ => 0,
+            }
+        };
+        let n_whitespace = n_fields(Delimiter::Whitespace);
+        for delim in [Delimiter::Tab, Delimiter::Comma, Delimiter::Semicolon] {
+            let n = n_fields(delim);
+            // "a b\tc" and "a \t b" are whitespace-delimited, not tab-delimited
+            let padded = lines
+                .iter()
+                .flat_map(|l| delim.split(l))
+                .any(|f| f.trim() != f);
+            if n > 1 && !(delim == Delimiter::Tab && (n_whitespace > n || padded)) {
+                return delim;
+            }
+        }
+        Delimiter::Whitespace
+    }
+
+    /// Split one line into fields.
+    pub fn split<'a>(&self, line: &'a str) -> Vec<&'a str> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        match self {
+            Delimiter::Tab => line.split('\t').collect(),
+            Delimiter::Whitespace => line.split_whitespace().collect(),
+            Delimiter::Comma => line.split(',').collect(),
+            Delimiter::Semicolon => line.split(';').collect(),
+        }
+    }
+
+    // separator handed to polars; whitespace-delimited lines are rewritten to tabs
+    fn separator(&self) -> u8 {
+        match self {
+            Delimiter::Tab | Delimiter::Whitespace => b'\t',
+            Delimiter::Comma => b',',
+            Delimiter::Semicolon => b';',
+        }
+    }
+}
+
+/// Read the header (split into column names) and guess the delimiter of the table at
+/// `path`, unless `delim` is given.
+pub fn read_header(path: &str, delim: Option<Delimiter>) -> Result<(Vec<String>, Delimiter)> {
+    let mut reader = get_input_reader(path)?;
+    let mut header = String::new();
+    if reader.read_line(&mut header)? == 0 {
+        anyhow::bail!("Empty file: {:?}", path);
+    }
+    let delim = match delim {
+        Some(delim) => delim,
+        None => {
+            let mut first = String::new();
+            reader.read_line(&mut first)?;
+            Delimiter::sniff(&[&header, &first])
+        }
+    };
+    let colnames = delim
+        .split(&header)
+        .iter()
+        .map(|s| s.trim().to_string())
+        .collect();
+    Ok((colnames, delim))
+}
+
+/// Read the IDs in column `column` (0-based) of the whitespace-delimited, headerless
+/// file at `path`, in file order, e.g. SNPs for `--extract` or IIDs for `--keep`.
+pub fn read_ids(path: &str, column: usize) -> Result<Vec<String>> {
+    let mut ids = Vec::new();
+    for line in get_input_reader(path)?.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.split_whitespace().nth(column) {
+            Some(id) => ids.push(id.to_string()),
+            None => anyhow::bail!("Missing column {} in {:?}: {}", column + 1, path, line),
+        }
+    }
+    Ok(ids)
+}
+
+/// Read a whole delimited table from `path` (decoded by [`get_input_reader`]),
+/// guessing the delimiter unless `delim` is given.
+pub fn read_csv(
+    path: &str,
+    read_options: CsvReadOptions,
+    delim: Option<Delimiter>,
+) -> Result<DataFrame> {
+    let (_, delim) = read_header(path, delim)?;
+    let mut chunks = CsvChunks::new(get_input_reader(path)?, read_options, usize::MAX, delim)?;
+    match chunks.next() {
+        Some(df) => df,
+        None => anyhow::bail!("No rows in file: {:?}", path),
+    }
+}
+
+/// Parse a delimited table from `reader` `chunksize` rows at a time.
+///
+/// Each chunk is parsed by polars with the header of the table prepended, so every
+/// `DataFrame` yielded has the same columns. Only the current chunk is kept in memory.
+pub struct CsvChunks {
+    reader: Box<dyn BufRead + Send>,
+    header: Vec<u8>,
+    read_options: CsvReadOptions,
+    delim: Delimiter,
+    chunksize: usize,
+    done: bool,
+}
+
+impl CsvChunks {
+    pub fn new(
+        mut reader: Box<dyn BufRead + Send>,
+        read_options: CsvReadOptions,
+        chunksize: usize,
+        delim: Delimiter,
+    ) -> Result<Self> {
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line)?;
+        let mut header = Vec::new();
+        push_line(delim, &line, &mut header);
+        Ok(Self {
+            reader,
+            header,
+            read_options: read_options
+                .with_has_header(true)
+                .map_parse_options(|opts| opts.with_separator(delim.separator())),
+            delim,
+            chunksize: chunksize.max(1),
+            done: false,
+        })
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<DataFrame>> {
+        let mut buf = self.header.clone();
+        let mut line = Vec::new();
+        let mut n_lines = 0;
+        while n_lines < self.chunksize {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                self.done = true;
+                break;
+            }
+            if push_line(self.delim, &line, &mut buf) {
+                n_lines += 1;
+            }
+        }
+        if n_lines == 0 {
+            return Ok(None);
+        }
+        let df = self
+            .read_options
+            .clone()
+            .into_reader_with_file_handle(Cursor::new(buf))
+            .finish()?;
+        Ok(Some(df))
+    }
+}
+
+impl Iterator for CsvChunks {
+    type Item = Result<DataFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        self.read_chunk().transpose()
+    }
+}
+
+// Append `line` to `buf`, rewriting whitespace runs to single tabs. Blank lines are
+// skipped, and false is returned for them.
+fn push_line(delim: Delimiter, line: &[u8], buf: &mut Vec<u8>) -> bool {
+    if line.iter().all(|b| b.is_ascii_whitespace()) {
+        return false;
+    }
+    match delim {
+        Delimiter::Whitespace => {
+            let line = String::from_utf8_lossy(line);
+            let mut fields = line.split_whitespace();
+            if let Some(first) = fields.next() {
+                buf.extend_from_slice(first.as_bytes());
+            }
+            for field in fields {
+                buf.push(b'\t');
+                buf.extend_from_slice(field.as_bytes());
+            }
+        }
+        _ => buf.extend_from_slice(line),
+    }
+    if !buf.ends_with(b"\n") {
+        buf.push(b'\n');
+    }
+    true
+}
+
+/// Format `x` like Python 2's `str(float)` (12 significant digits), which ldsc relies on
+/// when it prints floats, e.g. in the .M files of continuous annotations.
+pub fn py_str(x: f64) -> String {
+    if !x.is_finite() {
+        return match x {
+            x if x.is_nan() => "nan".to_string(),
+            x if x > 0.0 => "inf".to_string(),
+            _ => "-inf".to_string(),
+        };
+    }
+    let sci = format!("{:.11e}", x);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp = exp.parse::<i32>().unwrap();
+    let trim = |s: &str| {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            s.to_string()
+        }
+    };
+    if !(-4..12).contains(&exp) {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim(mantissa), sign, exp.abs())
+    } else {
+        let fixed = trim(&format!("{:.*}", (11 - exp) as usize, x));
+        if fixed.contains('.') {
+            fixed
+        } else {
+            format!("{}.0", fixed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::io::Write;
+
+    const TABLE: &str = "SNP\tA1\tA2\nrs1\tA\tG\nrs2\tC\tT\nrs3\tA\tC\n";
+
+    fn gzip(text: &str) -> Vec<u8> {
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(text.as_bytes()).unwrap();
+        enc.finish().unwrap()
+    }
+
+    fn bzip2(text: &str) -> Vec<u8> {
+        let mut enc = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        enc.write_all(text.as_bytes()).unwrap();
+        enc.finish().unwrap()
+    }
+
+    fn xz(text: &str) -> Vec<u8> {
+        let mut enc = xz2::write::XzEncoder::new(Vec::new(), 6);
+        enc.write_all(text.as_bytes()).unwrap();
+        enc.finish().unwrap()
+    }
+
+    fn read_all(path: &str) -> String {
+        let mut out = String::new();
+        get_input_reader(path)
+            .unwrap()
+            .read_to_string(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn decodes_all_codecs() {
+        let dir = TempDir::new();
+        for (name, bytes) in [
+            ("plain.txt", TABLE.as_bytes().to_vec()),
+            ("table.gz", gzip(TABLE)),
+            ("table.bz2", bzip2(TABLE)),
+            ("table.xz", xz(TABLE)),
+            // detected by magic number, not extension
+            ("gz_no_ext", gzip(TABLE)),
+            ("bz2_no_ext", bzip2(TABLE)),
+            ("xz_no_ext", xz(TABLE)),
+        ] {
+            let path = dir.write(name, &bytes);
+            assert_eq!(read_all(&path), TABLE, "{}", name);
+        }
+    }
+
+    #[test]
+    fn decodes_multi_member_files() {
+        let dir = TempDir::new();
+        let (head, tail) = TABLE.split_at(20);
+        let mut gz = gzip(head);
+        gz.extend(gzip(tail));
+        let mut bz = bzip2(head);
+        bz.extend(bzip2(tail));
+        let mut x = xz(head);
+        x.extend(xz(tail));
+        for (name, bytes) in [("multi.gz", gz), ("multi.bz2", bz), ("multi.xz", x)] {
+            let path = dir.write(name, &bytes);
+            assert_eq!(read_all(&path), TABLE, "{}", name);
+        }
+    }
+
+    #[test]
+    fn reads_compressed_tables() {
+        let dir = TempDir::new();
+        let parse_opts = CsvParseOptions::default().with_separator(b'\t');
+        for (name, bytes) in [
+            ("read.gz", gzip(TABLE)),
+            ("read.bz2", bzip2(TABLE)),
+            ("read.xz", xz(TABLE)),
+        ] {
+            let path = dir.write(name, &bytes);
+            let read_opts = CsvReadOptions::default()
+                .with_has_header(true)
+                .with_parse_options(parse_opts.clone());
+            let df = read_csv(&path, read_opts.clone(), None).unwrap();
+            assert_eq!(df.shape(), (3, 3), "{}", name);
+
+            let chunks = CsvChunks::new(
+                get_input_reader(&path).unwrap(),
+                read_opts,
+                2,
+                Delimiter::Tab,
+            )
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+            let heights = chunks.iter().map(|c| c.height()).collect::<Vec<_>>();
+            assert_eq!(heights, vec![2, 1], "{}", name);
+            assert_eq!(
+                chunks[1].column("SNP").unwrap().str().unwrap().get(0),
+                Some("rs3")
+            );
+        }
+    }
+
+    #[test]
+    fn sniffs_delimiters() {
+        assert_eq!(
+            Delimiter::sniff(&["SNP\tA1\tA2", "rs1\tA\tG"]),
+            Delimiter::Tab
+        );
+        assert_eq!(
+            Delimiter::sniff(&["SNP A1 A2", "rs1  A G"]),
+            Delimiter::Whitespace
+        );
+        assert_eq!(
+            Delimiter::sniff(&["SNP\tA1 A2", "rs1 A\tG"]),
+            Delimiter::Whitespace
+        );
+        assert_eq!(
+            Delimiter::sniff(&["SNP,A1,A2", "rs1,A,G"]),
+            Delimiter::Comma
+        );
+        assert_eq!(
+            Delimiter::sniff(&["SNP;A1;A2", "rs1;A;G"]),
+            Delimiter::Semicolon
+        );
+        assert_eq!(
+            Delimiter::sniff(&["SNP  \t A1\tA2", "rs1\t A\tG"]),
+            Delimiter::Whitespace
+        );
+        // a tab-delimited header with a space in a column name
+        assert_eq!(
+            Delimiter::sniff(&["SNP\tP value", "rs1\t0.1"]),
+            Delimiter::Tab
+        );
+    }
+
+    #[test]
+    fn reads_whitespace_delimited_tables() {
+        let dir = TempDir::new();
+        let path = dir.write("spaces.txt", b"SNP  A1 A2\nrs1\tA G\n\nrs2 C   T\n");
+        let (colnames, delim) = read_header(&path, None).unwrap();
+        assert_eq!(colnames, vec!["SNP", "A1", "A2"]);
+        assert_eq!(delim, Delimiter::Whitespace);
+        let df = read_csv(&path, CsvReadOptions::default(), None).unwrap();
+        assert_eq!(df.shape(), (2, 3));
+        assert_eq!(df.column("A2").unwrap().str().unwrap().get(1), Some("T"));
+    }
+
+    #[test]
+    fn expands_chromosome_patterns() {
+        let dir = TempDir::new();
+        assert_eq!(sub_chr("ld/chr@.x", 3), "ld/chr3.x");
+        assert_eq!(sub_chr("eur_w_ld_chr/", 22), "eur_w_ld_chr/22");
+
+        let prefix = dir.path("chr");
+        for chr in 1..=22 {
+            // mix plain and compressed files
+            let path = match chr % 2 {
+                0 => format!("{}{}.l2.M", prefix, chr),
+                _ => format!("{}{}.l2.M.gz", prefix, chr),
+            };
+            File::create(path).unwrap();
+        }
+        let paths = expand_chr(&prefix, ".l2.M").unwrap();
+        assert_eq!(paths.len(), 22);
+        assert_eq!(paths[0], format!("{}1.l2.M.gz", prefix));
+        assert_eq!(paths[21], format!("{}22.l2.M", prefix));
+
+        std::fs::remove_file(format!("{}7.l2.M.gz", prefix)).unwrap();
+        let err = expand_chr(&prefix, ".l2.M").unwrap_err().to_string();
+        assert!(err.ends_with("chromosome(s) 7."), "{}", err);
+        assert_eq!(input_paths(&prefix, "8.l2.M", false).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn py_str_matches_python2() {
+        assert_eq!(py_str(1234.0), "1234.0");
+        assert_eq!(py_str(-1.0), "-1.0");
+        assert_eq!(py_str(0.5), "0.5");
+        assert_eq!(py_str(12345.678901234567), "12345.6789012");
+        assert_eq!(py_str(1e-5), "1e-05");
+        assert_eq!(py_str(1e16), "1e+16");
+        assert_eq!(py_str(123456789012.0), "123456789012.0");
+        assert_eq!(py_str(0.1 + 0.2), "0.3");
+    }
+}